@@ -0,0 +1,236 @@
+//! Grouping of the trades by the orders they filled and by the instruments.
+use crate::TradeInfo;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/**
+Collects the trades received from the trade_status_callback grouped by the order number.
+
+The clones of the aggregator share the trades, so one clone can consume the channel
+while the others are queried.
+
+# Example of use
+```ignore
+let aggregator = TradeAggregator::new();
+let consumer = aggregator.clone();
+tokio::spawn(async move { consumer.consume(trade_status_receiver).await });
+
+if let Some(price) = aggregator.avg_fill_price(order_num) {
+    println!("order {} filled at {}", order_num, price);
+}
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct TradeAggregator {
+    trades: Arc<Mutex<HashMap<u64, Vec<TradeInfo>>>>,
+}
+
+impl TradeAggregator {
+    pub fn new() -> Self {
+        TradeAggregator::default()
+    }
+
+    /// Adds the trade to its order. The trade already added with the same trade number is ignored,
+    /// the library Trans2QUIK.dll resends the trades of the session after the reconnection.
+    pub fn add(&self, trade: TradeInfo) {
+        let mut trades = self.trades.lock().unwrap();
+        let order_trades = trades.entry(trade.order_num).or_default();
+
+        if !order_trades.iter().any(|t| t.trade_num == trade.trade_num) {
+            order_trades.push(trade);
+        }
+    }
+
+    /// Adds the trades received from the channel until it is closed.
+    pub async fn consume(&self, mut receiver: UnboundedReceiver<TradeInfo>) {
+        while let Some(trade) = receiver.recv().await {
+            self.add(trade);
+        }
+    }
+
+    /// The trades of the order in the order of receipt.
+    pub fn trades_for_order(&self, order_num: u64) -> Vec<TradeInfo> {
+        self.trades
+            .lock()
+            .unwrap()
+            .get(&order_num)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Volume-weighted average price of the trades of the order,
+    /// `None` if the order has no trades.
+    pub fn avg_fill_price(&self, order_num: u64) -> Option<f64> {
+        let trades = self.trades.lock().unwrap();
+        let order_trades = trades.get(&order_num)?;
+
+        let quantity: i64 = order_trades.iter().map(|t| t.quantity).sum();
+        if quantity == 0 {
+            return None;
+        }
+
+        let value: f64 = order_trades
+            .iter()
+            .map(|t| t.price * t.quantity as f64)
+            .sum();
+
+        Some(value / quantity as f64)
+    }
+}
+
+/// Running totals of the trades in the instrument.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TradeStats {
+    /// Total quantity of the trades.
+    pub volume: i64,
+    /// Total value of the trades.
+    pub notional: f64,
+    pub trade_count: u64,
+}
+
+#[derive(Debug, Default)]
+struct SessionStatsState {
+    stats: HashMap<(String, String), TradeStats>,
+    trade_nums: HashSet<u64>,
+}
+
+/// Accumulates the totals of the trades received from the trade_status_callback
+/// per `(class_code, sec_code)`. The clones share the totals like the clones of `TradeAggregator`.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    state: Arc<Mutex<SessionStatsState>>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats::default()
+    }
+
+    /// Adds the trade to the totals of its instrument, the trade already added
+    /// with the same trade number is ignored.
+    pub fn add(&self, trade: &TradeInfo) {
+        let mut state = self.state.lock().unwrap();
+
+        if !state.trade_nums.insert(trade.trade_num) {
+            return;
+        }
+
+        let stats = state
+            .stats
+            .entry((trade.class_code.clone(), trade.sec_code.clone()))
+            .or_default();
+        stats.volume += trade.quantity;
+        stats.notional += trade.value;
+        stats.trade_count += 1;
+    }
+
+    /// Adds the trades received from the channel until it is closed.
+    pub async fn consume(&self, mut receiver: UnboundedReceiver<TradeInfo>) {
+        while let Some(trade) = receiver.recv().await {
+            self.add(&trade);
+        }
+    }
+
+    /// The totals of the instrument, `None` if there were no trades in it.
+    pub fn stats_for(&self, class_code: &str, sec_code: &str) -> Option<TradeStats> {
+        self.state
+            .lock()
+            .unwrap()
+            .stats
+            .get(&(class_code.to_string(), sec_code.to_string()))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IsSell, Mode, TradeKind};
+
+    fn trade(trade_num: u64, order_num: u64, price: f64, quantity: i64) -> TradeInfo {
+        TradeInfo {
+            mode: Mode::NewOrder,
+            trade_num,
+            order_num,
+            class_code: String::from("TQBR"),
+            sec_code: String::from("SBER"),
+            price,
+            quantity,
+            is_sell: IsSell::Buy,
+            value: price * quantity as f64,
+            date: None,
+            time: None,
+            settle_code: String::from("T0"),
+            settle_time: None,
+            trade_kind: TradeKind::Spot,
+            user_id: String::new(),
+            firm_id: String::new(),
+            price_currency: String::from("SUR"),
+            settle_currency: String::from("SUR"),
+            uid: 0,
+            seq: trade_num,
+        }
+    }
+
+    #[test]
+    fn test_avg_fill_price() {
+        let aggregator = TradeAggregator::new();
+        aggregator.add(trade(1, 100, 250.0, 10));
+        aggregator.add(trade(2, 100, 251.0, 30));
+        aggregator.add(trade(3, 200, 300.0, 5));
+        // Resent after the reconnection.
+        aggregator.add(trade(2, 100, 251.0, 30));
+
+        assert_eq!(aggregator.trades_for_order(100).len(), 2);
+        assert_eq!(aggregator.avg_fill_price(100), Some(250.75));
+        assert_eq!(aggregator.avg_fill_price(200), Some(300.0));
+        assert_eq!(aggregator.avg_fill_price(300), None);
+        assert!(aggregator.trades_for_order(300).is_empty());
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let stats = SessionStats::new();
+        stats.add(&trade(1, 100, 250.0, 10));
+        stats.add(&trade(2, 100, 251.0, 30));
+        stats.add(&trade(2, 100, 251.0, 30));
+
+        let mut gazp = trade(3, 200, 130.0, 5);
+        gazp.sec_code = String::from("GAZP");
+        stats.add(&gazp);
+
+        assert_eq!(
+            stats.stats_for("TQBR", "SBER"),
+            Some(TradeStats {
+                volume: 40,
+                notional: 2500.0 + 7530.0,
+                trade_count: 2,
+            })
+        );
+        assert_eq!(stats.stats_for("TQBR", "GAZP").unwrap().trade_count, 1);
+        assert_eq!(stats.stats_for("TQBR", "LKOH"), None);
+    }
+
+    #[test]
+    fn test_consume() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        sender.send(trade(1, 100, 10.0, 1)).unwrap();
+        sender.send(trade(2, 100, 20.0, 3)).unwrap();
+        drop(sender);
+
+        let aggregator = TradeAggregator::new();
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(aggregator.consume(receiver));
+
+        let trade_nums: Vec<u64> = aggregator
+            .trades_for_order(100)
+            .iter()
+            .map(|t| t.trade_num)
+            .collect();
+        assert_eq!(trade_nums, vec![1, 2]);
+        assert_eq!(aggregator.avg_fill_price(100), Some(17.5));
+    }
+}
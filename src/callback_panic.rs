@@ -0,0 +1,94 @@
+//! Catching of the panics in the callback functions, which must not unwind into the library
+//! Trans2QUIK.dll.
+use lazy_static::lazy_static;
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::sync::{Mutex, Once};
+use tracing::error;
+
+/// The hook called with the panics of the callback functions, see `Terminal::set_callback_panic_hook`.
+pub(crate) type CallbackPanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Send + Sync>;
+
+lazy_static! {
+    static ref CALLBACK_PANIC_HOOK: Mutex<Option<CallbackPanicHook>> = Mutex::new(None);
+}
+
+thread_local! {
+    /// The thread is running a callback function.
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Sets the hook and chains it before the global panic hook, which is still called
+/// for all the panics.
+pub(crate) fn set_hook(hook: CallbackPanicHook) {
+    *CALLBACK_PANIC_HOOK.lock().unwrap() = Some(hook);
+
+    INSTALL_HOOK.call_once(|| {
+        let global_hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if IN_CALLBACK.with(Cell::get) {
+                // The hook panicking itself is not called again.
+                if let Ok(hook) = CALLBACK_PANIC_HOOK.try_lock() {
+                    if let Some(hook) = hook.as_ref() {
+                        hook(info);
+                    }
+                }
+            }
+
+            global_hook(info);
+        }));
+    });
+}
+
+/// Removes the hook for the tests.
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) fn reset() {
+    *CALLBACK_PANIC_HOOK.lock().unwrap() = None;
+}
+
+/// Runs the body of the callback function, logging the panic instead of unwinding
+/// into the library. Returns `true` if the body panicked.
+pub(crate) fn catch<F: FnOnce()>(callback: &str, body: F) -> bool {
+    let in_callback = IN_CALLBACK.with(|flag| flag.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    IN_CALLBACK.with(|flag| flag.set(in_callback));
+
+    match result {
+        Ok(()) => false,
+        Err(payload) => {
+            error!("{} panicked: {}", callback, panic_message(payload.as_ref()));
+            true
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static");
+
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "formatted 1");
+
+        let payload = panic::catch_unwind(|| panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "unknown panic");
+    }
+}
@@ -0,0 +1,230 @@
+//! Senders of the events of the callback functions to the channels of the user.
+use crate::{OrderInfo, TradeInfo, TransactionInfo};
+use std::error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+/// What to do with the event when the bounded channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// Drop the event that doesn't fit and count it, see `EventSender::dropped`.
+    /// The events already in the channel are kept: tokio channels can't be popped from the sending side.
+    #[default]
+    DropNewest,
+    /// Block the callback function until the consumer frees the place.
+    /// Dangerous: the library Trans2QUIK.dll doesn't call the other callbacks meanwhile,
+    /// and the callback must not be called from a thread of the tokio runtime.
+    Block,
+}
+
+/// What to do with the events of the callback functions while the delivery is paused,
+/// see `Terminal::pause_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PausePolicy {
+    /// Keep the events and deliver them in the order of receiving when resumed.
+    /// The buffer grows without limit while paused.
+    #[default]
+    Buffer,
+    /// Drop the events received while paused.
+    Drop,
+}
+
+#[derive(Debug)]
+enum Channel<T> {
+    Unbounded(UnboundedSender<T>),
+    Bounded(Sender<T>, OverflowPolicy),
+}
+
+/**
+The sender of the events of the callback functions,
+set to `TRANSACTION_REPLY_SENDER`, `ORDER_STATUS_SENDER` and `TRADE_STATUS_SENDER`.
+
+The unbounded channel grows without limit if the consumer is slower than the events,
+e.g. after subscribing to all the trades. The bounded channel applies the `OverflowPolicy`.
+
+# Example of use
+```ignore
+let (trade_sender, mut trade_receiver) = bounded_channel(10_000, OverflowPolicy::DropNewest);
+TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.clone())));
+// ...
+info!("trades delivered: {}, dropped: {}", trade_sender.delivered(), trade_sender.dropped());
+```
+*/
+#[derive(Debug)]
+pub struct EventSender<T> {
+    channel: Channel<T>,
+    delivered: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        let channel = match &self.channel {
+            Channel::Unbounded(sender) => Channel::Unbounded(sender.clone()),
+            Channel::Bounded(sender, policy) => Channel::Bounded(sender.clone(), *policy),
+        };
+
+        EventSender {
+            channel,
+            delivered: Arc::clone(&self.delivered),
+            dropped: Arc::clone(&self.dropped),
+        }
+    }
+}
+
+impl<T> EventSender<T> {
+    pub fn unbounded(sender: UnboundedSender<T>) -> Self {
+        EventSender {
+            channel: Channel::Unbounded(sender),
+            delivered: Arc::new(AtomicU64::new(0)),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn bounded(sender: Sender<T>, policy: OverflowPolicy) -> Self {
+        EventSender {
+            channel: Channel::Bounded(sender, policy),
+            delivered: Arc::new(AtomicU64::new(0)),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The number of the events sent to the channel, shared by the clones of the sender.
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    /// The number of the events dropped because the bounded channel was full,
+    /// shared by the clones of the sender.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends the event according to the type of the channel and the overflow policy.
+    pub fn send(&self, event: T) -> Result<(), EventSendError> {
+        let result = match &self.channel {
+            Channel::Unbounded(sender) => sender.send(event).map_err(|_| EventSendError::Closed),
+            Channel::Bounded(sender, OverflowPolicy::Block) => sender
+                .blocking_send(event)
+                .map_err(|_| EventSendError::Closed),
+            Channel::Bounded(sender, OverflowPolicy::DropNewest) => {
+                sender.try_send(event).map_err(|err| match err {
+                    mpsc::error::TrySendError::Full(_) => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        EventSendError::Full
+                    }
+                    mpsc::error::TrySendError::Closed(_) => EventSendError::Closed,
+                })
+            }
+        };
+
+        if result.is_ok() {
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
+
+impl<T> From<UnboundedSender<T>> for EventSender<T> {
+    fn from(sender: UnboundedSender<T>) -> Self {
+        EventSender::unbounded(sender)
+    }
+}
+
+/// Creates the bounded channel of the events with the capacity and the overflow policy.
+pub fn bounded_channel<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (EventSender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    (EventSender::bounded(sender, policy), receiver)
+}
+
+/// The receivers of the events of the callback functions returned by `Terminal::channels`.
+#[derive(Debug)]
+pub struct EventReceivers {
+    pub transaction_reply: UnboundedReceiver<TransactionInfo>,
+    pub order_status: UnboundedReceiver<OrderInfo>,
+    pub trade_status: UnboundedReceiver<TradeInfo>,
+}
+
+/// Error of sending the event to the channel.
+#[derive(Debug, PartialEq)]
+pub enum EventSendError {
+    /// The receiver is dropped.
+    Closed,
+    /// The bounded channel is full, the event is dropped.
+    Full,
+}
+
+impl fmt::Display for EventSendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventSendError::Closed => write!(f, "Channel closed"),
+            EventSendError::Full => write!(f, "Channel full, event dropped"),
+        }
+    }
+}
+
+impl error::Error for EventSendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_channel_overflow() {
+        let (sender, mut receiver) = bounded_channel(2, OverflowPolicy::DropNewest);
+        let counter = sender.clone();
+
+        assert_eq!(sender.send(1), Ok(()));
+        assert_eq!(sender.send(2), Ok(()));
+        assert_eq!(sender.send(3), Err(EventSendError::Full));
+        assert_eq!(sender.send(4), Err(EventSendError::Full));
+        assert_eq!(counter.dropped(), 2);
+
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Ok(2));
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(sender.send(5), Ok(()));
+        assert_eq!(receiver.try_recv(), Ok(5));
+
+        drop(receiver);
+        assert_eq!(sender.send(6), Err(EventSendError::Closed));
+        assert_eq!(counter.dropped(), 2);
+        assert_eq!(counter.delivered(), 3);
+    }
+
+    #[test]
+    fn test_bounded_channel_block() {
+        let (sender, mut receiver) = bounded_channel(1, OverflowPolicy::Block);
+
+        let producer = std::thread::spawn(move || {
+            for event in 0..3 {
+                sender.send(event).unwrap();
+            }
+        });
+
+        let received: Vec<i32> = (0..3).map(|_| receiver.blocking_recv().unwrap()).collect();
+        producer.join().unwrap();
+
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let sender = EventSender::from(sender);
+
+        for event in 0..100 {
+            sender.send(event).unwrap();
+        }
+
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert_eq!(sender.dropped(), 0);
+    }
+}
@@ -0,0 +1,88 @@
+//! Temporary change of the current directory of the process.
+use lazy_static::lazy_static;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+lazy_static! {
+    /// The current directory is shared by the whole process,
+    /// the terminals change it one at a time.
+    static ref CURRENT_DIR_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Serializes the tests checking the current directory.
+#[cfg(test)]
+pub(crate) fn test_lock() -> MutexGuard<'static, ()> {
+    TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Sets the current directory of the process and restores the previous one when dropped.
+///
+/// The guard serializes only the terminals of this crate: the other threads of the process
+/// resolving relative paths while the guard is alive see the changed directory.
+pub(crate) struct CurrentDirGuard {
+    previous_dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl CurrentDirGuard {
+    pub(crate) fn set(dir: &Path) -> io::Result<Self> {
+        let lock = CURRENT_DIR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_dir = env::current_dir()?;
+        env::set_current_dir(dir)?;
+
+        Ok(CurrentDirGuard {
+            previous_dir,
+            _lock: lock,
+        })
+    }
+}
+
+impl Drop for CurrentDirGuard {
+    fn drop(&mut self) {
+        if let Err(err) = env::set_current_dir(&self.previous_dir) {
+            tracing::error!(
+                "failed to restore the current directory {:?}: {}",
+                self.previous_dir,
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_dir_restored() {
+        let _test_lock = test_lock();
+        let previous_dir = env::current_dir().unwrap();
+        let temp_dir = env::temp_dir().canonicalize().unwrap();
+
+        {
+            let _guard = CurrentDirGuard::set(&temp_dir).unwrap();
+            assert_eq!(
+                env::current_dir().unwrap().canonicalize().unwrap(),
+                temp_dir
+            );
+        }
+
+        assert_eq!(env::current_dir().unwrap(), previous_dir);
+    }
+
+    #[test]
+    fn test_nonexistent_dir() {
+        let _test_lock = test_lock();
+        let previous_dir = env::current_dir().unwrap();
+
+        assert!(CurrentDirGuard::set(Path::new("/invalid/path/to/nonexistent/dir")).is_err());
+        assert_eq!(env::current_dir().unwrap(), previous_dir);
+    }
+}
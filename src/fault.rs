@@ -0,0 +1,141 @@
+//! Injection of the faults into the calls of the library Trans2QUIK.dll, available in the tests
+//! of the crate and with the feature `test-util`. Allows the tests to exercise the handling of
+//! the errors, e.g. the retries of the strategy on `Trans2QuikResult::QuikNotConnected`,
+//! without the real library. Without the feature the functions are called as is.
+#![cfg_attr(not(any(test, feature = "test-util")), allow(unused_variables))]
+use libc::c_long;
+
+#[cfg(any(test, feature = "test-util"))]
+pub use injection::{clear_faults, inject_fault, Fault};
+
+/// Calls the function of the library named `function_name`, e.g. `TRANS2QUIK_CONNECT`,
+/// unless the fault injected for it returns the result instead.
+#[inline]
+pub(crate) fn call<F: FnOnce() -> c_long>(function_name: &str, function: F) -> c_long {
+    #[cfg(any(test, feature = "test-util"))]
+    if let Some(code) = injection::take(function_name) {
+        return code;
+    }
+
+    function()
+}
+
+#[cfg(any(test, feature = "test-util"))]
+mod injection {
+    use crate::Trans2QuikResult;
+    use lazy_static::lazy_static;
+    use libc::c_long;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tracing::warn;
+
+    lazy_static! {
+        static ref FAULTS: Mutex<HashMap<String, VecDeque<Fault>>> = Mutex::new(HashMap::new());
+    }
+
+    /// The fault of the next call of the function of the library, see `inject_fault`.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Fault {
+        /// Returned instead of calling the function, `None` calls the function after the delay.
+        pub result: Option<Trans2QuikResult>,
+        /// The duration of the call before returning the result.
+        pub delay: Duration,
+    }
+
+    impl Fault {
+        /// The fault returning the `result` without calling the function.
+        pub fn result(result: Trans2QuikResult) -> Self {
+            Fault {
+                result: Some(result),
+                ..Default::default()
+            }
+        }
+
+        /// The fault delaying the call of the function.
+        pub fn delay(delay: Duration) -> Self {
+            Fault {
+                delay,
+                ..Default::default()
+            }
+        }
+    }
+
+    /**
+    Injects the fault into the next call of the function of the library named `function_name`,
+    e.g. `TRANS2QUIK_SEND_ASYNC_TRANSACTION`. The faults injected into the same function apply
+    to its calls one by one. The faults are process-global, like `reset_globals`, such tests
+    must not run concurrently.
+
+    # Example of use
+    ```ignore
+    inject_fault(
+        "TRANS2QUIK_SEND_ASYNC_TRANSACTION",
+        Fault::result(Trans2QuikResult::QuikNotConnected),
+    );
+    ```
+    */
+    pub fn inject_fault(function_name: &str, fault: Fault) {
+        FAULTS
+            .lock()
+            .unwrap()
+            .entry(function_name.to_string())
+            .or_default()
+            .push_back(fault);
+    }
+
+    /// Removes the faults not applied yet.
+    pub fn clear_faults() {
+        FAULTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        FAULTS.clear_poison();
+    }
+
+    /// Applies the next fault of the function: sleeps for its delay and returns the code
+    /// of its result.
+    pub(super) fn take(function_name: &str) -> Option<c_long> {
+        let fault = FAULTS
+            .lock()
+            .unwrap()
+            .get_mut(function_name)
+            .and_then(VecDeque::pop_front)?;
+
+        warn!("{} -> injected fault: {:?}", function_name, fault);
+        std::thread::sleep(fault.delay);
+
+        fault.result.as_ref().map(code)
+    }
+
+    /// The code of the result returned by the library.
+    fn code(result: &Trans2QuikResult) -> c_long {
+        match result {
+            Trans2QuikResult::Success => 0,
+            Trans2QuikResult::Failed => 1,
+            Trans2QuikResult::TerminalNotFound => 2,
+            Trans2QuikResult::DllVersionNotSupported => 3,
+            Trans2QuikResult::AlreadyConnectedToQuik => 4,
+            Trans2QuikResult::WrongSyntax => 5,
+            Trans2QuikResult::QuikNotConnected => 6,
+            Trans2QuikResult::DllNotConnected => 7,
+            Trans2QuikResult::QuikConnected => 8,
+            Trans2QuikResult::QuikDisconnected => 9,
+            Trans2QuikResult::DllConnected => 10,
+            Trans2QuikResult::DllDisconnected => 11,
+            Trans2QuikResult::MemoryAllocationError => 12,
+            Trans2QuikResult::WrongConnectionHandle => 13,
+            Trans2QuikResult::WrongInputParams => 14,
+            Trans2QuikResult::Unknown(code) => *code,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_code() {
+            for result_code in [0, 6, 14, 999] {
+                assert_eq!(code(&Trans2QuikResult::from(result_code)), result_code);
+            }
+        }
+    }
+}
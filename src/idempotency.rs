@@ -0,0 +1,206 @@
+//! Protection of the transactions from the duplicate sending by the application retrying them,
+//! see `Terminal::send_idempotent`.
+use crate::{Trans2QuikError, TransactionInfo};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::warn;
+
+/// How long the replies are remembered by default.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+enum Entry {
+    /// The transaction is sent, the receiver is notified when its sender is dropped.
+    InFlight(watch::Receiver<()>),
+    Completed(Box<TransactionInfo>, Instant),
+    /// The reply didn't arrive, the transaction may have been executed.
+    TimedOut(Instant),
+}
+
+/// What to do with the transaction of the key, see `IdempotencyKeys::begin`.
+#[derive(Debug)]
+pub(crate) enum Begin {
+    /// Send the transaction, the waiting duplicates are notified when the sender is dropped.
+    Send(watch::Sender<()>),
+    /// The transaction of the key is being sent, check again when notified.
+    Wait(watch::Receiver<()>),
+    Completed(Box<TransactionInfo>),
+    TimedOut,
+}
+
+/// The keys of the transactions sent by `Terminal::send_idempotent` and their replies.
+#[derive(Debug)]
+pub(crate) struct IdempotencyKeys {
+    window: Duration,
+    entries: HashMap<String, Entry>,
+}
+
+impl Default for IdempotencyKeys {
+    fn default() -> Self {
+        IdempotencyKeys {
+            window: DEFAULT_WINDOW,
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl IdempotencyKeys {
+    pub(crate) fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Starts sending the transaction of the key at `now` unless it's being sent or was sent
+    /// within the window.
+    pub(crate) fn begin(&mut self, key: &str, now: Instant) -> Begin {
+        let window = self.window;
+        self.entries.retain(|_, entry| match entry {
+            // The sending future was dropped without completing.
+            Entry::InFlight(receiver) => receiver.has_changed().is_ok(),
+            Entry::Completed(_, completed) | Entry::TimedOut(completed) => {
+                now.saturating_duration_since(*completed) < window
+            }
+        });
+
+        match self.entries.get(key) {
+            Some(Entry::InFlight(receiver)) => Begin::Wait(receiver.clone()),
+            Some(Entry::Completed(transaction_info, _)) => {
+                Begin::Completed(transaction_info.clone())
+            }
+            Some(Entry::TimedOut(_)) => Begin::TimedOut,
+            None => {
+                let (sender, receiver) = watch::channel(());
+                self.entries
+                    .insert(key.to_string(), Entry::InFlight(receiver));
+                Begin::Send(sender)
+            }
+        }
+    }
+
+    pub(crate) fn complete(&mut self, key: &str, transaction_info: TransactionInfo, now: Instant) {
+        self.entries.insert(
+            key.to_string(),
+            Entry::Completed(Box::new(transaction_info), now),
+        );
+    }
+
+    pub(crate) fn time_out(&mut self, key: &str, now: Instant) {
+        self.entries.insert(key.to_string(), Entry::TimedOut(now));
+    }
+
+    /// Forgets the key of the transaction not sent, so it can be sent again.
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+}
+
+/// The transaction of the key being sent by `Terminal::send_idempotent`. If the sending future
+/// is dropped after the transaction is sent, e.g. on a timeout, the key is marked timed out
+/// so that the retries don't send the transaction again.
+pub(crate) struct Sending<'a> {
+    keys: &'a Mutex<IdempotencyKeys>,
+    key: &'a str,
+    sent: bool,
+    finished: bool,
+    /// Notifies the waiting duplicates when dropped, after the key is updated.
+    _sender: watch::Sender<()>,
+}
+
+impl<'a> Sending<'a> {
+    pub(crate) fn new(
+        keys: &'a Mutex<IdempotencyKeys>,
+        key: &'a str,
+        sender: watch::Sender<()>,
+    ) -> Self {
+        Sending {
+            keys,
+            key,
+            sent: false,
+            finished: false,
+            _sender: sender,
+        }
+    }
+
+    /// The transaction is passed to the library, it may be executed from now on.
+    pub(crate) fn sent(&mut self) {
+        self.sent = true;
+    }
+
+    /// Remembers the reply of the transaction, or forgets the key of the transaction not sent.
+    pub(crate) fn finish(mut self, result: &Result<TransactionInfo, Trans2QuikError>) {
+        let mut keys = self.keys.lock().unwrap();
+        match result {
+            Ok(transaction_info) => {
+                keys.complete(self.key, transaction_info.clone(), Instant::now())
+            }
+            Err(_) if self.sent => keys.time_out(self.key, Instant::now()),
+            Err(_) => keys.forget(self.key),
+        }
+        drop(keys);
+
+        self.finished = true;
+    }
+}
+
+impl Drop for Sending<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        if self.sent {
+            warn!(
+                "the transaction with the key {} is sent, but its reply is not awaited",
+                self.key
+            );
+            keys.time_out(self.key, Instant::now());
+        } else {
+            keys.forget(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_begin() {
+        let mut keys = IdempotencyKeys::default();
+        keys.set_window(Duration::from_secs(10));
+        let now = Instant::now();
+
+        let sender = match keys.begin("order-1", now) {
+            Begin::Send(sender) => sender,
+            other => panic!("unexpected begin: {:?}", other),
+        };
+        assert!(matches!(keys.begin("order-1", now), Begin::Wait(_)));
+        assert!(matches!(keys.begin("order-2", now), Begin::Send(_)));
+
+        keys.complete("order-1", TransactionInfo::default(), now);
+        drop(sender);
+        assert!(matches!(keys.begin("order-1", now), Begin::Completed(_)));
+
+        // Remembered within the window only.
+        let later = now + Duration::from_secs(10);
+        assert!(matches!(keys.begin("order-1", later), Begin::Send(_)));
+
+        keys.time_out("order-3", now);
+        assert!(matches!(keys.begin("order-3", now), Begin::TimedOut));
+
+        keys.forget("order-1");
+        assert!(matches!(keys.begin("order-1", later), Begin::Send(_)));
+    }
+
+    #[test]
+    fn test_dropped_sender() {
+        let mut keys = IdempotencyKeys::default();
+        let now = Instant::now();
+
+        // The sending future is dropped before the reply.
+        drop(keys.begin("order-1", now));
+        assert!(matches!(keys.begin("order-1", now), Begin::Send(_)));
+    }
+}
@@ -0,0 +1,258 @@
+//! Metadata of the instruments: the lot size and the price step, stored by QUIK
+//! but not provided by the library Trans2QUIK.dll.
+use crate::PriceStep;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The lot size and the price step of the instrument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstrumentInfo {
+    /// The number of the securities in one lot, e.g. `10` for SBER.
+    pub lot_size: i64,
+    /// The price step, e.g. `0.01` for SBER.
+    pub price_step: f64,
+}
+
+impl InstrumentInfo {
+    pub fn price_step(&self) -> PriceStep {
+        PriceStep::new(self.price_step)
+    }
+}
+
+/// The source of the metadata of the instruments, e.g. a table exported from QUIK,
+/// see `Terminal::set_instrument_info`.
+pub trait InstrumentInfoProvider: Send + Sync {
+    /// The metadata of the instrument, `None` if the instrument is unknown.
+    fn instrument_info(&self, class_code: &str, sec_code: &str) -> Option<InstrumentInfo>;
+}
+
+/// Error of reading the table of the instruments.
+#[derive(Debug)]
+pub enum InstrumentTableError {
+    Io(io::Error),
+    /// The line of the table, starting from 1, is not valid.
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for InstrumentTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrumentTableError::Io(err) => write!(f, "IO error: {}", err),
+            InstrumentTableError::InvalidLine(number, line) => {
+                write!(
+                    f,
+                    "Invalid line {} of the instrument table: {}",
+                    number, line
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for InstrumentTableError {}
+
+impl From<io::Error> for InstrumentTableError {
+    fn from(err: io::Error) -> Self {
+        InstrumentTableError::Io(err)
+    }
+}
+
+/**
+The table of the instruments supplied by the user.
+
+The CSV table has the columns `class_code,sec_code,lot_size,price_step`, the semicolon is accepted
+as the separator as well. The empty lines, the lines starting with `#` and the header are skipped.
+
+# Example of use
+```ignore
+let table = InstrumentTable::from_csv_file("instruments.csv")?;
+terminal.set_instrument_info(table);
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentTable {
+    instruments: HashMap<(String, String), InstrumentInfo>,
+}
+
+impl InstrumentTable {
+    pub fn new() -> Self {
+        InstrumentTable::default()
+    }
+
+    /// Adds the instrument, replacing the previous metadata.
+    pub fn insert(&mut self, class_code: &str, sec_code: &str, info: InstrumentInfo) {
+        self.instruments
+            .insert((class_code.to_string(), sec_code.to_string()), info);
+    }
+
+    pub fn from_csv<R: BufRead>(reader: R) -> Result<Self, InstrumentTableError> {
+        let mut table = InstrumentTable::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+
+            if trimmed.is_empty()
+                || trimmed.starts_with('#')
+                || trimmed.to_ascii_lowercase().starts_with("class_code")
+            {
+                continue;
+            }
+
+            let separator = if trimmed.contains(';') { ';' } else { ',' };
+            let fields: Vec<&str> = trimmed.split(separator).map(str::trim).collect();
+
+            let info = match fields.as_slice() {
+                [_, _, lot_size, price_step] => lot_size
+                    .parse::<i64>()
+                    .ok()
+                    .zip(price_step.replace(',', ".").parse::<f64>().ok())
+                    .filter(|(lot_size, price_step)| *lot_size > 0 && *price_step > 0.0)
+                    .map(|(lot_size, price_step)| InstrumentInfo {
+                        lot_size,
+                        price_step,
+                    }),
+                _ => None,
+            };
+
+            match info {
+                Some(info) => table.insert(fields[0], fields[1], info),
+                None => return Err(InstrumentTableError::InvalidLine(index + 1, line)),
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn from_csv_file<P: AsRef<Path>>(path: P) -> Result<Self, InstrumentTableError> {
+        InstrumentTable::from_csv(BufReader::new(File::open(path)?))
+    }
+}
+
+impl InstrumentInfoProvider for InstrumentTable {
+    fn instrument_info(&self, class_code: &str, sec_code: &str) -> Option<InstrumentInfo> {
+        self.instruments
+            .get(&(class_code.to_string(), sec_code.to_string()))
+            .copied()
+    }
+}
+
+/// Caches the metadata of the provider, which can be slow, e.g. request QUIK.
+/// Shared by the terminal and its transaction builders.
+pub(crate) struct InstrumentInfoCache {
+    provider: Box<dyn InstrumentInfoProvider>,
+    cache: Mutex<HashMap<(String, String), Option<InstrumentInfo>>>,
+}
+
+impl InstrumentInfoCache {
+    pub(crate) fn new<P: InstrumentInfoProvider + 'static>(provider: P) -> Arc<Self> {
+        Arc::new(InstrumentInfoCache {
+            provider: Box::new(provider),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn get(&self, class_code: &str, sec_code: &str) -> Option<InstrumentInfo> {
+        let key = (class_code.to_string(), sec_code.to_string());
+
+        if let Some(info) = self.cache.lock().unwrap().get(&key) {
+            return *info;
+        }
+
+        let info = self.provider.instrument_info(class_code, sec_code);
+        self.cache.lock().unwrap().insert(key, info);
+        info
+    }
+}
+
+impl fmt::Debug for InstrumentInfoCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentInfoCache")
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SAMPLE_TABLE: &str = "\
+class_code;sec_code;lot_size;price_step
+# Shares
+TQBR;SBER;10;0,01
+TQBR;GAZP;10;0,01
+
+SPBFUT;SiZ6;1;1
+";
+
+    #[test]
+    fn test_from_csv() {
+        let table = InstrumentTable::from_csv(SAMPLE_TABLE.as_bytes()).unwrap();
+
+        assert_eq!(
+            table.instrument_info("TQBR", "SBER"),
+            Some(InstrumentInfo {
+                lot_size: 10,
+                price_step: 0.01,
+            })
+        );
+        assert_eq!(
+            table.instrument_info("SPBFUT", "SiZ6"),
+            Some(InstrumentInfo {
+                lot_size: 1,
+                price_step: 1.0,
+            })
+        );
+        assert_eq!(table.instrument_info("TQBR", "LKOH"), None);
+
+        let table = InstrumentTable::from_csv("TQBR,SBER,10,0.01".as_bytes()).unwrap();
+        assert_eq!(table.instrument_info("TQBR", "SBER").unwrap().lot_size, 10);
+    }
+
+    #[test]
+    fn test_from_csv_invalid_line() {
+        let result = InstrumentTable::from_csv("TQBR;SBER;10;0,01\nTQBR;GAZP;0;0,01".as_bytes());
+        assert!(matches!(
+            result,
+            Err(InstrumentTableError::InvalidLine(2, _))
+        ));
+
+        let result = InstrumentTable::from_csv("TQBR;SBER;10".as_bytes());
+        assert!(matches!(
+            result,
+            Err(InstrumentTableError::InvalidLine(1, _))
+        ));
+    }
+
+    struct CountingProvider(Arc<AtomicUsize>);
+
+    impl InstrumentInfoProvider for CountingProvider {
+        fn instrument_info(&self, _class_code: &str, sec_code: &str) -> Option<InstrumentInfo> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            (sec_code == "SBER").then_some(InstrumentInfo {
+                lot_size: 10,
+                price_step: 0.01,
+            })
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = InstrumentInfoCache::new(CountingProvider(Arc::clone(&calls)));
+
+        for _ in 0..3 {
+            assert!(cache.get("TQBR", "SBER").is_some());
+            assert!(cache.get("TQBR", "LKOH").is_none());
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,159 @@
+//! The streams of the events as the JSON values, e.g. to forward them to the websocket
+//! clients of a trading UI. Enabled by the feature `json`.
+//!
+//! Every event is tagged by its type: `{"type": "order", "data": {...}}`, the types are
+//! `order`, `trade` and `transaction_reply`, the data is the serialized `OrderInfo`,
+//! `TradeInfo` or `TransactionInfo`.
+use crate::{OrderInfo, Terminal, TradeInfo, TransactionInfo};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{error, warn};
+
+/// The orders of the receiver as the events of the type `order`.
+pub fn orders(receiver: broadcast::Receiver<OrderInfo>) -> impl Stream<Item = Value> {
+    event_stream("order", receiver)
+}
+
+/// The trades of the receiver as the events of the type `trade`.
+pub fn trades(receiver: broadcast::Receiver<TradeInfo>) -> impl Stream<Item = Value> {
+    event_stream("trade", receiver)
+}
+
+/// The transaction replies of the receiver as the events of the type `transaction_reply`.
+pub fn transaction_replies(
+    receiver: broadcast::Receiver<TransactionInfo>,
+) -> impl Stream<Item = Value> {
+    event_stream("transaction_reply", receiver)
+}
+
+/**
+The orders, the trades and the transaction replies of the terminal in the order of receiving,
+subscribed to the broadcast channels of the terminal.
+
+# Example of use
+```ignore
+let mut events = trans2quik::json::events(&terminal);
+while let Some(event) = events.next().await {
+    websocket.send(event.to_string()).await?;
+}
+```
+*/
+pub fn events(terminal: &Terminal) -> impl Stream<Item = Value> {
+    orders(terminal.subscribe_orders_broadcast())
+        .merge(trades(terminal.subscribe_trades_broadcast()))
+        .merge(transaction_replies(
+            terminal.subscribe_transaction_replies_broadcast(),
+        ))
+}
+
+/// The event tagged by its type, `None` if the event can't be serialized.
+fn event<T: Serialize>(event_type: &str, data: &T) -> Option<Value> {
+    match serde_json::to_value(data) {
+        Ok(data) => Some(json!({ "type": event_type, "data": data })),
+        Err(err) => {
+            error!("{} serialization error: {}", event_type, err);
+            None
+        }
+    }
+}
+
+fn event_stream<T>(
+    event_type: &'static str,
+    receiver: broadcast::Receiver<T>,
+) -> impl Stream<Item = Value>
+where
+    T: Serialize + Clone + Send + 'static,
+{
+    BroadcastStream::new(receiver).filter_map(move |data| match data {
+        Ok(data) => event(event_type, &data),
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            warn!("json {} stream skipped {} events", event_type, skipped);
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IsSell, Mode, ReplyCode, Status, TransId};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_event() {
+        let order_info = OrderInfo {
+            mode: Mode::NewOrder,
+            trans_id: TransId::Id(7),
+            order_num: 1,
+            sec_code: String::from("SBER"),
+            price: 250.5,
+            is_sell: IsSell::Sell,
+            status: Status::Active,
+            date: NaiveDate::from_ymd_opt(2024, 11, 15),
+            ..Default::default()
+        };
+
+        let value = event("order", &order_info).unwrap();
+        assert_eq!(value["type"], "order");
+        assert_eq!(value["data"]["mode"], "NewOrder");
+        assert_eq!(value["data"]["trans_id"], json!({ "Id": 7 }));
+        assert_eq!(value["data"]["sec_code"], "SBER");
+        assert_eq!(value["data"]["price"], 250.5);
+        assert_eq!(value["data"]["is_sell"], "Sell");
+        assert_eq!(value["data"]["date"], "2024-11-15");
+        assert_eq!(value["data"]["time"], Value::Null);
+
+        let transaction_info = TransactionInfo {
+            reply_code: ReplyCode::Rejected,
+            reply_message: String::from("Недостаточно средств"),
+            rejection_reason: Some(crate::RejectionReason::InsufficientFunds),
+            ..Default::default()
+        };
+
+        let value = event("transaction_reply", &transaction_info).unwrap();
+        assert_eq!(value["data"]["reply_code"], "Rejected");
+        assert_eq!(value["data"]["reply_message"], "Недостаточно средств");
+        assert_eq!(value["data"]["rejection_reason"], "InsufficientFunds");
+    }
+
+    #[test]
+    fn test_streams() {
+        let (order_sender, order_receiver) = broadcast::channel(16);
+        let (trade_sender, trade_receiver) = broadcast::channel(16);
+
+        order_sender
+            .send(OrderInfo {
+                order_num: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        trade_sender
+            .send(TradeInfo {
+                trade_num: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        drop(order_sender);
+        drop(trade_sender);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let events: Vec<Value> = runtime.block_on(
+            orders(order_receiver)
+                .merge(trades(trade_receiver))
+                .collect(),
+        );
+
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .any(|event| event["type"] == "trade" && event["data"]["trade_num"] == 2));
+        assert!(events
+            .iter()
+            .any(|event| event["type"] == "order" && event["data"]["order_num"] == 1));
+    }
+}
@@ -32,31 +32,190 @@
 //! the procedure for obtaining information using the callback functions is started.
 //! Upon termination of receiving information on applications and transactions, the lists
 //! of received instruments are cleared.
+//!
+//! The library exports the ANSI functions only, there are no wide-char (`...W`) variants taking
+//! UTF-16 strings. The transactions are passed in WINDOWS-1251, the characters not representable
+//! in it, e.g. in COMMENT, are sent as the HTML numeric character references like `&#945;` for α.
 // #![allow(dead_code)]
-use chrono::{NaiveDate, NaiveTime};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{Datelike, Local, NaiveDate, NaiveTime, Timelike};
 use encoding_rs::WINDOWS_1251;
 use lazy_static::lazy_static;
 use libc::{c_char, c_double, c_long, c_ulonglong, intptr_t};
 use libloading::{Error as LibloadingError, Library, Symbol};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error;
 use std::ffi::{CStr, CString, NulError};
 use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::str;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::UnboundedSender;
-use tracing::{error, info};
-
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{error, info, warn};
+
+mod aggregator;
+mod callback_panic;
+mod channel;
+mod current_dir;
+mod fault;
+mod idempotency;
+mod instrument;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(test)]
+mod mock;
+mod order_filter;
+mod price;
+mod rate_limit;
+mod rejection;
+mod telemetry;
+mod trade_kind;
+mod transaction;
+mod watchdog;
+
+pub use aggregator::{SessionStats, TradeAggregator, TradeStats};
+pub use channel::{
+    bounded_channel, EventReceivers, EventSendError, EventSender, OverflowPolicy, PausePolicy,
+};
+pub use encoding_rs::Encoding;
+#[cfg(any(test, feature = "test-util"))]
+pub use fault::{clear_faults, inject_fault, Fault};
+pub use instrument::{
+    InstrumentInfo, InstrumentInfoProvider, InstrumentTable, InstrumentTableError,
+};
+pub use order_filter::OrderStateFilter;
+pub use price::{round_to_step, PriceStep};
+pub use rate_limit::RateLimitMode;
+pub use rejection::{classify_rejection, RejectionReason};
+pub use trade_kind::TradeKind;
+pub use transaction::{
+    Action, OrderType, StopOrderKind, TransactionBuildError, TransactionBuilder,
+};
+
+use current_dir::CurrentDirGuard;
+use idempotency::{Begin, IdempotencyKeys, Sending};
+use instrument::InstrumentInfoCache;
+use rate_limit::RateLimiter;
+
+// The callback functions are called by the library Trans2QUIK.dll on its own threads, not on
+// the threads of the tokio runtime. Sending to the channels doesn't need the runtime,
+// and neither does creating them, see `Terminal::channels`.
+//
+// The callback functions can run concurrently on different threads. They never hold a lock
+// while calling the user code: the handler and the senders, which can block with
+// `OverflowPolicy::Block`, are read without locking, `TERMINAL_INSTANCE` is locked only
+// to copy the descriptor getters. The other mutexes are locked one at a time and never nested.
+// `PAUSED_EVENTS` is held by `Terminal::resume_events` while delivering the buffered events,
+// so that the callback functions wait for them and the order of the events is kept.
 lazy_static! {
-    pub static ref TRANSACTION_REPLY_SENDER: Mutex<Option<UnboundedSender<TransactionInfo>>> =
-        Mutex::new(None);
-    pub static ref ORDER_STATUS_SENDER: Mutex<Option<UnboundedSender<OrderInfo>>> =
-        Mutex::new(None);
-    pub static ref TRADE_STATUS_SENDER: Mutex<Option<UnboundedSender<TradeInfo>>> =
-        Mutex::new(None);
-    static ref TERMINAL_INSTANCE: Mutex<Option<Arc<Mutex<Terminal>>>> = Mutex::new(None);
+    pub static ref TRANSACTION_REPLY_SENDER: ArcSwapOption<EventSender<TransactionInfo>> =
+        ArcSwapOption::empty();
+    pub static ref ORDER_STATUS_SENDER: ArcSwapOption<EventSender<OrderInfo>> =
+        ArcSwapOption::empty();
+    pub static ref TRADE_STATUS_SENDER: ArcSwapOption<EventSender<TradeInfo>> =
+        ArcSwapOption::empty();
+    static ref TERMINAL_INSTANCE: Mutex<Option<CallbackTerminal>> = Mutex::new(None);
+    static ref TRANSACTION_REPLY_HANDLER: ArcSwapOption<TransactionReplyHandler> =
+        ArcSwapOption::empty();
+    static ref SENT_ORDERS: Mutex<HashMap<c_long, SentOrder>> = Mutex::new(HashMap::new());
+    /// The last event received by the connection_status_callback.
+    static ref LAST_CONNECTION_EVENT: Mutex<Option<ConnectionEvent>> = Mutex::new(None);
+    /// The state of the connection for `Terminal::conn_state`.
+    static ref CONN_STATE: ArcSwap<ConnState> = ArcSwap::from_pointee(ConnState::Disconnected);
+    /// The copies of the events for the subscribers of `Terminal::subscribe_orders_broadcast`
+    /// and the similar methods.
+    static ref TRANSACTION_REPLY_BROADCAST: broadcast::Sender<TransactionInfo> =
+        broadcast::channel(BROADCAST_CAPACITY).0;
+    static ref ORDER_STATUS_BROADCAST: broadcast::Sender<OrderInfo> =
+        broadcast::channel(BROADCAST_CAPACITY).0;
+    static ref TRADE_STATUS_BROADCAST: broadcast::Sender<TradeInfo> =
+        broadcast::channel(BROADCAST_CAPACITY).0;
+    /// The last orders received by the order_status_callback for `Terminal::wait_for_order`.
+    static ref RECENT_ORDERS: Mutex<VecDeque<OrderInfo>> = Mutex::new(VecDeque::new());
+    /// The time of receiving the last new trade of the instrument for `Terminal::is_market_active`.
+    static ref LAST_TRADE_TIMES: Mutex<HashMap<(String, String), Instant>> =
+        Mutex::new(HashMap::new());
+    /// The orders and trades received per instrument for `Terminal::instrument_stats`.
+    static ref INSTRUMENT_STATS: Mutex<HashMap<(String, String), InstrumentStats>> =
+        Mutex::new(HashMap::new());
+    /// The events of the connection_status_callback for `Terminal::next_disconnect`.
+    static ref CONNECTION_EVENTS: broadcast::Sender<ConnectionEvent> = broadcast::channel(16).0;
+    /// The events of all the callback functions for `Terminal::events`.
+    static ref EVENT_BROADCAST: broadcast::Sender<Event> =
+        broadcast::channel(BROADCAST_CAPACITY).0;
+    static ref SENT_ORDERS_NOTIFY: Notify = Notify::new();
+    static ref TRANS_ID_COUNTER: AtomicI32 = AtomicI32::new(initial_trans_id());
+    static ref REPLY_WAITERS: Mutex<HashMap<c_long, oneshot::Sender<TransactionInfo>>> =
+        Mutex::new(HashMap::new());
+    static ref PENDING_TRANS_IDS: Mutex<HashSet<c_long>> = Mutex::new(HashSet::new());
+    static ref PENDING_TRANS_IDS_NOTIFY: Notify = Notify::new();
+    /// Notified when the initial orders are received, see `Terminal::orders_snapshot_complete`.
+    static ref ORDERS_SNAPSHOT_NOTIFY: Notify = Notify::new();
+    /// The events withheld while `EVENTS_PAUSED`, see `Terminal::pause_events`.
+    static ref PAUSED_EVENTS: Mutex<PausedEvents> = Mutex::new(PausedEvents::default());
+}
+
+/// The number of the orders kept in `RECENT_ORDERS`.
+const RECENT_ORDERS_CAPACITY: usize = 256;
+
+/// The number of the orders kept in `SENT_ORDERS`, the oldest one is forgotten first.
+const SENT_ORDERS_CAPACITY: usize = 1024;
+
+/// The number of the events kept for the slowest subscriber of the broadcast channels.
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// The logged prices and values use the comma as the decimal separator, as QUIK does.
+static LOG_DECIMAL_COMMA: AtomicBool = AtomicBool::new(false);
+
+/// The events with the strings failed to decode are dropped, see `Terminal::set_strict_decoding`.
+static STRICT_DECODING: AtomicBool = AtomicBool::new(false);
+
+/// The number of the events dropped by `Terminal::set_strict_decoding`.
+static UNDECODED_EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// A string of the current callback function failed to decode, see `drop_undecoded_event`.
+    static DECODE_FAILED: Cell<bool> = const { Cell::new(false) };
 }
 
+/// The events are not delivered to the channels, see `Terminal::pause_events`.
+static EVENTS_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// The order with `Mode::LastOrderReceived` is received since `Terminal::start_orders`.
+static ORDERS_SNAPSHOT_COMPLETE: AtomicBool = AtomicBool::new(false);
+
+/// The initial orders are not sent to the ORDER_STATUS_SENDER,
+/// see `Terminal::set_suppress_initial_orders`.
+static SUPPRESS_INITIAL_ORDERS: AtomicBool = AtomicBool::new(false);
+
+/// The sequence numbers of the last events of the callbacks, see `OrderInfo::seq`.
+static TRANSACTION_REPLY_SEQ: AtomicU64 = AtomicU64::new(0);
+static ORDER_STATUS_SEQ: AtomicU64 = AtomicU64::new(0);
+static TRADE_STATUS_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// The sequence number of the order remembered in `SENT_ORDERS`, the lowest is the oldest.
+static SENT_ORDER_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// The identifier of the next `Terminal`, see `Terminal::start_trades`.
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How long the crate waits for the transaction reply of an asynchronous transaction.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The user handler called from the transaction_reply_callback together with the access
+/// to the transaction reply descriptor.
+type TransactionReplyHandler =
+    Box<dyn Fn(&TransactionInfo, &TransactionReplyAccess<'_>) + Send + Sync>;
+
 /// Prototype of a callback function for monitoring the connection status.
 /// This function is used to track the state of the connection between the
 /// Trans2QUIK.dll library and the QUIK terminal, as well as the connection
@@ -81,6 +240,7 @@ type Trans2QuikTransactionReplyCallback = unsafe extern "C" fn(
 );
 
 /// A prototype of the callback function to get information about the order parameters.
+/// The balance is `__int64` in the library Trans2QUIK.dll, see `OrderInfo::qty`.
 type Trans2QuikOrderStatusCallback = unsafe extern "C" fn(
     mode: c_long,
     trans_id: c_long,
@@ -110,11 +270,14 @@ type Trans2QuikTradeStatusCallback = unsafe extern "C" fn(
 );
 
 /// Represents the state of order receipt.
-#[derive(Debug, PartialEq)]
+/// The default is `Mode::Unknown`.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub enum Mode {
     NewOrder = 0,
     InitialOrder = 1,
     LastOrderReceived = 2,
+    #[default]
     Unknown,
 }
 
@@ -132,12 +295,30 @@ impl From<c_long> for Mode {
 /// The TransID of the transaction that generated the request.
 /// It has a value of `0` if the request was not generated by a transaction from a file,
 /// or if the TransID is unknown.
-#[derive(Debug, PartialEq)]
+/// The default is `TransId::Unknown(0)`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub enum TransId {
     Id(c_long),
     Unknown(c_long),
 }
 
+impl Default for TransId {
+    fn default() -> Self {
+        TransId::Unknown(0)
+    }
+}
+
+impl TransId {
+    // c_long is i32 on Windows only.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            TransId::Id(id) | TransId::Unknown(id) => *id as i32,
+        }
+    }
+}
+
 impl From<c_long> for TransId {
     fn from(id: c_long) -> Self {
         match id {
@@ -148,8 +329,11 @@ impl From<c_long> for TransId {
 }
 
 /// Sending an application.
-#[derive(Debug, PartialEq)]
+/// The default is `IsSell::Buy` as the library Trans2QUIK.dll passes `0` for buy.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub enum IsSell {
+    #[default]
     Buy = 0,
     Sell,
 }
@@ -163,9 +347,31 @@ impl From<c_long> for IsSell {
     }
 }
 
+impl IsSell {
+    /// The value passed by the library Trans2QUIK.dll: `0` for buy, `1` for sell.
+    pub fn as_c_long(&self) -> c_long {
+        match self {
+            IsSell::Buy => 0,
+            IsSell::Sell => 1,
+        }
+    }
+}
+
+impl fmt::Display for IsSell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsSell::Buy => write!(f, "Buy"),
+            IsSell::Sell => write!(f, "Sell"),
+        }
+    }
+}
+
 /// Represents the execution status of an order.
-#[derive(Debug, PartialEq)]
+/// The default is `Status::Active`, the status of the new order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 pub enum Status {
+    #[default]
     Active = 1,
     Canceled = 2,
     Executed,
@@ -181,6 +387,33 @@ impl From<c_long> for Status {
     }
 }
 
+/// The execution condition of the order returned by TRANS2QUIK_ORDER_EXEC_TYPE.
+/// The default is `ExecCondition::Unknown`, also used for the other conditions, e.g. «До снятия»,
+/// and if the library Trans2QUIK.dll doesn't export TRANS2QUIK_ORDER_EXEC_TYPE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum ExecCondition {
+    /// «Поставить в очередь», the balance of the order rests in the order book.
+    PutInQueue,
+    /// «Немедленно или отклонить», the order is executed in full or canceled.
+    FillOrKill,
+    /// «Снять остаток», the balance not executed immediately is canceled.
+    CancelBalance,
+    #[default]
+    Unknown,
+}
+
+impl From<c_long> for ExecCondition {
+    fn from(code: c_long) -> Self {
+        match code {
+            1 => ExecCondition::FillOrKill,
+            2 => ExecCondition::PutInQueue,
+            3 => ExecCondition::CancelBalance,
+            _ => ExecCondition::Unknown,
+        }
+    }
+}
+
 /// Corresponds to the description of constants whose values are returned when exiting functions
 /// and procedures in the library Trans2QUIK.dll:
 /// ```
@@ -200,7 +433,11 @@ impl From<c_long> for Status {
 /// TRANS2QUIK_WRONG_CONNECTION_HANDLE 13
 /// TRANS2QUIK_WRONG_INPUT_PARAMS 14
 /// ```
-#[derive(Debug, PartialEq)]
+///
+/// The other codes, e.g. added by the new versions of the library, are kept
+/// in `Trans2QuikResult::Unknown`. The default is `Trans2QuikResult::Unknown(-1)`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 #[repr(i32)]
 pub enum Trans2QuikResult {
     Success = 0,
@@ -218,7 +455,14 @@ pub enum Trans2QuikResult {
     MemoryAllocationError = 12,
     WrongConnectionHandle = 13,
     WrongInputParams = 14,
-    Unknown,
+    /// The code unknown to the crate.
+    Unknown(c_long),
+}
+
+impl Default for Trans2QuikResult {
+    fn default() -> Self {
+        Trans2QuikResult::Unknown(-1)
+    }
 }
 
 impl From<c_long> for Trans2QuikResult {
@@ -239,16 +483,420 @@ impl From<c_long> for Trans2QuikResult {
             12 => Trans2QuikResult::MemoryAllocationError,
             13 => Trans2QuikResult::WrongConnectionHandle,
             14 => Trans2QuikResult::WrongInputParams,
-            _ => Trans2QuikResult::Unknown,
+            code => Trans2QuikResult::Unknown(code),
+        }
+    }
+}
+
+/// The severity of the result of the function of the library Trans2QUIK.dll,
+/// see `Trans2QuikResult::severity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Trans2QuikResult {
+    /// Classifies the result for monitoring: the successes and the established connections
+    /// are `Info`, the lost connections, which are restored by QUIK, are `Warning`,
+    /// the other results are `Error`.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Trans2QuikResult::Success
+            | Trans2QuikResult::AlreadyConnectedToQuik
+            | Trans2QuikResult::QuikConnected
+            | Trans2QuikResult::DllConnected => Severity::Info,
+            Trans2QuikResult::QuikNotConnected
+            | Trans2QuikResult::DllNotConnected
+            | Trans2QuikResult::QuikDisconnected
+            | Trans2QuikResult::DllDisconnected => Severity::Warning,
+            Trans2QuikResult::Failed
+            | Trans2QuikResult::TerminalNotFound
+            | Trans2QuikResult::DllVersionNotSupported
+            | Trans2QuikResult::WrongSyntax
+            | Trans2QuikResult::MemoryAllocationError
+            | Trans2QuikResult::WrongConnectionHandle
+            | Trans2QuikResult::WrongInputParams
+            | Trans2QuikResult::Unknown(_) => Severity::Error,
+        }
+    }
+}
+
+/// Logs the message about the result at the level of its severity.
+fn log_result(trans2quik_result: &Trans2QuikResult, message: fmt::Arguments<'_>) {
+    match trans2quik_result.severity() {
+        Severity::Info => info!("{}", message),
+        Severity::Warning => warn!("{}", message),
+        Severity::Error => error!("{}", message),
+    }
+}
+
+/// The extended error code returned with the result of the function of the library Trans2QUIK.dll.
+/// Most of the codes are the system error codes of Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ErrorCode(pub c_long);
+
+impl ErrorCode {
+    /// Description of the common error codes.
+    pub fn description(&self) -> &'static str {
+        match self.0 {
+            0 => "No error",
+            2 => "The system cannot find the file specified",
+            3 => "The system cannot find the path specified",
+            5 => "Access is denied",
+            6 => "The handle is invalid",
+            8 => "Not enough memory resources are available",
+            87 => "The parameter is incorrect",
+            109 => "The pipe has been ended",
+            126 => "The specified module could not be found",
+            127 => "The specified procedure could not be found",
+            231 => "All pipe instances are busy",
+            232 => "The pipe is being closed",
+            233 => "No process is on the other end of the pipe",
+            1460 => "This operation returned because the timeout period expired",
+            _ => "Unknown error code",
+        }
+    }
+}
+
+impl ErrorCode {
+    // c_long is i32 on Windows only.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn as_i32(&self) -> i32 {
+        self.0 as i32
+    }
+}
+
+impl From<c_long> for ErrorCode {
+    fn from(code: c_long) -> Self {
+        ErrorCode(code)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.0, self.description())
+    }
+}
+
+/// The status of the transaction reported by the QUIK server in the transaction reply.
+/// The default is `ReplyCode::Sent`, the code `0`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum ReplyCode {
+    /// The transaction is sent to the QUIK server.
+    #[default]
+    Sent,
+    /// The transaction is received by the QUIK server.
+    Received,
+    /// Error sending the transaction to the trading system, e.g. the gateway is disconnected.
+    SendError,
+    /// The transaction is executed.
+    Executed,
+    /// The transaction is rejected by the trading system.
+    Rejected,
+    /// The transaction failed the checks of the QUIK server.
+    NotAccepted,
+    /// The transaction failed the limit check of the QUIK server.
+    LimitsExceeded,
+    /// The transaction is not supported by the trading system.
+    NotSupported,
+    /// The transaction failed the electronic signature check.
+    SignatureError,
+    /// The reply of the trading system was not received in time.
+    Timeout,
+    /// The transaction is rejected as it could lead to a cross trade.
+    CrossTrade,
+    /// Any other code.
+    Unknown(c_long),
+}
+
+impl ReplyCode {
+    /// The transaction is not executed and won't be: any code except sent, received and executed.
+    pub fn is_rejected(&self) -> bool {
+        !matches!(
+            self,
+            ReplyCode::Sent | ReplyCode::Received | ReplyCode::Executed
+        )
+    }
+
+    // c_long is i32 on Windows only.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            ReplyCode::Sent => 0,
+            ReplyCode::Received => 1,
+            ReplyCode::SendError => 2,
+            ReplyCode::Executed => 3,
+            ReplyCode::Rejected => 4,
+            ReplyCode::NotAccepted => 5,
+            ReplyCode::LimitsExceeded => 6,
+            ReplyCode::NotSupported => 10,
+            ReplyCode::SignatureError => 11,
+            ReplyCode::Timeout => 12,
+            ReplyCode::CrossTrade => 13,
+            ReplyCode::Unknown(code) => *code as i32,
+        }
+    }
+}
+
+impl From<c_long> for ReplyCode {
+    fn from(code: c_long) -> Self {
+        match code {
+            0 => ReplyCode::Sent,
+            1 => ReplyCode::Received,
+            2 => ReplyCode::SendError,
+            3 => ReplyCode::Executed,
+            4 => ReplyCode::Rejected,
+            5 => ReplyCode::NotAccepted,
+            6 => ReplyCode::LimitsExceeded,
+            10 => ReplyCode::NotSupported,
+            11 => ReplyCode::SignatureError,
+            12 => ReplyCode::Timeout,
+            13 => ReplyCode::CrossTrade,
+            _ => ReplyCode::Unknown(code),
+        }
+    }
+}
+
+/// The successful subscription of `Terminal::subscribe_orders` and `Terminal::subscribe_trades`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscribeOutcome {
+    /// The instrument is added to the list of the received instruments.
+    Subscribed,
+    /// The instrument is already in the list, the library Trans2QUIK.dll is not called.
+    AlreadySubscribed,
+}
+
+/// The orders and trades of the instrument received since its subscription,
+/// see `Terminal::instrument_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InstrumentStats {
+    /// The orders received by the order_status_callback, the initial orders included.
+    pub orders: u64,
+    /// The trades received by the trade_status_callback, the initial trades included.
+    pub trades: u64,
+    /// The time of receiving the last order or trade.
+    pub last_event: Option<Instant>,
+}
+
+/// What `Terminal::send_async_transaction` does if the transaction_reply_callback is not set
+/// with `Terminal::set_transactions_reply_callback`, so the reply of the transaction is lost,
+/// see `Terminal::set_missing_reply_callback`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingReplyCallback {
+    /// The transaction is sent, the warning is logged.
+    #[default]
+    Warn,
+    /// The transaction is not sent, `Trans2QuikError::ReplyCallbackNotSet` is returned.
+    Error,
+    /// The transaction is sent, the replies are not needed.
+    FireAndForget,
+}
+
+/// The successful connection of `Terminal::ensure_connected`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectOutcome {
+    /// The library Trans2QUIK.dll connected to the QUIK terminal.
+    Connected,
+    /// The library Trans2QUIK.dll was already connected, `Trans2QuikResult::AlreadyConnectedToQuik`.
+    AlreadyConnected,
+}
+
+impl ConnectOutcome {
+    /// The outcome of the result of TRANS2QUIK_CONNECT, `None` if the connection failed.
+    pub fn from_result(trans2quik_result: &Trans2QuikResult) -> Option<Self> {
+        match trans2quik_result {
+            Trans2QuikResult::Success => Some(ConnectOutcome::Connected),
+            Trans2QuikResult::AlreadyConnectedToQuik => Some(ConnectOutcome::AlreadyConnected),
+            _ => None,
+        }
+    }
+}
+
+/// The failed subscription of `Terminal::subscribe_orders` and `Terminal::subscribe_trades`.
+///
+/// The library Trans2QUIK.dll can't check the class and the instrument before subscribing,
+/// it returns `Trans2QuikResult::Failed` for the class or the instrument unknown to the terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscribeError {
+    /// The class or the instrument is unknown, or the codes are not valid.
+    UnknownInstrument,
+    /// The QUIK terminal is not connected to the server.
+    QuikNotConnected,
+    /// The library Trans2QUIK.dll is not connected to the QUIK terminal.
+    DllNotConnected,
+    /// Any other result.
+    Other(Trans2QuikResult),
+}
+
+impl SubscribeError {
+    /// Interprets the result of the subscription.
+    pub fn check(trans2quik_result: Trans2QuikResult) -> Result<(), SubscribeError> {
+        match trans2quik_result {
+            Trans2QuikResult::Success => Ok(()),
+            Trans2QuikResult::Failed
+            | Trans2QuikResult::WrongSyntax
+            | Trans2QuikResult::WrongInputParams => Err(SubscribeError::UnknownInstrument),
+            Trans2QuikResult::QuikNotConnected => Err(SubscribeError::QuikNotConnected),
+            Trans2QuikResult::DllNotConnected => Err(SubscribeError::DllNotConnected),
+            other => Err(SubscribeError::Other(other)),
+        }
+    }
+}
+
+impl fmt::Display for SubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscribeError::UnknownInstrument => write!(f, "Unknown class or instrument"),
+            SubscribeError::QuikNotConnected => write!(f, "QUIK is not connected to the server"),
+            SubscribeError::DllNotConnected => {
+                write!(f, "Trans2QUIK.dll is not connected to QUIK")
+            }
+            SubscribeError::Other(result) => write!(f, "Subscription failed: {:?}", result),
+        }
+    }
+}
+
+impl error::Error for SubscribeError {}
+
+/// How `Terminal::send_transactions_from_file` sends the transactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SendMode {
+    /// With `Terminal::send_sync_transaction`.
+    Sync,
+    /// With `Terminal::send_async_transaction`.
+    Async,
+}
+
+/**
+The state of the connection, see `Terminal::conn_state`. Transitioned by `Terminal::connect`,
+`Terminal::disconnect`, the connection_status_callback and `Terminal::enable_auto_reconnect`:
+
+- `Disconnected` → `DllConnected` by the successful connection or `ConnectionEvent::DllConnected`;
+- `DllConnected` → `QuikConnected` by `ConnectionEvent::QuikConnected`;
+- `QuikConnected` → `DllConnected` by `ConnectionEvent::QuikDisconnected`;
+- any → `Disconnected` by `ConnectionEvent::DllDisconnected` or `Terminal::disconnect`;
+- `Reconnecting` while the auto-reconnect restores the connection after the disconnection.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnState {
+    /// The library Trans2QUIK.dll is not connected to the QUIK terminal.
+    #[default]
+    Disconnected,
+    /// The library Trans2QUIK.dll is connected to the QUIK terminal, but the QUIK terminal
+    /// is not known to be connected to the server.
+    DllConnected,
+    /// The QUIK terminal is connected to the server.
+    QuikConnected,
+    /// The auto-reconnect is restoring the connection.
+    Reconnecting,
+}
+
+impl ConnState {
+    /// The state after the event of the connection_status_callback.
+    fn after(self, event: &ConnectionEvent) -> ConnState {
+        match event {
+            ConnectionEvent::QuikConnected => ConnState::QuikConnected,
+            ConnectionEvent::QuikDisconnected(_) => ConnState::DllConnected,
+            ConnectionEvent::DllConnected => match self {
+                ConnState::Disconnected | ConnState::Reconnecting => ConnState::DllConnected,
+                state => state,
+            },
+            ConnectionEvent::DllDisconnected(_) => ConnState::Disconnected,
+        }
+    }
+}
+
+/// Sets the state of the connection if it is one of `from`.
+fn transition_conn_state(from: &[ConnState], to: ConnState) {
+    CONN_STATE.rcu(|state| match from.contains(state) {
+        true => Arc::new(to),
+        false => Arc::clone(state),
+    });
+}
+
+/// The event of the connection_status_callback.
+///
+/// The error code and the error message of the callback are meaningful for the disconnections only,
+/// they are `Some((error_code, error_message))` if the library Trans2QUIK.dll reported the error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// The QUIK terminal connected to the server.
+    QuikConnected,
+    /// The QUIK terminal disconnected from the server.
+    QuikDisconnected(Option<(i32, String)>),
+    /// The library Trans2QUIK.dll connected to the QUIK terminal.
+    DllConnected,
+    /// The library Trans2QUIK.dll disconnected from the QUIK terminal.
+    DllDisconnected(Option<(i32, String)>),
+}
+
+impl ConnectionEvent {
+    /// The event from the arguments of the connection_status_callback,
+    /// `None` if `code` is not a connection event.
+    fn from_callback(code: i32, error_code: i32, error_message: String) -> Option<Self> {
+        let error = (error_code != 0).then_some((error_code, error_message));
+
+        match code {
+            8 => Some(ConnectionEvent::QuikConnected),
+            9 => Some(ConnectionEvent::QuikDisconnected(error)),
+            10 => Some(ConnectionEvent::DllConnected),
+            11 => Some(ConnectionEvent::DllDisconnected(error)),
+            _ => None,
         }
     }
 }
 
+/// The event of any callback function, see `Terminal::events`.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Order(OrderInfo),
+    Trade(TradeInfo),
+    TransactionReply(TransactionInfo),
+    Connection(ConnectionEvent),
+}
+
 /// Сomposite error type for calling functions from the library Trans2QUIK.dll.
 #[derive(Debug)]
 pub enum Trans2QuikError {
     LibLoading(LibloadingError),
     NulError(NulError),
+    TransactionBuild(TransactionBuildError),
+    NotSent(Trans2QuikResult),
+    Timeout,
+    UnknownTransId(i32),
+    OrderNotPlaced(i32),
+    WouldBlock,
+    Io(io::Error),
+    /// Another `Terminal` receives the callback functions, see `Terminal::start_trades`.
+    TerminalInUse,
+    /// The order was cancelled before becoming active, e.g. rejected by the exchange.
+    OrderCanceled(u64),
+    Subscribe(SubscribeError),
+    /// The transaction with the same TRANS_ID is waiting for the reply.
+    DuplicateTransId(i32),
+    /// The asynchronous transaction has no positive TRANS_ID to be correlated to its reply.
+    MissingTransId,
+    /// The transaction is rejected, see `Terminal::send_sync`.
+    Rejected(Box<TransactionInfo>),
+    /// The price parameter and its value use the dot instead of the comma as the decimal
+    /// separator, see `Terminal::set_check_decimal_separator`.
+    WrongDecimalSeparator(&'static str, String),
+    /// The connection to the QUIK terminal failed, see `Terminal::ensure_connected`.
+    NotConnected(Trans2QuikResult),
+    /// The reply of the asynchronous transaction would be lost,
+    /// see `Terminal::set_missing_reply_callback`.
+    ReplyCallbackNotSet,
+    /// The getter returned the string that can't be decoded, see `TransactionReplyAccess::string`.
+    Decode(String, String),
+    /// The name is not the reply getter of the type, see `TransactionReplyAccess`.
+    UnknownGetter(String),
+    /// The synchronous transaction is not sent while this number of the asynchronous
+    /// transactions is waiting for the replies, see `Terminal::ping`.
+    PendingTransactions(usize),
 }
 
 impl fmt::Display for Trans2QuikError {
@@ -256,6 +904,74 @@ impl fmt::Display for Trans2QuikError {
         match self {
             Trans2QuikError::LibLoading(err) => write!(f, "Library loading error: {}", err),
             Trans2QuikError::NulError(err) => write!(f, "Nul error: {}", err),
+            Trans2QuikError::TransactionBuild(err) => {
+                write!(f, "Transaction build error: {}", err)
+            }
+            Trans2QuikError::NotSent(result) => write!(f, "Transaction not sent: {:?}", result),
+            Trans2QuikError::Timeout => write!(f, "Timeout"),
+            Trans2QuikError::UnknownTransId(trans_id) => {
+                write!(f, "No transaction was sent with TRANS_ID={}", trans_id)
+            }
+            Trans2QuikError::OrderNotPlaced(trans_id) => {
+                write!(
+                    f,
+                    "No order was placed by the transaction TRANS_ID={}",
+                    trans_id
+                )
+            }
+            Trans2QuikError::WouldBlock => write!(f, "Transaction rate limit exceeded"),
+            Trans2QuikError::Io(err) => write!(f, "IO error: {}", err),
+            Trans2QuikError::TerminalInUse => {
+                write!(f, "Another Terminal is already receiving the callbacks")
+            }
+            Trans2QuikError::OrderCanceled(order_num) => {
+                write!(f, "Order {} was canceled before becoming active", order_num)
+            }
+            Trans2QuikError::Subscribe(err) => write!(f, "Subscribe error: {}", err),
+            Trans2QuikError::DuplicateTransId(trans_id) => {
+                write!(
+                    f,
+                    "The transaction TRANS_ID={} is already waiting for the reply",
+                    trans_id
+                )
+            }
+            Trans2QuikError::MissingTransId => {
+                write!(f, "The asynchronous transaction has no TRANS_ID")
+            }
+            Trans2QuikError::WrongDecimalSeparator(param, value) => {
+                write!(
+                    f,
+                    "{}={} uses the dot as the decimal separator, use {}={}",
+                    param,
+                    value,
+                    param,
+                    value.replace('.', ",")
+                )
+            }
+            Trans2QuikError::NotConnected(result) => {
+                write!(f, "Not connected to the QUIK terminal: {:?}", result)
+            }
+            Trans2QuikError::ReplyCallbackNotSet => {
+                write!(f, "The transaction_reply_callback is not set")
+            }
+            Trans2QuikError::Decode(getter, err) => {
+                write!(f, "{} returned an invalid string: {}", getter, err)
+            }
+            Trans2QuikError::UnknownGetter(getter) => {
+                write!(f, "{} is not the reply getter of this type", getter)
+            }
+            Trans2QuikError::PendingTransactions(count) => {
+                write!(f, "{} asynchronous transactions are pending", count)
+            }
+            Trans2QuikError::Rejected(transaction_info) => {
+                write!(
+                    f,
+                    "The transaction {:?} is rejected: {:?}, {}",
+                    transaction_info.trans_id,
+                    transaction_info.reply_code,
+                    transaction_info.reply_message
+                )
+            }
         }
     }
 }
@@ -274,7 +990,30 @@ impl From<NulError> for Trans2QuikError {
     }
 }
 
-#[derive(Debug)]
+impl From<io::Error> for Trans2QuikError {
+    fn from(err: io::Error) -> Trans2QuikError {
+        Trans2QuikError::Io(err)
+    }
+}
+
+impl From<TransactionBuildError> for Trans2QuikError {
+    fn from(err: TransactionBuildError) -> Trans2QuikError {
+        Trans2QuikError::TransactionBuild(err)
+    }
+}
+
+impl From<SubscribeError> for Trans2QuikError {
+    fn from(err: SubscribeError) -> Trans2QuikError {
+        Trans2QuikError::Subscribe(err)
+    }
+}
+
+/// The order received in the order_status_callback.
+///
+/// The default with the empty codes and the default enums, e.g. `Mode::Unknown`,
+/// allows to construct the orders in the tests: `OrderInfo { order_num: 1, ..Default::default() }`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 #[allow(dead_code)]
 pub struct OrderInfo {
     pub mode: Mode,
@@ -282,22 +1021,59 @@ pub struct OrderInfo {
     pub order_num: u64,
     pub class_code: String,
     pub sec_code: String,
+    /// The price and the `value` are in the currency of the price of the instrument,
+    /// the library Trans2QUIK.dll doesn't provide it for the orders,
+    /// see `TradeInfo::price_currency` of the trades of the order.
     pub price: f64,
+    /// Quantity of the order in lots.
+    ///
+    /// The quantities are whole numbers of lots: the library Trans2QUIK.dll passes the balance
+    /// and returns TRANS2QUIK_ORDER_QTY as `__int64`, not as double. The fractional amounts,
+    /// e.g. of the currencies, are the lots of the fractional size, not the fractional lots.
+    pub qty: i64,
+    /// Quantity of the order not filled yet, in lots like `qty`.
     pub balance: i64,
     pub value: f64,
     pub is_sell: IsSell,
     pub status: Status,
-    pub date: NaiveDate,
-    pub time: NaiveTime,
+    /// `None` if the library Trans2QUIK.dll returned no date.
+    pub date: Option<NaiveDate>,
+    /// `None` if the library Trans2QUIK.dll returned no time.
+    pub time: Option<NaiveTime>,
+    /// Only the order with `ExecCondition::PutInQueue` is expected to rest in the order book
+    /// after the placement.
+    pub exec_condition: ExecCondition,
+    /// The number of the order status event, increasing by 1 from 1 with every event,
+    /// a gap means a missed event.
+    pub seq: u64,
 }
 
 impl OrderInfo {
-    pub fn is_valid(&self) -> bool {
-        self.date != NaiveDate::default() && self.time != NaiveTime::default()
+    /// The state of the order: `(order_num, status, balance)`, equal for the same state
+    /// delivered twice, see `OrderStateFilter`.
+    pub fn state_key(&self) -> (u64, Status, i64) {
+        (self.order_num, self.status.clone(), self.balance)
+    }
+
+    /// Quantity of the order filled, `qty - balance`.
+    pub fn filled(&self) -> i64 {
+        self.qty - self.balance
+    }
+
+    /// Part of the quantity of the order filled from `0.0` to `1.0`, `0.0` if `qty` is `0`.
+    pub fn fill_ratio(&self) -> f64 {
+        if self.qty == 0 {
+            return 0.0;
+        }
+
+        self.filled() as f64 / self.qty as f64
     }
 }
 
-#[derive(Debug)]
+/// The trade received in the trade_status_callback, the default allows to construct
+/// the trades in the tests, see `OrderInfo`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 #[allow(dead_code)]
 pub struct TradeInfo {
     pub mode: Mode,
@@ -306,49 +1082,366 @@ pub struct TradeInfo {
     pub class_code: String,
     pub sec_code: String,
     pub price: f64,
+    /// Quantity of the trade in lots. The library Trans2QUIK.dll reports the quantity
+    /// without the sign for both sides, the side is `is_sell`, see `TradeInfo::signed_quantity`.
     pub quantity: i64,
     pub is_sell: IsSell,
+    /// Value of the trade in the currency of the price as reported by QUIK. For the bonds it is
+    /// computed from the price in percent of the face value, and, depending on the build of QUIK,
+    /// includes the accrued interest. It accounts for the lot size, unlike `computed_value`.
     pub value: f64,
-    pub date: NaiveDate,
-    pub time: NaiveTime,
+    /// `None` if the library Trans2QUIK.dll returned no date.
+    pub date: Option<NaiveDate>,
+    /// `None` if the library Trans2QUIK.dll returned no time.
+    pub time: Option<NaiveTime>,
+    /// Settlement code of the trade, for example `T0`, `Y0` or `B01`.
+    pub settle_code: String,
+    /// Settlement time of the trade for the instruments with the intraday settlement, `None`
+    /// if there is no time or the library Trans2QUIK.dll doesn't export TRANS2QUIK_TRADE_SETTLE_TIME.
+    pub settle_time: Option<NaiveTime>,
+    /// Spot, repo or margin trade, see `TradeKind::classify`.
+    pub trade_kind: TradeKind,
+    /// Identifier of the trader who made the trade, empty if the library doesn't return it.
+    pub user_id: String,
+    /// Identifier of the firm of the trade, e.g. of the clearing member,
+    /// empty if the library doesn't return it.
+    pub firm_id: String,
+    /// Currency of the `price` and the `value`, e.g. `SUR` or `USD`,
+    /// empty if the library doesn't return it.
+    pub price_currency: String,
+    /// Currency of the settlement of the trade, differs from `price_currency`
+    /// for the cross-currency instruments, empty if the library doesn't return it.
+    pub settle_currency: String,
+    /// Identifier of the user of the QUIK server who made the trade, `0` if unknown.
+    pub uid: u64,
+    /// The number of the trade event, increasing by 1 from 1 with every event,
+    /// a gap means a missed event.
+    pub seq: u64,
 }
 
 impl TradeInfo {
-    pub fn is_valid(&self) -> bool {
-        self.date != NaiveDate::default() && self.time != NaiveTime::default()
+    /// `price * quantity`, differs from `value` by the lot size, the accrued interest
+    /// and the face value of the bonds.
+    pub fn computed_value(&self) -> f64 {
+        self.price * self.quantity as f64
+    }
+
+    /// The quantity with the sign of the side: positive for the buy, negative for the sell.
+    /// The sign of `quantity` is ignored, so the sign is not applied twice.
+    /// `i64::MIN` saturates to `i64::MAX`.
+    pub fn signed_quantity(&self) -> i64 {
+        match self.is_sell {
+            IsSell::Buy => self.quantity.saturating_abs(),
+            IsSell::Sell => -self.quantity.saturating_abs(),
+        }
+    }
+
+    /// `value` and `computed_value` differ by more than `VALUE_TOLERANCE`.
+    fn value_diverges(&self) -> bool {
+        let computed_value = self.computed_value();
+        (self.value - computed_value).abs() > VALUE_TOLERANCE * computed_value.abs().max(1.0)
     }
 }
 
-#[derive(Debug)]
+/// The relative difference of `TradeInfo::value` and `TradeInfo::computed_value` logged as a warning.
+const VALUE_TOLERANCE: f64 = 1e-6;
+
+/// The transaction reply received in the transaction_reply_callback.
+///
+/// The reply has three codes with different meanings:
+/// - `trans2quik_result` is the result of the delivery of the transaction by the library Trans2QUIK.dll,
+/// - `error_code` is the extended error code of the library, mostly the system error code of Windows,
+/// - `reply_code` is the status of the transaction reported by the QUIK server.
+///
+/// The default allows to construct the replies in the tests, see `OrderInfo`.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
 #[allow(dead_code)]
 pub struct TransactionInfo {
     pub trans2quik_result: Trans2QuikResult,
-    pub error_code: i32,
-    pub reply_code: i32,
+    pub error_code: ErrorCode,
+    pub reply_code: ReplyCode,
     pub trans_id: TransId,
     pub order_num: u64,
     pub reply_message: String,
     pub sec_code: String,
     pub price: f64,
+    /// The firm, the client code and the trading account of the transaction,
+    /// empty for the synchronous transactions.
+    pub firm_id: String,
+    pub client_code: String,
+    pub account: String,
+    /// The reason recognized in the `reply_message` if the transaction is rejected.
+    pub rejection_reason: Option<RejectionReason>,
+    /// The number of the transaction reply, increasing by 1 from 1 with every reply,
+    /// a gap means a missed reply.
+    pub seq: u64,
+}
+
+impl TransactionInfo {
+    /// The transaction is delivered and executed.
+    pub fn is_executed(&self) -> bool {
+        self.trans2quik_result == Trans2QuikResult::Success
+            && self.reply_code == ReplyCode::Executed
+    }
+}
+
+/// The type of the value returned by the reply getter, see `REPLY_GETTERS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReplyGetterType {
+    String,
+    Double,
+    Integer,
+}
+
+/// The reply getters of the library Trans2QUIK.dll readable by `TransactionReplyAccess`.
+const REPLY_GETTERS: &[(&str, ReplyGetterType)] = &[
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_CLASS_CODE",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_PRICE",
+        ReplyGetterType::Double,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_QUANTITY",
+        ReplyGetterType::Integer,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_BALANCE",
+        ReplyGetterType::Integer,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_FIRMID",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_ACCOUNT",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_CLIENT_CODE",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_BROKERREF",
+        ReplyGetterType::String,
+    ),
+    (
+        "TRANS2QUIK_TRANSACTION_REPLY_EXCHANGE_CODE",
+        ReplyGetterType::String,
+    ),
+];
+
+/**
+Access to the transaction reply descriptor during the transaction_reply_callback.
+
+Transaction reply descriptor exposes more than the code of the instrument and the price.
+The values are read by the name of the getter exported by the library Trans2QUIK.dll.
+Only the known reply getters are called, with their return types:
+* `TRANS2QUIK_TRANSACTION_REPLY_CLASS_CODE` - class code, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE` - instrument code, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_PRICE` - order price, `double`.
+* `TRANS2QUIK_TRANSACTION_REPLY_QUANTITY` - order quantity, 64-bit `integer`.
+* `TRANS2QUIK_TRANSACTION_REPLY_BALANCE` - order balance, 64-bit `integer`.
+* `TRANS2QUIK_TRANSACTION_REPLY_FIRMID` - firm id, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_ACCOUNT` - trading account, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_CLIENT_CODE` - client code, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_BROKERREF` - comment of the order, `string`.
+* `TRANS2QUIK_TRANSACTION_REPLY_EXCHANGE_CODE` - exchange order number, `string`.
+
+Any other name, or the getter read as the other type, is `Trans2QuikError::UnknownGetter`.
+The getters already loaded with the library, e.g. `TRANS2QUIK_TRANSACTION_REPLY_FIRMID`,
+are called as is, the others are looked up in the library on each call.
+
+The descriptor is valid only until the callback function returns, so the access
+must not outlive the reply handler.
+*/
+pub struct TransactionReplyAccess<'a> {
+    descriptor: ReplyDescriptor<'a>,
+}
+
+impl<'a> TransactionReplyAccess<'a> {
+    fn new(descriptor: ReplyDescriptor<'a>) -> Self {
+        TransactionReplyAccess { descriptor }
+    }
+
+    /// The descriptor with the getters of the code of the instrument and the price.
+    pub fn descriptor(&self) -> &ReplyDescriptor<'a> {
+        &self.descriptor
+    }
+
+    /// Calls a reply getter returning a string, e.g. `TRANS2QUIK_TRANSACTION_REPLY_FIRMID`.
+    /// Returns `Trans2QuikError::Decode` if the string is null or can't be decoded.
+    pub fn string(&self, getter: &str) -> Result<String, Trans2QuikError> {
+        let getters = self.descriptor.getters;
+        let function = match getter {
+            "TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE" => {
+                getters.trans2quik_transaction_reply_sec_code
+            }
+            "TRANS2QUIK_TRANSACTION_REPLY_FIRMID" => getters.trans2quik_transaction_reply_firm_id,
+            "TRANS2QUIK_TRANSACTION_REPLY_CLIENT_CODE" => {
+                getters.trans2quik_transaction_reply_client_code
+            }
+            "TRANS2QUIK_TRANSACTION_REPLY_ACCOUNT" => getters.trans2quik_transaction_reply_account,
+            _ => self.getter::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                getter,
+                ReplyGetterType::String,
+            )?,
+        };
+        let value = unsafe { function(self.descriptor.raw) };
+
+        decode_lpstr(value).map_err(|e| {
+            error!("decode {} error: {:?}", getter, e);
+            Trans2QuikError::Decode(getter.to_string(), e.to_string())
+        })
+    }
+
+    /// Calls a reply getter returning a double, e.g. `TRANS2QUIK_TRANSACTION_REPLY_PRICE`.
+    pub fn double(&self, getter: &str) -> Result<f64, Trans2QuikError> {
+        let function = match getter {
+            "TRANS2QUIK_TRANSACTION_REPLY_PRICE" => {
+                self.descriptor.getters.trans2quik_transaction_reply_price
+            }
+            _ => self.getter::<unsafe extern "C" fn(intptr_t) -> c_double>(
+                getter,
+                ReplyGetterType::Double,
+            )?,
+        };
+        Ok(unsafe { function(self.descriptor.raw) })
+    }
+
+    /// Calls a reply getter returning a 64-bit integer,
+    /// e.g. `TRANS2QUIK_TRANSACTION_REPLY_QUANTITY`.
+    pub fn integer(&self, getter: &str) -> Result<i64, Trans2QuikError> {
+        let function =
+            self.getter::<unsafe extern "C" fn(intptr_t) -> i64>(getter, ReplyGetterType::Integer)?;
+        Ok(unsafe { function(self.descriptor.raw) })
+    }
+
+    /// Loads the getter from the library if it is the known reply getter of the `getter_type`.
+    fn getter<T: Copy>(
+        &self,
+        getter: &str,
+        getter_type: ReplyGetterType,
+    ) -> Result<T, Trans2QuikError> {
+        if !REPLY_GETTERS.contains(&(getter, getter_type)) {
+            error!("{} is not the reply getter of {:?}", getter, getter_type);
+            return Err(Trans2QuikError::UnknownGetter(getter.to_string()));
+        }
+
+        let name = CString::new(getter)?;
+        Ok(load_symbol::<T>(
+            self.descriptor.library,
+            name.as_bytes_with_nul(),
+        )?)
+    }
+}
+
+/**
+The transaction reply descriptor passed to the transaction_reply_callback.
+
+The descriptor is valid only until the callback function returns. It is borrowed for
+the duration of the callback and is read only by the getters, so it can't be stored
+and read after the library Trans2QUIK.dll has released it.
+*/
+pub struct ReplyDescriptor<'a> {
+    getters: &'a DescriptorGetters,
+    library: &'a Library,
+    raw: intptr_t,
+}
+
+impl<'a> ReplyDescriptor<'a> {
+    /// # Safety
+    /// `raw` is the descriptor passed to the running transaction_reply_callback.
+    unsafe fn new(getters: &'a DescriptorGetters, library: &'a Library, raw: intptr_t) -> Self {
+        ReplyDescriptor {
+            getters,
+            library,
+            raw,
+        }
+    }
+
+    /// The code of the instrument for which the transaction was made.
+    pub fn sec_code(&self) -> String {
+        let sec_code = unsafe { (self.getters.trans2quik_transaction_reply_sec_code)(self.raw) };
+        decode_field(sec_code, "sec_code")
+    }
+
+    /// The price of the transaction.
+    pub fn price(&self) -> f64 {
+        unsafe { (self.getters.trans2quik_transaction_reply_price)(self.raw) }
+    }
+
+    /// The identifier of the firm.
+    pub fn firm_id(&self) -> String {
+        let firm_id = unsafe { (self.getters.trans2quik_transaction_reply_firm_id)(self.raw) };
+        decode_field(firm_id, "firm_id")
+    }
+
+    /// The client code.
+    pub fn client_code(&self) -> String {
+        let client_code =
+            unsafe { (self.getters.trans2quik_transaction_reply_client_code)(self.raw) };
+        decode_field(client_code, "client_code")
+    }
+
+    /// The trading account.
+    pub fn account(&self) -> String {
+        let account = unsafe { (self.getters.trans2quik_transaction_reply_account)(self.raw) };
+        decode_field(account, "account")
+    }
+}
+
+/// The order sent by an asynchronous transaction, the order number is known after the transaction reply.
+#[derive(Debug)]
+struct SentOrder {
+    class_code: String,
+    sec_code: String,
+    order_num: Option<u64>,
+    seq: u64,
 }
 
 #[derive(Debug)]
 enum DecodeLpstrError {
     NullPointer,
-    DecodeError,
     InvalidString(NulError),
 }
 
 impl fmt::Display for DecodeLpstrError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            DecodeLpstrError::NullPointer => write!(f, "{:?}", self),
-            DecodeLpstrError::DecodeError => write!(f, "{:?}", self),
-            DecodeLpstrError::InvalidString(err) => write!(f, "NulError: {}", err),
+            DecodeLpstrError::NullPointer => write!(f, "Null pointer instead of the string"),
+            DecodeLpstrError::InvalidString(err) => write!(
+                f,
+                "Nul byte at position {} of the string b\"{}\"",
+                err.nul_position(),
+                escape_bytes(&err.clone().into_vec())
+            ),
         }
     }
 }
 
+/// The number of the bytes shown in the messages of the decoding errors.
+const ESCAPED_BYTES_LIMIT: usize = 64;
+
+/// Escapes the bytes of the string failed to decode for the messages,
+/// e.g. `\xd1\xc1ER`, truncated to `ESCAPED_BYTES_LIMIT` bytes.
+fn escape_bytes(bytes: &[u8]) -> String {
+    let escaped = bytes[..bytes.len().min(ESCAPED_BYTES_LIMIT)].escape_ascii();
+
+    if bytes.len() > ESCAPED_BYTES_LIMIT {
+        format!("{}... ({} bytes)", escaped, bytes.len())
+    } else {
+        escaped.to_string()
+    }
+}
+
 impl error::Error for DecodeLpstrError {}
 
 impl From<NulError> for DecodeLpstrError {
@@ -388,6 +1481,9 @@ The `Terminal` structure is used to interact with the QUIK trading terminal thro
 This structure provides loading of the DLL library Trans2QUIK.dll, establishing a connection to the QUIK terminal
 and calling functions from the library to control the terminal and perform trading operations.
 
+The library Trans2QUIK.dll calls the callback functions for the whole process and the channels of the events
+are global, so one `Terminal` (and its clones) is supported per process, see `Terminal::start_trades`.
+
 # Example of use
 ```
 pub async fn trade(
@@ -410,7 +1506,7 @@ pub async fn trade(
         terminal_guard.subscribe_orders(class_code, sec_code)?;
         terminal_guard.subscribe_trades(class_code, sec_code)?;
         terminal_guard.start_orders();
-        terminal_guard.start_trades();
+        terminal_guard.start_trades()?;
     }
 
     let (transaction_sender, mut transaction_receiver): (
@@ -418,30 +1514,21 @@ pub async fn trade(
         UnboundedReceiver<TransactionInfo>,
     ) = mpsc::unbounded_channel();
 
-    {
-        let mut transaction_reply_sender = TRANSACTION_REPLY_SENDER.lock().unwrap();
-        *transaction_reply_sender = Some(transaction_sender);
-    }
+    TRANSACTION_REPLY_SENDER.store(Some(Arc::new(transaction_sender.into())));
 
     let (order_sender, mut order_receiver): (
         UnboundedSender<OrderInfo>,
         UnboundedReceiver<OrderInfo>,
     ) = mpsc::unbounded_channel();
 
-    {
-        let mut order_status_sender = ORDER_STATUS_SENDER.lock().unwrap();
-        *order_status_sender = Some(order_sender);
-    }
+    ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
 
     let (trade_sender, mut trade_receiver): (
         UnboundedSender<TradeInfo>,
         UnboundedReceiver<TradeInfo>,
     ) = mpsc::unbounded_channel();
 
-    {
-        let mut trade_status_sender = TRADE_STATUS_SENDER.lock().unwrap();
-        *trade_status_sender = Some(trade_sender);
-    }
+    TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.into())));
 
     loop {
         tokio::select! {
@@ -472,18 +1559,14 @@ pub async fn trade(
             },
             Some(order_info) = order_receiver.recv() => {
                 info!("order_status_callback received: {:?}", order_info);
-                if order_info.is_valid() {
+                if let (Some(date), Some(time)) = (order_info.date, order_info.time) {
                     // Do something with order info
-                } else {
-                    error!("order_info invalid");
                 }
             },
             Some(trade_info) = trade_receiver.recv() => {
                 info!("trade_status_callback received: {:?}", trade_info);
-                if trade_info.is_valid() {
+                if let (Some(date), Some(time)) = (trade_info.date, trade_info.time) {
                     // Do something with trade info
-                } else {
-                    error!("trade_info invalid");
                 }
             },
             result = async {
@@ -524,13 +1607,56 @@ pub async fn trade(
 */
 pub struct Terminal {
     path_to_quik: String,
+    /// Shared by the clones of the terminal.
+    instance_id: u64,
 
-    /// Loading a dynamic library Trans2QUIK.dll, which provides an API for interacting with QUIK.
-    library: Arc<Library>,
+    /// The account and client code added to the transactions built by `Terminal::transaction`.
+    default_account: Option<(String, String)>,
 
-    /// Calling a function from the library Trans2QUIK.dll for establishing communication with the QUIK terminal.
-    trans2quik_connect:
-        unsafe extern "C" fn(*mut c_char, *mut c_long, *mut c_char, c_long) -> c_long,
+    /// The directory of the library Trans2QUIK.dll set as the current directory while connecting,
+    /// see `TerminalOptions::set_current_dir`.
+    library_dir: Option<PathBuf>,
+
+    /// The names of the functions loaded from the library Trans2QUIK.dll.
+    loaded_symbols: Vec<&'static str>,
+
+    /// The limit of the transactions per second, shared by the clones of the terminal.
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+
+    /// The keys of the transactions sent by `send_idempotent`, shared by the clones of the terminal.
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+
+    /// The prices of the sent transactions are checked for the dot,
+    /// shared by the clones of the terminal.
+    check_decimal_separator: Arc<AtomicBool>,
+
+    /// The asynchronous transactions sent without the transaction_reply_callback,
+    /// shared by the clones of the terminal.
+    missing_reply_callback: Arc<Mutex<MissingReplyCallback>>,
+
+    /// The callback functions set by the terminal, shared by the clones of the terminal.
+    callback_state: Arc<Mutex<CallbackState>>,
+
+    /// The metadata of the instruments used by the transaction builders,
+    /// shared by the clones of the terminal.
+    instrument_info: Arc<Mutex<Option<Arc<InstrumentInfoCache>>>>,
+
+    /// The instruments subscribed to by the terminal, shared by the clones of the terminal.
+    subscriptions: Arc<Mutex<Subscriptions>>,
+
+    /// The error of the last failed call, see `Terminal::last_error`.
+    last_error: Arc<Mutex<Option<(i32, String)>>>,
+
+    /// The auto-reconnect started by `Terminal::enable_auto_reconnect`,
+    /// shared by the clones of the terminal.
+    reconnect: Arc<Mutex<Option<ReconnectHandle>>>,
+
+    /// Loading a dynamic library Trans2QUIK.dll, which provides an API for interacting with QUIK.
+    library: Arc<Library>,
+
+    /// Calling a function from the library Trans2QUIK.dll for establishing communication with the QUIK terminal.
+    trans2quik_connect:
+        unsafe extern "C" fn(*mut c_char, *mut c_long, *mut c_char, c_long) -> c_long,
 
     /// Calling a function from the library Trans2QUIK.dll to disconnecting from the QUIK terminal.
     trans2quik_disconnect: unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long,
@@ -614,6 +1740,21 @@ pub struct Terminal {
     trans2quik_transaction_reply_price:
         unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> c_double,
 
+    /// Special function for the callback function transaction_reply_callback
+    /// returns the identifier of the firm.
+    trans2quik_transaction_reply_firm_id:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+
+    /// Special function for the callback function transaction_reply_callback
+    /// returns the client code.
+    trans2quik_transaction_reply_client_code:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+
+    /// Special function for the callback function transaction_reply_callback
+    /// returns the trading account.
+    trans2quik_transaction_reply_account:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+
     /// Special function for the callback function order_status_callback
     /// returns the date of the trade in the format: yyyymmdd
     trans2quik_order_date: unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long,
@@ -622,6 +1763,14 @@ pub struct Terminal {
     /// returns the time of the trade in the format: hhmmss
     trans2quik_order_time: unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long,
 
+    /// Special function for the callback function order_status_callback
+    /// returns the quantity of the order
+    trans2quik_order_qty: unsafe extern "C" fn(order_descriptor: intptr_t) -> i64,
+
+    /// Special function for the callback function order_status_callback
+    /// returns the execution condition of the order, not exported by all the versions of the library.
+    trans2quik_order_exec_type: Option<unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long>,
+
     /// Special function for the callback function trade_status_callback
     /// returns the date of the trade in the format: yyyymmdd
     trans2quik_trade_date: unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long,
@@ -629,12 +1778,67 @@ pub struct Terminal {
     /// Special fucntion for the callback function trade_status_callback
     /// returns the time of the trade in the format: hhmmss
     trans2quik_trade_time: unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the settlement code of the trade
+    trans2quik_trade_settle_code: unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the settlement time of the trade in the format: hhmmss,
+    /// not exported by all the versions of the library.
+    trans2quik_trade_settle_time:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns `1` for the margin trade, not exported by all the versions of the library.
+    trans2quik_trade_is_marginal:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the identifier of the trader, not exported by all the versions of the library.
+    trans2quik_trade_userid:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the identifier of the firm, not exported by all the versions of the library.
+    trans2quik_trade_firm_id:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the identifier of the user of the QUIK server,
+    /// not exported by all the versions of the library.
+    trans2quik_trade_uid: Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the currency of the price of the trade,
+    /// not exported by all the versions of the library.
+    trans2quik_trade_currency:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+
+    /// Special function for the callback function trade_status_callback
+    /// returns the settlement currency of the trade,
+    /// not exported by all the versions of the library.
+    trans2quik_trade_settle_currency:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
 }
 
 impl Clone for Terminal {
     fn clone(&self) -> Self {
         Terminal {
             path_to_quik: self.path_to_quik.clone(),
+            instance_id: self.instance_id,
+            default_account: self.default_account.clone(),
+            library_dir: self.library_dir.clone(),
+            loaded_symbols: self.loaded_symbols.clone(),
+            rate_limiter: Arc::clone(&self.rate_limiter),
+            idempotency_keys: Arc::clone(&self.idempotency_keys),
+            check_decimal_separator: Arc::clone(&self.check_decimal_separator),
+            missing_reply_callback: Arc::clone(&self.missing_reply_callback),
+            callback_state: Arc::clone(&self.callback_state),
+            instrument_info: Arc::clone(&self.instrument_info),
+            subscriptions: Arc::clone(&self.subscriptions),
+            last_error: Arc::clone(&self.last_error),
+            reconnect: Arc::clone(&self.reconnect),
             library: Arc::clone(&self.library),
             trans2quik_connect: self.trans2quik_connect,
             trans2quik_disconnect: self.trans2quik_disconnect,
@@ -654,174 +1858,425 @@ impl Clone for Terminal {
             trans2quik_unsubscribe_trades: self.trans2quik_unsubscribe_trades,
             trans2quik_transaction_reply_sec_code: self.trans2quik_transaction_reply_sec_code,
             trans2quik_transaction_reply_price: self.trans2quik_transaction_reply_price,
+            trans2quik_transaction_reply_firm_id: self.trans2quik_transaction_reply_firm_id,
+            trans2quik_transaction_reply_client_code: self.trans2quik_transaction_reply_client_code,
+            trans2quik_transaction_reply_account: self.trans2quik_transaction_reply_account,
             trans2quik_order_date: self.trans2quik_order_date,
             trans2quik_order_time: self.trans2quik_order_time,
+            trans2quik_order_qty: self.trans2quik_order_qty,
+            trans2quik_order_exec_type: self.trans2quik_order_exec_type,
             trans2quik_trade_date: self.trans2quik_trade_date,
             trans2quik_trade_time: self.trans2quik_trade_time,
+            trans2quik_trade_settle_code: self.trans2quik_trade_settle_code,
+            trans2quik_trade_settle_time: self.trans2quik_trade_settle_time,
+            trans2quik_trade_is_marginal: self.trans2quik_trade_is_marginal,
+            trans2quik_trade_userid: self.trans2quik_trade_userid,
+            trans2quik_trade_firm_id: self.trans2quik_trade_firm_id,
+            trans2quik_trade_uid: self.trans2quik_trade_uid,
+            trans2quik_trade_currency: self.trans2quik_trade_currency,
+            trans2quik_trade_settle_currency: self.trans2quik_trade_settle_currency,
+        }
+    }
+}
+
+/// Options of loading the library Trans2QUIK.dll.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalOptions {
+    /// Set the current directory of the process to the directory of the library
+    /// while loading the library and connecting, restoring it afterwards.
+    /// Some builds of the library resolve their auxiliary files relative to the current directory.
+    ///
+    /// The current directory is shared by the whole process: the terminals change it one at a time,
+    /// but the other threads resolving relative paths meanwhile see the directory of the library.
+    pub set_current_dir: bool,
+}
+
+/// The callback functions of the library Trans2QUIK.dll set by the `Terminal`,
+/// see `Terminal::callback_state`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallbackState {
+    /// Set by `Terminal::set_connection_status_callback`.
+    pub connection_status: bool,
+    /// Set by `Terminal::set_transactions_reply_callback`.
+    pub transaction_reply: bool,
+    /// Started by `Terminal::start_orders`, stopped by `Terminal::unsubscribe_orders`.
+    pub orders: bool,
+    /// Started by `Terminal::start_trades`, stopped by `Terminal::unsubscribe_trades`.
+    pub trades: bool,
+}
+
+/// Stops the auto-reconnect started by `Terminal::enable_auto_reconnect`.
+/// The clones of the handle stop the same auto-reconnect.
+#[derive(Debug, Clone, Default)]
+pub struct ReconnectHandle {
+    stopped: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl ReconnectHandle {
+    /// Stops the retries of the connection, including the retry in progress.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Resolves when the handle is stopped.
+    async fn stopped(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // Registers the waiter before the check, so the stop in between is not missed.
+        notified.as_mut().enable();
+
+        if !self.is_stopped() {
+            notified.await;
+        }
+    }
+}
+
+/// Calls `attempt` every `interval` until it succeeds, `false` if the handle is stopped before.
+/// The attempts call the blocking functions of the library, so they run on the blocking threads
+/// of the runtime.
+async fn retry_until<F>(handle: &ReconnectHandle, interval: Duration, attempt: F) -> bool
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    let attempt = Arc::new(attempt);
+
+    loop {
+        if handle.is_stopped() {
+            return false;
+        }
+
+        let blocking_attempt = Arc::clone(&attempt);
+        let succeeded = tokio::select! {
+            _ = handle.stopped() => return false,
+            succeeded = tokio::task::spawn_blocking(move || blocking_attempt()) => {
+                succeeded.unwrap_or_else(|e| {
+                    error!("auto-reconnect attempt failed: {}", e);
+                    false
+                })
+            }
+        };
+        if succeeded {
+            return true;
+        }
+
+        tokio::select! {
+            _ = handle.stopped() => return false,
+            _ = tokio::time::sleep(interval) => {}
         }
     }
 }
 
+/// The class and instrument codes of the subscriptions of the `Terminal`.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    orders: HashSet<(String, String)>,
+    trades: HashSet<(String, String)>,
+}
+
 impl Terminal {
-    /// The function is used to load the library Trans2QUIK.dll.
+    /// The function is used to load the library Trans2QUIK.dll and to set the QUIK directory
+    /// used by `Terminal::connect`.
     pub fn new(path_to_lib: &str, path_to_quik: &str) -> Result<Self, Trans2QuikError> {
-        let path_to_quik = path_to_quik.to_string();
+        Terminal::new_with_options(path_to_lib, path_to_quik, TerminalOptions::default())
+    }
+
+    /// The function is used to load the library Trans2QUIK.dll without the QUIK directory,
+    /// e.g. the library copied out of the QUIK directory. Connect with `Terminal::connect_to`.
+    pub fn load(path_to_lib: &str) -> Result<Self, Trans2QuikError> {
+        Terminal::new_with_options(path_to_lib, "", TerminalOptions::default())
+    }
+
+    /// The function is used to load the library Trans2QUIK.dll with the options.
+    pub fn new_with_options(
+        path_to_lib: &str,
+        path_to_quik: &str,
+        options: TerminalOptions,
+    ) -> Result<Self, Trans2QuikError> {
+        let library_dir = match options.set_current_dir {
+            true => Path::new(path_to_lib).parent().map(Path::to_path_buf),
+            false => None,
+        };
+
+        let current_dir_guard = match &library_dir {
+            Some(dir) => Some(CurrentDirGuard::set(dir)?),
+            None => None,
+        };
 
         // Loading a dynamic library Trans2QUIK.dll, which provides an API for interacting with QUIK.
-        let library = unsafe { Library::new(path_to_lib)? };
+        let library = load_library(path_to_lib)?;
+
+        drop(current_dir_guard);
+
+        let mut terminal = Terminal::from_library(Arc::new(library), path_to_quik.to_string())?;
+        terminal.library_dir = library_dir;
+
+        Ok(terminal)
+    }
+
+    /// Creates the terminal from the library Trans2QUIK.dll loaded by the caller,
+    /// e.g. with its own search path or shared with the other wrappers of the library.
+    pub fn from_library(
+        library: Arc<Library>,
+        path_to_quik: String,
+    ) -> Result<Self, Trans2QuikError> {
+        let mut symbols = SymbolLoader::new(&library);
 
         // Calling a function from the library Trans2QUIK.dll for establishing communication with the QUIK terminal.
-        let trans2quik_connect = load_symbol::<
-            unsafe extern "C" fn(*mut c_char, *mut c_long, *mut c_char, c_long) -> c_long,
-        >(&library, b"TRANS2QUIK_CONNECT\0")?;
+        let trans2quik_connect = symbols.load::<unsafe extern "C" fn(
+            *mut c_char,
+            *mut c_long,
+            *mut c_char,
+            c_long,
+        ) -> c_long>("TRANS2QUIK_CONNECT")?;
 
         // Calling a function from the library Trans2QUIK.dll to disconnecting from the QUIK terminal.
-        let trans2quik_disconnect = load_symbol::<
-            unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long,
-        >(&library, b"TRANS2QUIK_DISCONNECT\0")?;
+        let trans2quik_disconnect =
+            symbols.load::<unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long>(
+                "TRANS2QUIK_DISCONNECT",
+            )?;
 
         // Calling a function from the library Trans2QUIK.dll to check for a connection between the QUIK terminal and the server.
-        let trans2quik_is_quik_connected = load_symbol::<
-            unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long,
-        >(&library, b"TRANS2QUIK_IS_QUIK_CONNECTED\0")?;
+        let trans2quik_is_quik_connected =
+            symbols.load::<unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long>(
+                "TRANS2QUIK_IS_QUIK_CONNECTED",
+            )?;
 
         // Calling a function from the library Trans2QUIK.dll to check if there is a connection between the library Trans2QUIK.dll and the QUIK terminal.
-        let trans2quik_is_dll_connected = load_symbol::<
-            unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long,
-        >(&library, b"TRANS2QUIK_IS_DLL_CONNECTED\0")?;
+        let trans2quik_is_dll_connected =
+            symbols.load::<unsafe extern "C" fn(*mut c_long, *mut c_char, c_long) -> c_long>(
+                "TRANS2QUIK_IS_DLL_CONNECTED",
+            )?;
 
         // Sending a transaction synchronously. When sending synchronously, the return from the function occurs
         // only after receiving the result of the transaction, or after disconnecting the
         // QUIK terminal from the server.
         let trans2quik_send_sync_transaction =
-            load_symbol::<
-                unsafe extern "C" fn(
-                    *mut c_char,
-                    *mut c_long,
-                    *mut c_long,
-                    *mut c_double,
-                    *mut c_char,
-                    c_long,
-                    *mut c_long,
-                    *mut c_char,
-                    c_long,
-                ) -> c_long,
-            >(&library, b"TRANS2QUIK_SEND_SYNC_TRANSACTION\0")?;
+            symbols.load::<unsafe extern "C" fn(
+                *mut c_char,
+                *mut c_long,
+                *mut c_long,
+                *mut c_double,
+                *mut c_char,
+                c_long,
+                *mut c_long,
+                *mut c_char,
+                c_long,
+            ) -> c_long>("TRANS2QUIK_SEND_SYNC_TRANSACTION")?;
 
         // Asynchronous transfer of a transaction. When sending an asynchronous transaction, the refund is
         // the function is executed immediately, and the result of the transaction is reported via
         // the corresponding callback function.
-        let trans2quik_send_async_transaction =
-            load_symbol::<
-                unsafe extern "C" fn(*mut c_char, *mut c_long, *mut c_char, c_long) -> c_long,
-            >(&library, b"TRANS2QUIK_SEND_ASYNC_TRANSACTION\0")?;
+        let trans2quik_send_async_transaction = symbols.load::<unsafe extern "C" fn(
+            *mut c_char,
+            *mut c_long,
+            *mut c_char,
+            c_long,
+        ) -> c_long>(
+            "TRANS2QUIK_SEND_ASYNC_TRANSACTION"
+        )?;
 
         // А callback function for processing the received connection information.
         let trans2quik_set_connection_status_callback =
-            load_symbol::<
-                unsafe extern "C" fn(
-                    Trans2QuikConnectionStatusCallback,
-                    *mut c_long,
-                    *mut c_char,
-                    c_long,
-                ) -> c_long,
-            >(&library, b"TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK\0")?;
+            symbols.load::<unsafe extern "C" fn(
+                Trans2QuikConnectionStatusCallback,
+                *mut c_long,
+                *mut c_char,
+                c_long,
+            ) -> c_long>("TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK")?;
 
         // Sets the callback function to receive information about the sent asynchronous transaction.
         let trans2quik_set_transactions_reply_callback =
-            load_symbol::<
-                unsafe extern "C" fn(
-                    Trans2QuikTransactionReplyCallback,
-                    *mut c_long,
-                    *mut c_char,
-                    c_long,
-                ) -> c_long,
-            >(&library, b"TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK\0")?;
+            symbols.load::<unsafe extern "C" fn(
+                Trans2QuikTransactionReplyCallback,
+                *mut c_long,
+                *mut c_char,
+                c_long,
+            ) -> c_long>("TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK")?;
 
         // The function is used to create a list of classes and tools for subscribing to receive
         // applications for them.
-        let trans2quik_subscribe_orders = load_symbol::<
-            unsafe extern "C" fn(*mut c_char, *mut c_char) -> c_long,
-        >(&library, b"TRANS2QUIK_SUBSCRIBE_ORDERS\0")?;
+        let trans2quik_subscribe_orders =
+            symbols.load::<unsafe extern "C" fn(*mut c_char, *mut c_char) -> c_long>(
+                "TRANS2QUIK_SUBSCRIBE_ORDERS",
+            )?;
 
         // The function is used to create a list of classes and tools for subscribing to receive deals on them.
-        let trans2quik_subscribe_trades = load_symbol::<
-            unsafe extern "C" fn(*mut c_char, *mut c_char) -> c_long,
-        >(&library, b"TRANS2QUIK_SUBSCRIBE_TRADES\0")?;
+        let trans2quik_subscribe_trades =
+            symbols.load::<unsafe extern "C" fn(*mut c_char, *mut c_char) -> c_long>(
+                "TRANS2QUIK_SUBSCRIBE_TRADES",
+            )?;
 
         // The function starts the process of receiving requests for classes and tools defined
         // by the TRANS2QUIK_SUBSCRIBE_ORDERS function.
-        let trans2quik_start_orders = load_symbol::<
-            unsafe extern "C" fn(Trans2QuikOrderStatusCallback),
-        >(&library, b"TRANS2QUIK_START_ORDERS\0")?;
+        let trans2quik_start_orders = symbols
+            .load::<unsafe extern "C" fn(Trans2QuikOrderStatusCallback)>(
+                "TRANS2QUIK_START_ORDERS",
+            )?;
 
         // The function starts the process of receiving transactions with the parameters set
         // by the function TRANS2QUIK_SUBSCRIBE_TRADES.
-        let trans2quik_start_trades = load_symbol::<
-            unsafe extern "C" fn(Trans2QuikTradeStatusCallback),
-        >(&library, b"TRANS2QUIK_START_TRADES\0")?;
+        let trans2quik_start_trades = symbols
+            .load::<unsafe extern "C" fn(Trans2QuikTradeStatusCallback)>(
+                "TRANS2QUIK_START_TRADES",
+            )?;
 
         // The function interrupts the operation of the TRANS2QUIK_START_ORDERS function and clears
         // the list of received tools generated by the function
         // TRANS2QUIK_SUBSCRIBE_ORDERS.
-        let trans2quik_unsubscribe_orders = load_symbol::<unsafe extern "C" fn() -> c_long>(
-            &library,
-            b"TRANS2QUIK_UNSUBSCRIBE_ORDERS\0",
-        )?;
+        let trans2quik_unsubscribe_orders =
+            symbols.load::<unsafe extern "C" fn() -> c_long>("TRANS2QUIK_UNSUBSCRIBE_ORDERS")?;
 
         // The function interrupts the operation of the TRANS2QUIK_START_TRADES function and clears
         // the list of received tools generated by the function
         // TRANS2QUIK_SUBSCRIBE_TRADES.
-        let trans2quik_unsubscribe_trades = load_symbol::<unsafe extern "C" fn() -> c_long>(
-            &library,
-            b"TRANS2QUIK_UNSUBSCRIBE_TRADES\0",
-        )?;
+        let trans2quik_unsubscribe_trades =
+            symbols.load::<unsafe extern "C" fn() -> c_long>("TRANS2QUIK_UNSUBSCRIBE_TRADES")?;
 
         // Special function for the callback function transaction_reply_callback
         // Returns the code of the instrument for which the transaction was made
         let trans2quik_transaction_reply_sec_code =
-            load_symbol::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
-                &library,
-                b"TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE\0",
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE",
             )?;
 
         // Special function for the callback function transaction_reply_callback
         // returns transaction price
-        let trans2quik_transaction_reply_price = load_symbol::<
-            unsafe extern "C" fn(intptr_t) -> c_double,
-        >(&library, b"TRANS2QUIK_ORDER_DATE\0")?;
+        let trans2quik_transaction_reply_price =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> c_double>(
+                "TRANS2QUIK_TRANSACTION_REPLY_PRICE",
+            )?;
+
+        // Special function for the callback function transaction_reply_callback
+        // returns the identifier of the firm
+        let trans2quik_transaction_reply_firm_id =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRANSACTION_REPLY_FIRMID",
+            )?;
+
+        // Special function for the callback function transaction_reply_callback
+        // returns the client code
+        let trans2quik_transaction_reply_client_code =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRANSACTION_REPLY_CLIENT_CODE",
+            )?;
+
+        // Special function for the callback function transaction_reply_callback
+        // returns the trading account
+        let trans2quik_transaction_reply_account =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRANSACTION_REPLY_ACCOUNT",
+            )?;
 
         // Special function for the callback function order_status_callback
         // returns the date of the trade in the format: yyyymmdd
-        let trans2quik_order_date = load_symbol::<unsafe extern "C" fn(intptr_t) -> c_long>(
-            &library,
-            b"TRANS2QUIK_ORDER_DATE\0",
-        )?;
+        let trans2quik_order_date =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> c_long>("TRANS2QUIK_ORDER_DATE")?;
 
         // Special fucntion for the callback function order_status_callback
         // returns the time of the trade in the format: hhmmss
-        let trans2quik_order_time = load_symbol::<unsafe extern "C" fn(intptr_t) -> c_long>(
-            &library,
-            b"TRANS2QUIK_ORDER_TIME\0",
-        )?;
+        let trans2quik_order_time =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> c_long>("TRANS2QUIK_ORDER_TIME")?;
+
+        // Special function for the callback function order_status_callback
+        // returns the quantity of the order
+        let trans2quik_order_qty =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> i64>("TRANS2QUIK_ORDER_QTY")?;
+
+        // Special function for the callback function order_status_callback
+        // returns the execution condition of the order
+        let trans2quik_order_exec_type = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> c_long>(
+                "TRANS2QUIK_ORDER_EXEC_TYPE",
+            );
 
         // Special function for the callback function trade_status_callback
         // returns the date of the trade in the format: yyyymmdd
-        let trans2quik_trade_date = load_symbol::<unsafe extern "C" fn(intptr_t) -> c_long>(
-            &library,
-            b"TRANS2QUIK_TRADE_DATE\0",
-        )?;
+        let trans2quik_trade_date =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> c_long>("TRANS2QUIK_TRADE_DATE")?;
 
         // Special fucntion for the callback function trade_status_callback
         // returns the time of the trade in the format: hhmmss
-        let trans2quik_trade_time = load_symbol::<unsafe extern "C" fn(intptr_t) -> c_long>(
-            &library,
-            b"TRANS2QUIK_TRADE_TIME\0",
-        )?;
+        let trans2quik_trade_time =
+            symbols.load::<unsafe extern "C" fn(intptr_t) -> c_long>("TRANS2QUIK_TRADE_TIME")?;
+
+        // Special function for the callback function trade_status_callback
+        // returns the settlement code of the trade
+        let trans2quik_trade_settle_code = symbols
+            .load::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRADE_SETTLE_CODE",
+            )?;
+
+        // Special function for the callback function trade_status_callback
+        // returns the settlement time of the trade in the format: hhmmss
+        let trans2quik_trade_settle_time = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> c_long>(
+                "TRANS2QUIK_TRADE_SETTLE_TIME",
+            );
+
+        // Special function for the callback function trade_status_callback
+        // returns 1 for the margin trade
+        let trans2quik_trade_is_marginal = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> c_long>(
+                "TRANS2QUIK_TRADE_IS_MARGINAL",
+            );
+
+        // Special function for the callback function trade_status_callback
+        // returns the identifier of the trader
+        let trans2quik_trade_userid = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRADE_USERID",
+            );
+
+        // Special function for the callback function trade_status_callback
+        // returns the identifier of the firm
+        let trans2quik_trade_firm_id = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRADE_FIRMID",
+            );
+
+        // Special function for the callback function trade_status_callback
+        // returns the identifier of the user of the QUIK server
+        let trans2quik_trade_uid = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> c_long>("TRANS2QUIK_TRADE_UID");
+
+        // Special function for the callback function trade_status_callback
+        // returns the currency of the price of the trade
+        let trans2quik_trade_currency = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRADE_CURRENCY",
+            );
+
+        // Special function for the callback function trade_status_callback
+        // returns the settlement currency of the trade
+        let trans2quik_trade_settle_currency = symbols
+            .load_optional::<unsafe extern "C" fn(intptr_t) -> *mut c_char>(
+                "TRANS2QUIK_TRADE_SETTLE_CURRENCY",
+            );
+
+        let loaded_symbols = symbols.loaded;
 
         Ok(Terminal {
             path_to_quik,
-            library: library.into(),
+            instance_id: next_instance_id(),
+            default_account: None,
+            library_dir: None,
+            loaded_symbols,
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::default())),
+            idempotency_keys: Arc::new(Mutex::new(IdempotencyKeys::default())),
+            check_decimal_separator: Arc::new(AtomicBool::new(true)),
+            missing_reply_callback: Arc::new(Mutex::new(MissingReplyCallback::default())),
+            callback_state: Arc::new(Mutex::new(CallbackState::default())),
+            instrument_info: Arc::new(Mutex::new(None)),
+            subscriptions: Arc::new(Mutex::new(Subscriptions::default())),
+            last_error: Arc::new(Mutex::new(None)),
+            reconnect: Arc::new(Mutex::new(None)),
+            library,
             trans2quik_connect,
             trans2quik_disconnect,
             trans2quik_is_quik_connected,
@@ -838,13 +2293,432 @@ impl Terminal {
             trans2quik_unsubscribe_trades,
             trans2quik_transaction_reply_sec_code,
             trans2quik_transaction_reply_price,
+            trans2quik_transaction_reply_firm_id,
+            trans2quik_transaction_reply_client_code,
+            trans2quik_transaction_reply_account,
             trans2quik_order_date,
             trans2quik_order_time,
+            trans2quik_order_qty,
+            trans2quik_order_exec_type,
             trans2quik_trade_date,
             trans2quik_trade_time,
+            trans2quik_trade_settle_code,
+            trans2quik_trade_settle_time,
+            trans2quik_trade_is_marginal,
+            trans2quik_trade_userid,
+            trans2quik_trade_firm_id,
+            trans2quik_trade_uid,
+            trans2quik_trade_currency,
+            trans2quik_trade_settle_currency,
+        })
+    }
+
+    /// Sets the account and client code used by default in the transactions
+    /// built by `Terminal::transaction`.
+    pub fn with_default_account(mut self, account: &str, client_code: &str) -> Self {
+        self.default_account = Some((account.to_string(), client_code.to_string()));
+        self
+    }
+
+    /// Limits the transactions sent by `send_sync_transaction` and `send_async_transaction`
+    /// to `per_second` transactions per second, `0` removes the limit.
+    ///
+    /// With `RateLimitMode::Block` the synchronous methods sleep the calling thread, so they
+    /// shouldn't be called from the async tasks. The async methods, e.g. `replace_order`,
+    /// wait for the limiter without blocking the thread of the runtime.
+    pub fn set_rate_limit(&self, per_second: u32) {
+        self.rate_limiter.lock().unwrap().set_rate(per_second);
+    }
+
+    /// Sets whether the transaction exceeding the rate limit waits or fails with
+    /// `Trans2QuikError::WouldBlock`. The default is `RateLimitMode::Block`.
+    pub fn set_rate_limit_mode(&self, mode: RateLimitMode) {
+        self.rate_limiter.lock().unwrap().mode = mode;
+    }
+
+    /// Sets how long `send_idempotent` remembers the transaction replies by the key.
+    /// The default is 60 seconds.
+    pub fn set_idempotency_window(&self, window: Duration) {
+        self.idempotency_keys.lock().unwrap().set_window(window);
+    }
+
+    /// Sets whether the sent transactions with the dot as the decimal separator of PRICE
+    /// or STOPPRICE, e.g. `PRICE=250.5`, fail with `Trans2QuikError::WrongDecimalSeparator`
+    /// instead of being mis-parsed or rejected by QUIK. The default is `true`, the check can be
+    /// turned off for the transactions formatted by the caller.
+    pub fn set_check_decimal_separator(&self, check: bool) {
+        self.check_decimal_separator.store(check, Ordering::Relaxed);
+    }
+
+    /// Sets what the asynchronous transactions do if the transaction_reply_callback is not set
+    /// and their replies would be lost. The default is `MissingReplyCallback::Warn`.
+    pub fn set_missing_reply_callback(&self, missing_reply_callback: MissingReplyCallback) {
+        *self.missing_reply_callback.lock().unwrap() = missing_reply_callback;
+    }
+
+    /// Fails if the check of the decimal separator is on and a price uses the dot.
+    fn check_decimal_separator(&self, transaction_str: &str) -> Result<(), Trans2QuikError> {
+        if !self.check_decimal_separator.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        for param in ["PRICE", "STOPPRICE"] {
+            if let Some(value) = transaction_param(transaction_str, param) {
+                if value.contains('.') {
+                    return Err(Trans2QuikError::WrongDecimalSeparator(
+                        param,
+                        value.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the rate limiter to allow the next transaction.
+    fn acquire_rate_limit(&self) -> Result<(), Trans2QuikError> {
+        loop {
+            let (acquired, mode) = {
+                let mut rate_limiter = self.rate_limiter.lock().unwrap();
+                (rate_limiter.try_acquire(Instant::now()), rate_limiter.mode)
+            };
+
+            match (acquired, mode) {
+                (Ok(()), _) => return Ok(()),
+                (Err(wait), RateLimitMode::Block) => std::thread::sleep(wait),
+                (Err(_), RateLimitMode::WouldBlock) => return Err(Trans2QuikError::WouldBlock),
+            }
+        }
+    }
+
+    /// Waits for the rate limiter like `acquire_rate_limit` without blocking the thread.
+    async fn acquire_rate_limit_async(&self) -> Result<(), Trans2QuikError> {
+        loop {
+            let (acquired, mode) = {
+                let mut rate_limiter = self.rate_limiter.lock().unwrap();
+                (rate_limiter.try_acquire(Instant::now()), rate_limiter.mode)
+            };
+
+            match (acquired, mode) {
+                (Ok(()), _) => return Ok(()),
+                (Err(wait), RateLimitMode::Block) => tokio::time::sleep(wait).await,
+                (Err(_), RateLimitMode::WouldBlock) => return Err(Trans2QuikError::WouldBlock),
+            }
+        }
+    }
+
+    /// Creates the unbounded channels of the events of the callback functions,
+    /// sets their senders to `TRANSACTION_REPLY_SENDER`, `ORDER_STATUS_SENDER` and `TRADE_STATUS_SENDER`
+    /// and returns the receivers, replacing the senders set before.
+    ///
+    /// Doesn't require the tokio runtime, so it can be called from the main thread
+    /// before the runtime is started.
+    pub fn channels() -> EventReceivers {
+        let (transaction_reply_sender, transaction_reply) = mpsc::unbounded_channel();
+        let (order_status_sender, order_status) = mpsc::unbounded_channel();
+        let (trade_status_sender, trade_status) = mpsc::unbounded_channel();
+
+        TRANSACTION_REPLY_SENDER.store(Some(Arc::new(transaction_reply_sender.into())));
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_status_sender.into())));
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_status_sender.into())));
+
+        EventReceivers {
+            transaction_reply,
+            order_status,
+            trade_status,
+        }
+    }
+
+    /// The number of the orders sent to the ORDER_STATUS_SENDER set now, see `EventSender::delivered`.
+    /// `0` if the sender is not set.
+    pub fn orders_delivered(&self) -> u64 {
+        ORDER_STATUS_SENDER
+            .load()
+            .as_ref()
+            .map_or(0, |sender| sender.delivered())
+    }
+
+    /// The number of the trades sent to the TRADE_STATUS_SENDER set now, see `EventSender::delivered`.
+    /// `0` if the sender is not set.
+    pub fn trades_delivered(&self) -> u64 {
+        TRADE_STATUS_SENDER
+            .load()
+            .as_ref()
+            .map_or(0, |sender| sender.delivered())
+    }
+
+    /// The number of the transaction replies sent to the TRANSACTION_REPLY_SENDER set now,
+    /// see `EventSender::delivered`. `0` if the sender is not set.
+    pub fn transaction_replies_delivered(&self) -> u64 {
+        TRANSACTION_REPLY_SENDER
+            .load()
+            .as_ref()
+            .map_or(0, |sender| sender.delivered())
+    }
+
+    /**
+    Subscribes to the copies of all the events of the order_status_callback,
+    in addition to the `ORDER_STATUS_SENDER`. Every subscriber receives every event
+    received after the subscription.
+
+    Up to 1024 events are kept for the slowest subscriber, the older events are dropped
+    and its `recv` returns `RecvError::Lagged` with the number of the dropped events.
+    The callback function is never blocked by the subscribers.
+    */
+    pub fn subscribe_orders_broadcast(&self) -> broadcast::Receiver<OrderInfo> {
+        ORDER_STATUS_BROADCAST.subscribe()
+    }
+
+    /// Subscribes to the copies of the events of the trade_status_callback,
+    /// see `Terminal::subscribe_orders_broadcast`.
+    pub fn subscribe_trades_broadcast(&self) -> broadcast::Receiver<TradeInfo> {
+        TRADE_STATUS_BROADCAST.subscribe()
+    }
+
+    /// Subscribes to the copies of the events of the transaction_reply_callback,
+    /// see `Terminal::subscribe_orders_broadcast`.
+    pub fn subscribe_transaction_replies_broadcast(&self) -> broadcast::Receiver<TransactionInfo> {
+        TRANSACTION_REPLY_BROADCAST.subscribe()
+    }
+
+    /**
+    The copies of the events of all the callback functions received after the call, merged
+    into one stream in the order of receiving: all the events are sent to one broadcast channel
+    by the callback functions, so e.g. the transaction reply precedes the order it placed
+    if the library Trans2QUIK.dll called the callbacks in this order. The `seq` of the orders,
+    the trades and the transaction replies still counts the events of each type.
+
+    The stream lags like `Terminal::subscribe_orders_broadcast`, the skipped events are logged.
+    The events withheld by `Terminal::pause_events` are streamed when resumed, the connection
+    events are not paused.
+
+    # Example of use
+    ```ignore
+    let mut events = terminal.events();
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Order(order_info) => book.update(order_info),
+            Event::Trade(trade_info) => positions.update(trade_info),
+            Event::TransactionReply(transaction_info) => journal.push(transaction_info),
+            Event::Connection(connection_event) => warn!("{:?}", connection_event),
+        }
+    }
+    ```
+    */
+    pub fn events(&self) -> impl Stream<Item = Event> {
+        BroadcastStream::new(EVENT_BROADCAST.subscribe()).filter_map(|event| match event {
+            Ok(event) => Some(event),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                warn!("events stream skipped {} events", skipped);
+                None
+            }
         })
     }
 
+    /**
+    Whether the instrument is trading: a new trade in it was received by the trade_status_callback
+    within the last `within`. The initial trades received after `Terminal::start_trades` are
+    not counted.
+
+    It's a lightweight heuristic: the library Trans2QUIK.dll reports only the trades of the
+    subscribed instruments made by the orders of the account, not all the trades of the market,
+    so `false` means no own trades rather than the closed market.
+    */
+    pub fn is_market_active(&self, class_code: &str, sec_code: &str, within: Duration) -> bool {
+        LAST_TRADE_TIMES
+            .lock()
+            .unwrap()
+            .get(&(class_code.to_string(), sec_code.to_string()))
+            .is_some_and(|received| received.elapsed() <= within)
+    }
+
+    /**
+    The orders and trades of the instrument received since its subscription with
+    `Terminal::subscribe_orders` and `Terminal::subscribe_trades`. The instrument subscribed to
+    but never delivering the events may have a wrong class or instrument code.
+
+    # Example of use
+    ```ignore
+    let stats = terminal.instrument_stats("TQBR", "SBER");
+    if stats.orders == 0 && stats.trades == 0 {
+        warn!("no events of TQBR SBER, check the codes");
+    }
+    ```
+    */
+    pub fn instrument_stats(&self, class_code: &str, sec_code: &str) -> InstrumentStats {
+        INSTRUMENT_STATS
+            .lock()
+            .unwrap()
+            .get(&(class_code.to_string(), sec_code.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Logs the prices and the values of the callback functions with the comma as the decimal
+    /// separator, e.g. `price: 250,5`, matching the messages of QUIK in the same log.
+    /// Affects only the log, not the events.
+    pub fn set_log_decimal_comma(&self, comma: bool) {
+        LOG_DECIMAL_COMMA.store(comma, Ordering::Relaxed);
+    }
+
+    /// Drops the events of the callback functions with the strings failed to decode, e.g. the null
+    /// pointer instead of `sec_code`, instead of delivering them with the empty or partly replaced
+    /// strings. The dropped events are counted by
+    /// `Terminal::undecoded_events_dropped`. The dropped transaction reply fails its waiter.
+    pub fn set_strict_decoding(&self, strict: bool) {
+        STRICT_DECODING.store(strict, Ordering::Relaxed);
+    }
+
+    /// The number of the events dropped by `Terminal::set_strict_decoding`.
+    pub fn undecoded_events_dropped(&self) -> u64 {
+        UNDECODED_EVENTS_DROPPED.load(Ordering::Relaxed)
+    }
+
+    /// Drops the orders with `Mode::InitialOrder` and `Mode::LastOrderReceived` replayed after
+    /// `Terminal::start_orders` instead of sending them to the ORDER_STATUS_SENDER, which then
+    /// receives only the updates with `Mode::NewOrder`. The broadcast subscribers, the recent
+    /// orders and `Terminal::orders_snapshot_complete` still see the initial orders.
+    pub fn set_suppress_initial_orders(&self, suppress: bool) {
+        SUPPRESS_INITIAL_ORDERS.store(suppress, Ordering::Relaxed);
+    }
+
+    /// Sets what to do with the events received while the delivery is paused,
+    /// see `Terminal::pause_events`. The default is `PausePolicy::Buffer`.
+    pub fn set_pause_policy(&self, policy: PausePolicy) {
+        PAUSED_EVENTS.lock().unwrap().policy = policy;
+    }
+
+    /**
+    Pauses the delivery of the events of the callback functions to the channels and
+    the broadcast subscribers without unsubscribing from QUIK, e.g. during the rebalance of
+    the strategy. The events received while paused are buffered or dropped according to
+    `Terminal::set_pause_policy`.
+
+    The terminal keeps tracking the orders and the transaction replies meanwhile:
+    `Terminal::send_async_transaction_and_wait` and `Terminal::wait_for_order` with the recent
+    orders work, but the methods waiting for the new events of the broadcast channels, e.g.
+    `Terminal::subscribe_and_snapshot`, wait until resumed. The transaction reply handler is called
+    as usual: the transaction reply descriptor can't be read after the callback.
+    */
+    pub fn pause_events(&self) {
+        EVENTS_PAUSED.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes the delivery of the events paused by `Terminal::pause_events`, the buffered events
+    /// are delivered first, in the order of receiving.
+    pub fn resume_events(&self) {
+        let mut paused_events = PAUSED_EVENTS.lock().unwrap();
+
+        let buffered = paused_events.events.len();
+        for event in paused_events.events.drain(..) {
+            deliver_event(event);
+        }
+
+        EVENTS_PAUSED.store(false, Ordering::SeqCst);
+
+        info!(
+            "events resumed, buffered: {}, dropped: {}",
+            buffered, paused_events.dropped
+        );
+        paused_events.dropped = 0;
+    }
+
+    /**
+    Sets the hook called with the panics of the callback functions, e.g. to report them
+    to the crash reporting service. The panics are caught so that they don't unwind
+    into the library Trans2QUIK.dll, and logged.
+
+    The hook is called from the global panic hook, which is chained rather than replaced:
+    the panic hook set before is still called for all the panics.
+    */
+    pub fn set_callback_panic_hook(
+        &self,
+        hook: Box<dyn Fn(&std::panic::PanicHookInfo<'_>) + Send + Sync>,
+    ) {
+        callback_panic::set_hook(hook);
+    }
+
+    /// The callback functions set by the terminal or its clones, for the debugging.
+    pub fn callback_state(&self) -> CallbackState {
+        *self.callback_state.lock().unwrap()
+    }
+
+    /// The names of the functions loaded from the library Trans2QUIK.dll, for the diagnostics
+    /// of the compatibility of the library.
+    pub fn loaded_symbols(&self) -> Vec<&'static str> {
+        self.loaded_symbols.clone()
+    }
+
+    /// Sets the source of the lot sizes and the price steps of the instruments used by the
+    /// transaction builders of `Terminal::transaction`: the prices are rounded to the price step,
+    /// the quantity set with `TransactionBuilder::quantity_units` is converted to the lots.
+    /// The metadata is cached for each instrument.
+    pub fn set_instrument_info<P: InstrumentInfoProvider + 'static>(&self, provider: P) {
+        *self.instrument_info.lock().unwrap() = Some(InstrumentInfoCache::new(provider));
+    }
+
+    /// Creates the transaction builder with the default account of the terminal.
+    pub fn transaction(&self, action: Action) -> TransactionBuilder {
+        let mut builder = TransactionBuilder::new(action);
+
+        if let Some(instrument_info) = self.instrument_info.lock().unwrap().as_ref() {
+            builder = builder.instrument_info(Arc::clone(instrument_info));
+        }
+
+        match &self.default_account {
+            Some((account, client_code)) => builder.default_account(account, client_code),
+            None => builder,
+        }
+    }
+
+    /// The `error_code` and the `error_message` of the last call of the library Trans2QUIK.dll
+    /// by the terminal or its clones that didn't return `Trans2QuikResult::Success`,
+    /// `None` if all the calls succeeded.
+    pub fn last_error(&self) -> Option<(i32, String)> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Keeps the error of the failed call for `Terminal::last_error`.
+    fn record_error(
+        &self,
+        trans2quik_result: &Trans2QuikResult,
+        error_code: c_long,
+        error_message: &str,
+    ) {
+        if *trans2quik_result != Trans2QuikResult::Success {
+            // c_long is i32 on Windows only.
+            #[allow(clippy::unnecessary_cast)]
+            let error_code = error_code as i32;
+            *self.last_error.lock().unwrap() = Some((error_code, error_message.to_string()));
+        }
+    }
+
+    /// The functions reading the descriptors stored for the callback functions.
+    fn descriptor_getters(&self) -> DescriptorGetters {
+        DescriptorGetters {
+            trans2quik_transaction_reply_sec_code: self.trans2quik_transaction_reply_sec_code,
+            trans2quik_transaction_reply_price: self.trans2quik_transaction_reply_price,
+            trans2quik_transaction_reply_firm_id: self.trans2quik_transaction_reply_firm_id,
+            trans2quik_transaction_reply_client_code: self.trans2quik_transaction_reply_client_code,
+            trans2quik_transaction_reply_account: self.trans2quik_transaction_reply_account,
+            trans2quik_order_date: self.trans2quik_order_date,
+            trans2quik_order_time: self.trans2quik_order_time,
+            trans2quik_order_qty: self.trans2quik_order_qty,
+            trans2quik_order_exec_type: self.trans2quik_order_exec_type,
+            trans2quik_trade_date: self.trans2quik_trade_date,
+            trans2quik_trade_time: self.trans2quik_trade_time,
+            trans2quik_trade_settle_code: self.trans2quik_trade_settle_code,
+            trans2quik_trade_settle_time: self.trans2quik_trade_settle_time,
+            trans2quik_trade_is_marginal: self.trans2quik_trade_is_marginal,
+            trans2quik_trade_userid: self.trans2quik_trade_userid,
+            trans2quik_trade_firm_id: self.trans2quik_trade_firm_id,
+            trans2quik_trade_uid: self.trans2quik_trade_uid,
+            trans2quik_trade_currency: self.trans2quik_trade_currency,
+            trans2quik_trade_settle_currency: self.trans2quik_trade_settle_currency,
+        }
+    }
+
     /// Calling a function from the library Trans2QUIK.dll.
     fn call_trans2quik_function<F>(
         &self,
@@ -861,13 +2735,15 @@ impl Terminal {
         let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
 
         // Вызов функции
-        let function_result = func(
-            error_code_ptr,
-            error_message_ptr,
-            error_message.len() as c_long,
-        );
+        let function_result = fault::call(function_name, || {
+            func(
+                error_code_ptr,
+                error_message_ptr,
+                error_message.len() as c_long,
+            )
+        });
 
-        let error_message = match extract_string_from_vec(error_message) {
+        let error_message = match extract_string_from_vec(error_message, WINDOWS_1251) {
             Ok(message) => message,
             Err(e) => {
                 error!("Warning: error_message contains invalid UTF-8: {}", e);
@@ -876,20 +2752,87 @@ impl Terminal {
         };
 
         let trans2quik_result = Trans2QuikResult::from(function_result);
+        self.record_error(&trans2quik_result, error_code, &error_message);
 
-        info!(
-            "{} -> {:?}, error_code: {}, error_message: {}",
-            function_name, trans2quik_result, error_code, error_message
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "{} -> {:?}, error_code: {}, error_message: {}",
+                function_name,
+                trans2quik_result,
+                ErrorCode::from(error_code),
+                error_message
+            ),
         );
         Ok(trans2quik_result)
     }
 
-    /// The function is used to establish communication with the QUIK terminal.
+    /// The function is used to establish communication with the QUIK terminal
+    /// in the directory passed to `Terminal::new`.
     pub fn connect(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let connection_str = CString::new(&*self.path_to_quik)?;
-        let connection_str_ptr = connection_str.as_ptr() as *mut c_char;
+        self.connect_to(&self.path_to_quik)
+    }
 
-        let function = |error_code_ptr: *mut c_long,
+    /// Connects to the QUIK terminal like `Terminal::connect`, treating the connection established
+    /// before, `Trans2QuikResult::AlreadyConnectedToQuik`, as the success. The other results are
+    /// returned as `Trans2QuikError::NotConnected`.
+    pub fn ensure_connected(&self) -> Result<ConnectOutcome, Trans2QuikError> {
+        let trans2quik_result = self.connect()?;
+
+        ConnectOutcome::from_result(&trans2quik_result)
+            .ok_or(Trans2QuikError::NotConnected(trans2quik_result))
+    }
+
+    /**
+    Connects to the QUIK terminal like `Terminal::connect`, but returns `Trans2QuikError::Timeout`
+    if the connection is not established within the `timeout`, e.g. if the QUIK terminal is not
+    running, instead of blocking the caller.
+
+    TRANS2QUIK_CONNECT runs on a worker thread owning a clone of the terminal. The call of the
+    library can't be interrupted: after the timeout it keeps running on the worker thread,
+    and its result is only logged. The library may still connect later, reported by the
+    connection_status_callback, so call `Terminal::disconnect` if the connection is not wanted.
+    The other functions of the library can be called meanwhile, but with
+    `TerminalOptions::set_current_dir` the current directory of the process is restored
+    only when the abandoned call returns.
+    */
+    pub fn connect_timeout(&self, timeout: Duration) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let terminal = self.clone();
+
+        std::thread::Builder::new()
+            .name(String::from("trans2quik-connect"))
+            .spawn(move || {
+                let result = terminal.connect();
+                if let Err(std::sync::mpsc::SendError(result)) = sender.send(result) {
+                    warn!(
+                        "TRANS2QUIK_CONNECT returned after the timeout: {:?}",
+                        result
+                    );
+                }
+            })?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                error!("TRANS2QUIK_CONNECT timed out after {:?}", timeout);
+                Err(Trans2QuikError::Timeout)
+            }
+        }
+    }
+
+    /// The function is used to establish communication with the QUIK terminal in the directory
+    /// `path_to_quik`, which can differ from the directory of the library Trans2QUIK.dll.
+    pub fn connect_to(&self, path_to_quik: &str) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let connection_str = CString::new(path_to_quik)?;
+        let connection_str_ptr = connection_str.as_ptr() as *mut c_char;
+
+        let _current_dir_guard = match &self.library_dir {
+            Some(dir) => Some(CurrentDirGuard::set(dir)?),
+            None => None,
+        };
+
+        let function = |error_code_ptr: *mut c_long,
                         error_message_ptr: *mut c_char,
                         error_message_len: c_long| unsafe {
             (self.trans2quik_connect)(
@@ -900,7 +2843,15 @@ impl Terminal {
             )
         };
 
-        self.call_trans2quik_function("TRANS2QUIK_CONNECT", function)
+        let result = self.call_trans2quik_function("TRANS2QUIK_CONNECT", function);
+        if let Ok(Trans2QuikResult::Success | Trans2QuikResult::AlreadyConnectedToQuik) = result {
+            transition_conn_state(
+                &[ConnState::Disconnected, ConnState::Reconnecting],
+                ConnState::DllConnected,
+            );
+        }
+
+        result
     }
 
     /// The function is used to disconnect from the QUIK terminal.
@@ -911,7 +2862,26 @@ impl Terminal {
             (self.trans2quik_disconnect)(error_code, error_message, error_message_len)
         };
 
-        self.call_trans2quik_function("TRANS2QUIK_DISCONNECT", function)
+        // The disconnection must not be restored by the auto-reconnect.
+        if let Some(reconnect) = self.reconnect.lock().unwrap().take() {
+            reconnect.stop();
+        }
+
+        let result = self.call_trans2quik_function("TRANS2QUIK_DISCONNECT", function);
+        if let Ok(Trans2QuikResult::Success) = result {
+            CONN_STATE.store(Arc::new(ConnState::Disconnected));
+        }
+
+        // Another Terminal can be started after the disconnection.
+        let mut terminal_instance = TERMINAL_INSTANCE.lock().unwrap();
+        let is_this_terminal = terminal_instance
+            .as_ref()
+            .is_some_and(|instance| instance.instance_id == self.instance_id);
+        if is_this_terminal {
+            *terminal_instance = None;
+        }
+
+        result
     }
 
     /// The function is used to check if there is a connection between the QUIK terminal and the server.
@@ -944,9 +2914,157 @@ impl Terminal {
         &self,
         transaction_str: &str,
     ) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let trans_str = CString::new(transaction_str)?;
+        Ok(self
+            .send_sync_reply(transaction_str, WINDOWS_1251, false)?
+            .trans2quik_result)
+    }
+
+    /// Sends the transaction synchronously like `Terminal::send_sync_transaction`, returning
+    /// the reply of the QUIK server with the result message and the error message decoded from
+    /// the `encoding`, see `Terminal::send_async_transaction_with_encoding`.
+    pub fn send_sync_transaction_with_encoding(
+        &self,
+        transaction_str: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        self.send_sync_reply(transaction_str, encoding.unwrap_or(WINDOWS_1251), false)
+    }
+
+    /**
+    Builds the transaction and sends it synchronously, returning the reply of the QUIK server.
+    The transaction not sent is returned as `Trans2QuikError::NotSent` and the rejected one,
+    e.g. with no money for the order, as `Trans2QuikError::Rejected` with the reply.
+
+    # Example of use
+    ```ignore
+    let reply = terminal.send_sync(
+        terminal
+            .transaction(Action::NewOrder)
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .operation(IsSell::Buy)
+            .order_type(OrderType::Limit)
+            .price(250.5)
+            .quantity(1),
+    )?;
+    println!("order {} is placed", reply.order_num);
+    ```
+    */
+    pub fn send_sync(
+        &self,
+        transaction: TransactionBuilder,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        let transaction_info = self.send_sync_reply(&transaction.build()?, WINDOWS_1251, false)?;
+
+        if transaction_info.trans2quik_result != Trans2QuikResult::Success {
+            return Err(Trans2QuikError::NotSent(transaction_info.trans2quik_result));
+        }
+
+        if transaction_info.reply_code.is_rejected() {
+            return Err(Trans2QuikError::Rejected(Box::new(transaction_info)));
+        }
+
+        Ok(transaction_info)
+    }
+
+    /**
+    Measures the round trip to the QUIK server: sends the KILL_ORDER transaction of the nonexistent
+    order `0` of the instrument `sec_code` in the class `class_code` synchronously and returns
+    the time to the reply. The instrument should be available to the account, e.g. SBER in TQBR.
+    The reply is expected to be the rejection, it's not checked: only the transaction not sent
+    is returned as `Trans2QuikError::NotSent`.
+
+    Note that a real transaction is sent to the QUIK server, counted by the server limits and
+    by `Terminal::set_rate_limit`, and logged in the QUIK terminal like the other transactions.
+
+    The ping is the synchronous transaction, so it is not sent while the asynchronous
+    transactions are waiting for their replies, see `Terminal::pending_transactions`:
+    the library Trans2QUIK.dll prohibits the synchronous and the asynchronous transactions
+    at the same time. `Trans2QuikError::PendingTransactions` is returned then.
+    The time waiting for `Terminal::set_rate_limit` is not counted in the latency.
+
+    The synchronous transaction runs on a worker thread like `Terminal::connect_timeout`,
+    `Trans2QuikError::Timeout` is returned if there is no reply within the `timeout`.
+
+    # Example of use
+    ```ignore
+    let latency = terminal.ping("TQBR", "SBER", Duration::from_secs(5))?;
+    ```
+    */
+    pub fn ping(
+        &self,
+        class_code: &str,
+        sec_code: &str,
+        timeout: Duration,
+    ) -> Result<Duration, Trans2QuikError> {
+        let pending_transactions = self.pending_transactions();
+        if pending_transactions > 0 {
+            error!(
+                "ping is not sent, {} asynchronous transactions are pending",
+                pending_transactions
+            );
+            return Err(Trans2QuikError::PendingTransactions(pending_transactions));
+        }
+
+        let transaction_str = TransactionBuilder::new(Action::KillOrder)
+            .trans_id(next_trans_id())
+            .class_code(class_code)
+            .sec_code(sec_code)
+            .order_key(0)
+            .build()?;
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let terminal = self.clone();
+
+        std::thread::Builder::new()
+            .name(String::from("trans2quik-ping"))
+            .spawn(move || {
+                // The wait for the rate limiter is not the latency.
+                let result = terminal.acquire_rate_limit().and_then(|()| {
+                    let started = Instant::now();
+                    terminal
+                        .send_sync_reply(&transaction_str, WINDOWS_1251, true)
+                        .map(|transaction_info| (transaction_info, started.elapsed()))
+                });
+                if let Err(std::sync::mpsc::SendError(result)) = sender.send(result) {
+                    warn!("ping returned after the timeout: {:?}", result);
+                }
+            })?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => {
+                let (transaction_info, elapsed) = result?;
+                if transaction_info.trans2quik_result != Trans2QuikResult::Success {
+                    return Err(Trans2QuikError::NotSent(transaction_info.trans2quik_result));
+                }
+                Ok(elapsed)
+            }
+            Err(_) => {
+                error!("ping timed out after {:?}", timeout);
+                Err(Trans2QuikError::Timeout)
+            }
+        }
+    }
+
+    /// Sends the transaction synchronously, returns the reply of the QUIK server with the result
+    /// message and the error message decoded from the `encoding`. The rate limiter is not waited
+    /// for if `rate_limit_acquired`, see `Terminal::ping`.
+    fn send_sync_reply(
+        &self,
+        transaction_str: &str,
+        encoding: &'static Encoding,
+        rate_limit_acquired: bool,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        self.check_decimal_separator(transaction_str)?;
+
+        let trans_str = encode_lpstr(transaction_str)?;
         let trans_str_ptr = trans_str.as_ptr() as *mut c_char;
 
+        if !rate_limit_acquired {
+            self.acquire_rate_limit()?;
+        }
+
         let mut reply_code: c_long = 0;
         let reply_code_ptr = &mut reply_code as *mut c_long;
 
@@ -965,7 +3083,8 @@ impl Terminal {
         let mut error_message = vec![0 as c_char; 256];
         let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
 
-        let function_result = unsafe {
+        let started = Instant::now();
+        let function_result = fault::call("TRANS2QUIK_SEND_SYNC_TRANSACTION", || unsafe {
             (self.trans2quik_send_sync_transaction)(
                 trans_str_ptr,
                 reply_code_ptr,
@@ -977,9 +3096,9 @@ impl Terminal {
                 error_message_ptr,
                 error_message.len() as c_long,
             )
-        };
+        });
 
-        let result_message = match extract_string_from_vec(result_message) {
+        let result_message = match extract_string_from_vec(result_message, encoding) {
             Ok(message) => message,
             Err(e) => {
                 error!("Warning: result_message contains invalid UTF-8: {}", e);
@@ -987,7 +3106,7 @@ impl Terminal {
             }
         };
 
-        let error_message = match extract_string_from_vec(error_message) {
+        let error_message = match extract_string_from_vec(error_message, encoding) {
             Ok(message) => message,
             Err(e) => {
                 error!("Warning: error_message contains invalid UTF-8: {}", e);
@@ -996,81 +3115,191 @@ impl Terminal {
         };
 
         let trans2quik_result = Trans2QuikResult::from(function_result);
+        self.record_error(&trans2quik_result, error_code, &error_message);
 
-        info!("TRANS2QUIK_SEND_SYNC_TRANSACTION -> {:?}, reply_code: {}, trans_id: {}, order_num: {}, result_message: {}, error_code: {}, error_message: {}",
+        log_result(&trans2quik_result, format_args!("TRANS2QUIK_SEND_SYNC_TRANSACTION -> {:?}, reply_code: {:?}, trans_id: {}, order_num: {}, result_message: {}, error_code: {}, error_message: {}",
             trans2quik_result,
-            reply_code,
+            ReplyCode::from(reply_code),
             trans_id,
             order_num,
             result_message,
-            error_code,
+            ErrorCode::from(error_code),
             error_message,
-        );
+        ));
 
-        Ok(trans2quik_result)
+        let reply_code = ReplyCode::from(reply_code);
+
+        telemetry::send_sync_duration(started.elapsed());
+        telemetry::transaction_sent("sync", &trans2quik_result);
+        if trans2quik_result == Trans2QuikResult::Success {
+            telemetry::transaction_reply(&reply_code);
+        }
+
+        let rejection_reason = match reply_code.is_rejected() {
+            true => Some(classify_rejection(&result_message)),
+            false => None,
+        };
+
+        let price = transaction_param(transaction_str, "PRICE")
+            .and_then(|price| price.replace(',', ".").parse::<f64>().ok())
+            .unwrap_or(0.0);
+
+        Ok(TransactionInfo {
+            trans2quik_result,
+            error_code: ErrorCode::from(error_code),
+            reply_code,
+            trans_id: TransId::from(trans_id),
+            order_num: order_num as u64,
+            reply_message: result_message,
+            sec_code: transaction_param(transaction_str, "SECCODE")
+                .unwrap_or_default()
+                .to_string(),
+            price,
+            // Not returned by TRANS2QUIK_SEND_SYNC_TRANSACTION.
+            firm_id: String::new(),
+            client_code: String::new(),
+            account: String::new(),
+            rejection_reason,
+            // Not received by the transaction_reply_callback.
+            seq: 0,
+        })
     }
 
     /// Asynchronous transfer of a transaction. When sending an asynchronous transaction, the refund is
     /// the function is executed immediately, and the result of the transaction is reported via
     /// the corresponding callback function.
+    ///
+    /// The transaction without a positive TRANS_ID is not sent if the transaction_reply_callback
+    /// is set with `Terminal::set_transactions_reply_callback`: its reply couldn't be correlated
+    /// to the transaction, `Trans2QuikError::MissingTransId` is returned.
+    /// The TRANS_ID is not assigned automatically, see `Terminal::transaction`.
     pub fn send_async_transaction(
         &self,
         transaction_str: &str,
     ) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let trans_str = CString::new(transaction_str)?;
-        let trans_str_ptr = trans_str.as_ptr() as *mut c_char;
+        let trans_str = encode_lpstr(transaction_str)?;
 
-        let mut error_code: c_long = 0;
-        let error_code_ptr = &mut error_code as *mut c_long;
+        self.send_async_lpstr(&trans_str, transaction_str, WINDOWS_1251, false)
+    }
 
-        let mut error_message = vec![0 as c_char; 256];
-        let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
+    /// Sends the asynchronous transaction like `send_async_transaction`, waiting for the rate
+    /// limiter without blocking the thread of the runtime.
+    async fn send_async_transaction_limited(
+        &self,
+        transaction_str: &str,
+    ) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let trans_str = encode_lpstr(transaction_str)?;
 
-        let function_result = unsafe {
-            (self.trans2quik_send_async_transaction)(
-                trans_str_ptr,
-                error_code_ptr,
-                error_message_ptr,
-                error_message.len() as c_long,
-            )
-        };
+        self.acquire_rate_limit_async().await?;
 
-        let error_message = match extract_string_from_vec(error_message) {
-            Ok(message) => message,
-            Err(e) => {
-                error!("Warning: error_message contains invalid UTF-8: {}", e);
-                String::from("Invalid UTF-8 in error_message")
-            }
-        };
+        self.send_async_lpstr(&trans_str, transaction_str, WINDOWS_1251, true)
+    }
 
-        let trans2quik_result = Trans2QuikResult::from(function_result);
+    /**
+    Sends the transaction asynchronously like `Terminal::send_async_transaction`, decoding
+    the error message of the library Trans2QUIK.dll from the `encoding`, e.g. of the terminal
+    running in another locale. The messages are decoded from WINDOWS-1251 if `encoding` is `None`.
+    The transaction itself is encoded in WINDOWS-1251 as usual.
+
+    # Example of use
+    ```ignore
+    let encoding = Encoding::for_label(b"koi8-r");
+    terminal.send_async_transaction_with_encoding(&transaction_str, encoding)?;
+    ```
+    */
+    pub fn send_async_transaction_with_encoding(
+        &self,
+        transaction_str: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let trans_str = encode_lpstr(transaction_str)?;
+
+        self.send_async_lpstr(
+            &trans_str,
+            transaction_str,
+            encoding.unwrap_or(WINDOWS_1251),
+            false,
+        )
+    }
 
-        info!(
-            "TRANS2QUIK_SEND_ASYNC_TRANSACTION -> {:?}, error_code: {}, error_message: {}",
-            trans2quik_result, error_code, error_message,
-        );
+    /// Asynchronous transfer of the transaction already encoded in WINDOWS-1251, the bytes are passed
+    /// to the library Trans2QUIK.dll unchanged. Returns `Trans2QuikError::NulError` if the bytes
+    /// contain the nul byte.
+    pub fn send_async_transaction_bytes(
+        &self,
+        bytes: &[u8],
+    ) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let trans_str = CString::new(bytes)?;
 
-        Ok(trans2quik_result)
+        // Decoded only to track TRANS_ID.
+        let (transaction_str, _, _) = WINDOWS_1251.decode(bytes);
+
+        self.send_async_lpstr(&trans_str, &transaction_str, WINDOWS_1251, false)
     }
 
-    /// А callback function for processing the received connection information.
-    pub fn set_connection_status_callback(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+    /// Sends the encoded transaction `trans_str`, `transaction_str` is its text.
+    /// The error message is decoded from the `encoding`. The rate limiter is not waited for
+    /// if `rate_limit_acquired`, see `send_async_transaction_limited`.
+    fn send_async_lpstr(
+        &self,
+        trans_str: &CStr,
+        transaction_str: &str,
+        encoding: &'static Encoding,
+        rate_limit_acquired: bool,
+    ) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let trans_str_ptr = trans_str.as_ptr() as *mut c_char;
+
+        self.check_decimal_separator(transaction_str)?;
+
+        let has_trans_id = transaction_param(transaction_str, "TRANS_ID")
+            .and_then(|trans_id| trans_id.parse::<c_long>().ok())
+            .is_some_and(|trans_id| trans_id > 0);
+        let reply_callback_set = self.callback_state.lock().unwrap().transaction_reply;
+        if !has_trans_id && reply_callback_set {
+            return Err(Trans2QuikError::MissingTransId);
+        }
+
+        if !reply_callback_set {
+            match *self.missing_reply_callback.lock().unwrap() {
+                MissingReplyCallback::Warn => warn!(
+                    "the transaction_reply_callback is not set, the reply of TRANS_ID={} is lost",
+                    transaction_param(transaction_str, "TRANS_ID").unwrap_or_default()
+                ),
+                MissingReplyCallback::Error => return Err(Trans2QuikError::ReplyCallbackNotSet),
+                MissingReplyCallback::FireAndForget => {}
+            }
+        }
+
         let mut error_code: c_long = 0;
         let error_code_ptr = &mut error_code as *mut c_long;
 
         let mut error_message = vec![0 as c_char; 256];
         let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
 
-        let function_result = unsafe {
-            (self.trans2quik_set_connection_status_callback)(
-                connection_status_callback,
+        if !rate_limit_acquired {
+            self.acquire_rate_limit()?;
+        }
+
+        // The reply can arrive before the function returns.
+        let tracked_trans_id = track_sent_order(transaction_str);
+        // Without the transaction_reply_callback the reply never removes the TRANS_ID.
+        let pending_trans_id = transaction_param(transaction_str, "TRANS_ID")
+            .and_then(|trans_id| trans_id.parse::<c_long>().ok())
+            .filter(|_| reply_callback_set);
+        if let Some(trans_id) = pending_trans_id {
+            PENDING_TRANS_IDS.lock().unwrap().insert(trans_id);
+        }
+
+        let function_result = fault::call("TRANS2QUIK_SEND_ASYNC_TRANSACTION", || unsafe {
+            (self.trans2quik_send_async_transaction)(
+                trans_str_ptr,
                 error_code_ptr,
                 error_message_ptr,
                 error_message.len() as c_long,
             )
-        };
+        });
 
-        let error_message = match extract_string_from_vec(error_message) {
+        let error_message = match extract_string_from_vec(error_message, encoding) {
             Ok(message) => message,
             Err(e) => {
                 error!("Warning: error_message contains invalid UTF-8: {}", e);
@@ -1079,593 +3308,5541 @@ impl Terminal {
         };
 
         let trans2quik_result = Trans2QuikResult::from(function_result);
-        info!(
-            "TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK -> {:?}, error_code: {}, error_message: {}",
-            trans2quik_result, error_code, error_message
+        self.record_error(&trans2quik_result, error_code, &error_message);
+
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "TRANS2QUIK_SEND_ASYNC_TRANSACTION -> {:?}, error_code: {}, error_message: {}",
+                trans2quik_result,
+                ErrorCode::from(error_code),
+                error_message,
+            ),
         );
+        telemetry::transaction_sent("async", &trans2quik_result);
+
+        if trans2quik_result != Trans2QuikResult::Success {
+            if let Some(trans_id) = tracked_trans_id {
+                SENT_ORDERS.lock().unwrap().remove(&trans_id);
+            }
+            if let Some(trans_id) = pending_trans_id {
+                PENDING_TRANS_IDS.lock().unwrap().remove(&trans_id);
+            }
+        }
 
         Ok(trans2quik_result)
     }
 
-    /// Sets the callback function to receive information about the sent asynchronous transaction.
-    pub fn set_transactions_reply_callback(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let mut error_code: c_long = 0;
-        let error_code_ptr = &mut error_code as *mut c_long;
+    /// The state of the connection, the single source of truth for the connectivity,
+    /// see `ConnState` for the transitions.
+    pub fn conn_state(&self) -> ConnState {
+        **CONN_STATE.load()
+    }
 
-        let mut error_message = vec![0 as c_char; 256];
-        let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
+    /// The last event received by the connection_status_callback,
+    /// `None` if the callback has not been called yet.
+    /// Allows to track the connection without polling `is_quik_connected` and `is_dll_connected`.
+    pub fn last_connection_event(&self) -> Option<ConnectionEvent> {
+        LAST_CONNECTION_EVENT.lock().unwrap().clone()
+    }
 
-        let function_result = unsafe {
-            (self.trans2quik_set_transactions_reply_callback)(
-                transaction_reply_callback,
-                error_code_ptr,
-                error_message_ptr,
-                error_message.len() as c_long,
-            )
-        };
+    /**
+    Waits for the next `ConnectionEvent::QuikDisconnected` or `ConnectionEvent::DllDisconnected`
+    received by the connection_status_callback, set with `Terminal::set_connection_status_callback`.
+    The disconnections before the first poll of the future are not awaited.
 
-        let error_message = match extract_string_from_vec(error_message) {
-            Ok(message) => message,
-            Err(e) => {
-                error!("Warning: error_message contains invalid UTF-8: {}", e);
-                String::from("Invalid UTF-8 in error_message")
+    # Example of use
+    ```ignore
+    tokio::select! {
+        event = terminal.next_disconnect() => error!("halt trading: {:?}", event),
+        _ = trading_loop() => {}
+    }
+    ```
+    */
+    pub async fn next_disconnect(&self) -> ConnectionEvent {
+        let mut events = CONNECTION_EVENTS.subscribe();
+
+        loop {
+            match events.recv().await {
+                Ok(
+                    event @ (ConnectionEvent::QuikDisconnected(_)
+                    | ConnectionEvent::DllDisconnected(_)),
+                ) => return event,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("next_disconnect skipped {} connection events", skipped);
+                }
+                // The sender is static and never dropped.
+                Err(broadcast::error::RecvError::Closed) => unreachable!(),
             }
-        };
+        }
+    }
 
-        let trans2quik_result = Trans2QuikResult::from(function_result);
+    /**
+    Reconnects to the QUIK terminal after `ConnectionEvent::DllDisconnected`, retrying the connection
+    every `interval` until it succeeds. After `ConnectionEvent::QuikDisconnected` the QUIK terminal
+    reconnects to the server itself, so the connection is not retried: `Terminal::is_quik_connected`
+    is polled every `interval` until the terminal is connected.
+    Requires `Terminal::set_connection_status_callback` and the tokio runtime,
+    the retries run in a task of the runtime, the blocking calls of the library
+    on its blocking threads.
+
+    The auto-reconnect runs until the returned handle is stopped or `Terminal::disconnect` is called.
+    Replaces the auto-reconnect enabled before.
+
+    # Example of use
+    ```ignore
+    let reconnect = terminal.enable_auto_reconnect(Duration::from_secs(5));
+    // ...
+    reconnect.stop();
+    ```
+    */
+    pub fn enable_auto_reconnect(&self, interval: Duration) -> ReconnectHandle {
+        let handle = ReconnectHandle::default();
+        if let Some(previous) = self.reconnect.lock().unwrap().replace(handle.clone()) {
+            previous.stop();
+        }
 
-        info!(
-            "TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK -> {:?}, error_code: {}, error_message: {}",
-            trans2quik_result, error_code, error_message
-        );
+        // Subscribed before the return, so the disconnections after it are not missed.
+        let mut events = CONNECTION_EVENTS.subscribe();
+        let terminal = self.clone();
+        let task_handle = handle.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = task_handle.stopped() => return,
+                    event = events.recv() => match event {
+                        Ok(
+                            event @ (ConnectionEvent::QuikDisconnected(_)
+                            | ConnectionEvent::DllDisconnected(_)),
+                        ) => event,
+                        Ok(_) => continue,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("auto-reconnect skipped {} connection events", skipped);
+                            continue;
+                        }
+                        // The sender is static and never dropped.
+                        Err(broadcast::error::RecvError::Closed) => unreachable!(),
+                    },
+                };
+
+                let after = ConnState::Reconnecting.after(&event);
+                CONN_STATE.store(Arc::new(ConnState::Reconnecting));
+
+                let restored = match event {
+                    ConnectionEvent::DllDisconnected(_) => {
+                        let terminal = terminal.clone();
+                        retry_until(&task_handle, interval, move || match terminal.connect() {
+                            Ok(
+                                Trans2QuikResult::Success
+                                | Trans2QuikResult::AlreadyConnectedToQuik,
+                            ) => {
+                                info!("auto-reconnect restored the connection");
+                                telemetry::reconnect();
+                                true
+                            }
+                            Ok(result) => {
+                                warn!("auto-reconnect failed: {:?}", result);
+                                false
+                            }
+                            Err(err) => {
+                                error!("auto-reconnect failed: {}", err);
+                                false
+                            }
+                        })
+                        .await
+                    }
+                    _ => {
+                        let terminal = terminal.clone();
+                        retry_until(&task_handle, interval, move || {
+                            match terminal.is_quik_connected() {
+                                Ok(Trans2QuikResult::QuikConnected) => {
+                                    info!("auto-reconnect: QUIK reconnected to the server");
+                                    transition_conn_state(
+                                        &[ConnState::Reconnecting],
+                                        ConnState::QuikConnected,
+                                    );
+                                    true
+                                }
+                                // Reconnected after its own DllDisconnected event.
+                                Ok(Trans2QuikResult::DllNotConnected) => true,
+                                Ok(_) => false,
+                                Err(err) => {
+                                    error!("auto-reconnect failed: {}", err);
+                                    false
+                                }
+                            }
+                        })
+                        .await
+                    }
+                };
 
-        Ok(trans2quik_result)
+                if !restored {
+                    // Stopped, the state before the auto-reconnect unless disconnected meanwhile.
+                    transition_conn_state(&[ConnState::Reconnecting], after);
+                    return;
+                }
+            }
+        });
+
+        handle
     }
 
-    /// The function is used to create a list of classes and tools for subscribing to receive orders for them.
-    pub fn subscribe_orders(
+    /// Sends the transactions from the file of the QUIK transaction import (.tri),
+    /// one transaction per line. The empty lines are skipped, the lines without
+    /// `ACTION` or `TRANS_ID` are not sent.
+    /// The file is read as UTF-8 or, if it isn't valid UTF-8, as WINDOWS-1251 used by QUIK.
+    /// Returns the result of every transaction with the number of its line starting from 1.
+    #[allow(clippy::type_complexity)]
+    pub fn send_transactions_from_file(
         &self,
-        class_code: &str,
-        sec_code: &str,
-    ) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let class_code_c = CString::new(class_code)?;
-        let class_code_ptr = class_code_c.as_ptr() as *mut c_char;
+        path: &Path,
+        mode: SendMode,
+    ) -> Result<Vec<(usize, Result<Trans2QuikResult, Trans2QuikError>)>, Trans2QuikError> {
+        let bytes = std::fs::read(path)?;
+
+        let content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            Err(err) => {
+                let (decoded_str, _, _) = WINDOWS_1251.decode(err.as_bytes());
+                decoded_str.into_owned()
+            }
+        };
 
-        let sec_code_c = CString::new(sec_code)?;
-        let sec_code_ptr = sec_code_c.as_ptr() as *mut c_char;
+        let mut results = Vec::new();
 
-        let function_result =
-            unsafe { (self.trans2quik_subscribe_orders)(class_code_ptr, sec_code_ptr) };
+        for (index, line) in content.lines().enumerate() {
+            let transaction_str = line.trim();
+            if transaction_str.is_empty() {
+                continue;
+            }
 
-        let trans2quik_result = Trans2QuikResult::from(function_result);
+            let result = match validate_transaction_str(transaction_str) {
+                Err(err) => Err(err.into()),
+                Ok(()) => match mode {
+                    SendMode::Sync => self.send_sync_transaction(transaction_str),
+                    SendMode::Async => self.send_async_transaction(transaction_str),
+                },
+            };
 
-        info!(
-            "TRANS2QUIK_SUBSCRIBE_ORDERS -> {:?}, class_code: {}, sec_code: {}",
-            trans2quik_result, class_code, sec_code
-        );
+            results.push((index + 1, result));
+        }
 
-        Ok(trans2quik_result)
+        Ok(results)
     }
 
-    /// The function is used to create a list of classes and tools for subscribing to receive trades on them.
-    pub fn subscribe_trades(
+    /**
+    Sends the asynchronous transaction, retrying up to `retries` times while the library returns
+    `Trans2QuikResult::QuikNotConnected` or `Trans2QuikResult::DllNotConnected`,
+    e.g. during the reconnection of the terminal. The delays before the retries start from `backoff`,
+    double every time and are randomized by up to a half to spread the retries of the strategies.
+    Returns the result of the last attempt.
+
+    Retrying NEW_ORDER can place the duplicate order if the first attempt has actually
+    reached the server. Keep the same TRANS_ID for all the attempts and check the orders
+    by TRANS_ID before sending the transaction anew.
+    */
+    pub async fn send_async_transaction_retry(
         &self,
-        class_code: &str,
-        sec_code: &str,
+        transaction_str: &str,
+        retries: u32,
+        backoff: Duration,
     ) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let class_code_c = CString::new(class_code)?;
-        let class_code_ptr = class_code_c.as_ptr() as *mut c_char;
+        let mut delay = backoff;
+        let mut attempt = 0;
 
-        let sec_code_c = CString::new(sec_code)?;
-        let sec_code_ptr = sec_code_c.as_ptr() as *mut c_char;
+        loop {
+            let trans2quik_result = self.send_async_transaction_limited(transaction_str).await?;
 
-        let function_result =
-            unsafe { (self.trans2quik_subscribe_trades)(class_code_ptr, sec_code_ptr) };
+            let not_connected = matches!(
+                trans2quik_result,
+                Trans2QuikResult::QuikNotConnected | Trans2QuikResult::DllNotConnected
+            );
+            if !not_connected || attempt == retries {
+                return Ok(trans2quik_result);
+            }
 
-        let trans2quik_result = Trans2QuikResult::from(function_result);
+            let jittered_delay = jitter(delay);
+            info!(
+                "{:?}, retrying the transaction in {:?} ({}/{})",
+                trans2quik_result,
+                jittered_delay,
+                attempt + 1,
+                retries
+            );
+            tokio::time::sleep(jittered_delay).await;
+            delay = delay.saturating_mul(2);
+            attempt += 1;
+        }
+    }
 
-        info!(
-            "TRANS2QUIK_SUBSCRIBE_TRADES -> {:?}, class_code: {}, sec_code: {}",
-            trans2quik_result, class_code, sec_code
-        );
+    /// The time since the last call of any callback function,
+    /// `None` if no callback function has been called yet.
+    pub fn last_callback_age(&self) -> Option<Duration> {
+        watchdog::last_callback_age()
+    }
 
-        Ok(trans2quik_result)
+    /**
+    Starts the watchdog calling `on_stall` with the idle time when no callback function
+    is called within `max_idle`, e.g. when the library Trans2QUIK.dll hangs.
+    The handler is called once per stall from the thread of the watchdog.
+    Replaces the watchdog set before.
+
+    # Example of use
+    ```ignore
+    terminal.set_callback_watchdog_hours(Some((
+        NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+        NaiveTime::from_hms_opt(18, 50, 0).unwrap(),
+    )));
+    terminal.set_callback_watchdog(Duration::from_secs(60), |idle| {
+        error!("no callbacks for {:?}", idle);
+    });
+    ```
+    */
+    pub fn set_callback_watchdog<F>(&self, max_idle: Duration, on_stall: F)
+    where
+        F: Fn(Duration) + Send + 'static,
+    {
+        watchdog::start(max_idle, on_stall);
     }
 
-    /// The function starts the process of receiving requests for classes and tools defined
-    /// by the TRANS2QUIK_SUBSCRIBE_ORDERS function.
-    pub fn start_orders(&self) {
-        unsafe { (self.trans2quik_start_orders)(order_status_callback) }
+    /// The market hours in the local time, the watchdog doesn't report the stalls outside of them.
+    /// The end can be before the start for the session passing midnight, `None` for all day.
+    pub fn set_callback_watchdog_hours(&self, market_hours: Option<(NaiveTime, NaiveTime)>) {
+        watchdog::set_market_hours(market_hours);
     }
 
-    /// The function starts the process of receiving transactions with the parameters set
-    /// by the function TRANS2QUIK_SUBSCRIBE_TRADES.
-    pub fn start_trades(&self) {
-        let terminal_clone = (*self).clone();
-        let terminal_instance = Arc::new(Mutex::new(terminal_clone));
-        *TERMINAL_INSTANCE.lock().unwrap() = Some(terminal_instance);
+    pub fn stop_callback_watchdog(&self) {
+        watchdog::stop();
+    }
 
-        unsafe { (self.trans2quik_start_trades)(trade_status_callback) }
+    /// The number of the asynchronous transactions sent but not replied yet.
+    /// Only the transactions with the TRANS_ID parameter sent while the transaction_reply_callback
+    /// is set are counted.
+    pub fn pending_transactions(&self) -> usize {
+        PENDING_TRANS_IDS.lock().unwrap().len()
     }
 
-    /// The function interrupts the operation of the TRANS2QUIK_START_ORDERS function and clears
-    /// the list of received tools generated by the function
-    /// TRANS2QUIK_SUBSCRIBE_ORDERS.
-    pub fn unsubscribe_orders(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let function_result = unsafe { (self.trans2quik_unsubscribe_orders)() };
+    /// Waits until all the asynchronous transactions sent receive the transaction replies,
+    /// returns `Trans2QuikError::Timeout` if they don't within the `timeout`.
+    /// Call it before disconnecting so that the orders in flight are not abandoned.
+    pub async fn drain_pending(&self, timeout: Duration) -> Result<(), Trans2QuikError> {
+        let drained = async {
+            loop {
+                let notified = PENDING_TRANS_IDS_NOTIFY.notified();
 
-        let trans2quik_result = Trans2QuikResult::from(function_result);
+                if PENDING_TRANS_IDS.lock().unwrap().is_empty() {
+                    return;
+                }
 
-        info!("TRANS2QUIK_UNSUBSCRIBE_ORDERS -> {:?}", trans2quik_result);
+                notified.await;
+            }
+        };
 
-        Ok(trans2quik_result)
+        tokio::time::timeout(timeout, drained)
+            .await
+            .map_err(|_| Trans2QuikError::Timeout)
     }
 
-    /// The function interrupts the operation of the TRANS2QUIK_START_TRADES function and clears
-    /// the list of received tools generated by the function
-    /// TRANS2QUIK_SUBSCRIBE_TRADES.
-    pub fn unsubscribe_trades(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
-        let function_result = unsafe { (self.trans2quik_unsubscribe_trades)() };
-
-        let trans2quik_result = Trans2QuikResult::from(function_result);
-
-        info!("TRANS2QUIK_UNSUBSCRIBE_TRADES -> {:?}", trans2quik_result);
+    /**
+    Waits until the existing orders replayed by `Terminal::start_orders` are received,
+    i.e. the order with `Mode::LastOrderReceived` is sent to the channels.
+    The following orders are the live updates. Resolves immediately if the initial orders
+    are already received.
+
+    # Example of use
+    ```ignore
+    terminal.start_orders();
+    terminal.orders_snapshot_complete().await;
+    // The state built from the initial orders is complete.
+    ```
+    */
+    pub async fn orders_snapshot_complete(&self) {
+        loop {
+            let notified = ORDERS_SNAPSHOT_NOTIFY.notified();
+
+            if ORDERS_SNAPSHOT_COMPLETE.load(Ordering::SeqCst) {
+                return;
+            }
 
-        Ok(trans2quik_result)
+            notified.await;
+        }
     }
-}
 
-/// Loads the symbol from the library Trans2QUIK.dll
-fn load_symbol<T>(library: &Library, name: &[u8]) -> Result<T, LibloadingError>
-where
-    T: Copy,
-{
-    unsafe {
-        let symbol: Symbol<T> = library.get(name)?;
-        Ok(*symbol)
+    /**
+    Subscribes to the orders of the instruments `(class_code, sec_code)`, starts receiving them
+    and waits for the existing orders. Returns the existing orders, received with
+    `Mode::InitialOrder` and `Mode::LastOrderReceived`, and the stream of the following orders.
+    The new orders received before the last existing order start the stream.
+
+    The terminal must receive the callback functions, see `Terminal::start_trades`.
+    The stream skips the orders dropped by the lag of the broadcast channel,
+    see `Terminal::subscribe_orders_broadcast`.
+
+    # Example of use
+    ```ignore
+    let (orders, mut updates) = terminal.subscribe_and_snapshot(&[("TQBR", "SBER")]).await?;
+    let mut book = OrderBook::from(orders);
+    while let Some(order_info) = updates.next().await {
+        book.update(order_info);
     }
-}
+    ```
+    */
+    pub async fn subscribe_and_snapshot(
+        &self,
+        instruments: &[(&str, &str)],
+    ) -> Result<(Vec<OrderInfo>, impl Stream<Item = OrderInfo>), Trans2QuikError> {
+        // Subscribed before starting, so the first orders are not missed.
+        let mut receiver = ORDER_STATUS_BROADCAST.subscribe();
 
-/// Extract String from `Vec<i8>`.
-fn extract_string_from_vec(vec_i8: Vec<i8>) -> Result<String, FromUtf8Error> {
-    let vec_u8: Vec<u8> = vec_i8.into_iter().map(|byte| byte as u8).collect();
+        for (class_code, sec_code) in instruments {
+            self.subscribe_orders(class_code, sec_code)?;
+        }
+        self.start_orders();
 
-    let null_pos = vec_u8
-        .iter()
-        .position(|&byte| byte == 0)
-        .unwrap_or(vec_u8.len());
+        let mut snapshot = Vec::new();
+        let mut new_orders = Vec::new();
 
-    let vec_u8_trimmed = &vec_u8[..null_pos];
-
-    let (decoded_str, _, _) = WINDOWS_1251.decode(vec_u8_trimmed);
+        loop {
+            match receiver.recv().await {
+                Ok(order_info) => match order_info.mode {
+                    Mode::InitialOrder => snapshot.push(order_info),
+                    Mode::LastOrderReceived => {
+                        snapshot.push(order_info);
+                        break;
+                    }
+                    _ => new_orders.push(order_info),
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("subscribe_and_snapshot skipped {} orders", skipped);
+                }
+                // The sender is static and never dropped.
+                Err(broadcast::error::RecvError::Closed) => unreachable!(),
+            }
+        }
 
-    Ok(decoded_str.into_owned())
-}
+        let updates = BroadcastStream::new(receiver).filter_map(|order_info| match order_info {
+            Ok(order_info) => Some(order_info),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                warn!("subscribe_and_snapshot stream skipped {} orders", skipped);
+                None
+            }
+        });
 
-fn decode_lpstr(code: *mut c_char) -> Result<String, DecodeLpstrError> {
-    if code.is_null() {
-        return Err(DecodeLpstrError::NullPointer);
+        Ok((snapshot, tokio_stream::iter(new_orders).chain(updates)))
     }
 
-    // Safe block to convert C string to Rust slice
-    let c_str = unsafe { CStr::from_ptr(code) };
+    /// Sends the asynchronous transaction and waits for its transaction reply.
+    async fn send_async_transaction_and_wait(
+        &self,
+        transaction_str: &str,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        let (trans_id, reply) = self
+            .send_async_transaction_for_reply(transaction_str)
+            .await?;
 
-    // Attempt to convert the C string to bytes
-    let bytes = c_str.to_bytes();
+        wait_for_reply(trans_id, reply).await
+    }
 
-    // Decode the bytes using WINDOWS-1251 encoding
-    let (decoded_str, _, had_errors) = WINDOWS_1251.decode(bytes);
+    /// Sends the asynchronous transaction, returns its TRANS_ID and the receiver of its
+    /// transaction reply, see `wait_for_reply`.
+    async fn send_async_transaction_for_reply(
+        &self,
+        transaction_str: &str,
+    ) -> Result<(c_long, oneshot::Receiver<TransactionInfo>), Trans2QuikError> {
+        let trans_id = transaction_param(transaction_str, "TRANS_ID")
+            .and_then(|trans_id| trans_id.parse::<c_long>().ok())
+            .ok_or(TransactionBuildError::MissingParam("TRANS_ID"))?;
+
+        let (waiter, reply) = oneshot::channel();
+        {
+            let mut reply_waiters = REPLY_WAITERS.lock().unwrap();
+
+            // The waiter whose future is dropped no longer waits.
+            if let Some(other) = reply_waiters.get(&trans_id) {
+                if !other.is_closed() {
+                    error!("TRANS_ID={} is already waiting for the reply", trans_id);
+                    // c_long is i32 on Windows only.
+                    #[allow(clippy::unnecessary_cast)]
+                    return Err(Trans2QuikError::DuplicateTransId(trans_id as i32));
+                }
+            }
 
-    // Check for decoding errors
-    if had_errors {
-        return Err(DecodeLpstrError::DecodeError);
-    }
+            reply_waiters.insert(trans_id, waiter);
+        }
 
-    // Convert the Cow<str> to String and return
-    Ok(decoded_str.into_owned())
-}
+        let trans2quik_result = match self.send_async_transaction_limited(transaction_str).await {
+            Ok(trans2quik_result) => trans2quik_result,
+            Err(err) => {
+                REPLY_WAITERS.lock().unwrap().remove(&trans_id);
+                return Err(err);
+            }
+        };
 
-fn format_date(date: i32) -> Result<NaiveDate, DateTimeError> {
-    if date <= 0 {
-        return Err(DateTimeError::InvalidDate);
+        if trans2quik_result != Trans2QuikResult::Success {
+            REPLY_WAITERS.lock().unwrap().remove(&trans_id);
+            return Err(Trans2QuikError::NotSent(trans2quik_result));
+        }
+
+        Ok((trans_id, reply))
     }
 
-    let date_str = format!("{:08}", date);
+    /// Sends the asynchronous transaction and waits for its transaction reply, like
+    /// `replace_order`, unless the transaction with the same `key` is being sent or was sent
+    /// within the window, see `Terminal::set_idempotency_window`. Then the transaction is not sent
+    /// again and the reply of the first one is returned, so the application can safely retry
+    /// the sending, e.g. after the restart of its task.
+    ///
+    /// If the reply of the first transaction didn't arrive, the transaction may have been executed
+    /// and the duplicates fail with `Trans2QuikError::Timeout` within the window. The same applies
+    /// if the future of the first transaction is dropped after sending it, e.g. by a timeout
+    /// of the caller. The key of the transaction not sent, e.g. rejected by the library,
+    /// is forgotten.
+    pub async fn send_idempotent(
+        &self,
+        key: &str,
+        transaction: TransactionBuilder,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        let transaction_str = transaction.build()?;
+
+        let sender = loop {
+            let begin = self
+                .idempotency_keys
+                .lock()
+                .unwrap()
+                .begin(key, Instant::now());
+
+            match begin {
+                Begin::Send(sender) => break sender,
+                Begin::Wait(mut receiver) => {
+                    // Notified when the first sending completes or its future is dropped.
+                    let _ = receiver.changed().await;
+                }
+                Begin::Completed(transaction_info) => {
+                    info!("the transaction with the key {} is already sent", key);
+                    return Ok(*transaction_info);
+                }
+                Begin::TimedOut => {
+                    error!("the transaction with the key {} timed out", key);
+                    return Err(Trans2QuikError::Timeout);
+                }
+            }
+        };
 
-    let naive_date = NaiveDate::parse_from_str(&date_str, "%Y%m%d")?;
+        // Updates the key even if this future is dropped.
+        let mut sending = Sending::new(&self.idempotency_keys, key, sender);
 
-    Ok(naive_date)
-}
+        let result = match self
+            .send_async_transaction_for_reply(&transaction_str)
+            .await
+        {
+            Ok((trans_id, reply)) => {
+                sending.sent();
+                wait_for_reply(trans_id, reply).await
+            }
+            Err(err) => Err(err),
+        };
 
-fn format_time(time: i32) -> Result<NaiveTime, DateTimeError> {
-    if time <= 0 {
-        return Err(DateTimeError::InvalidTime);
+        sending.finish(&result);
+
+        result
     }
 
-    let time_str = format!("{:06}", time);
+    /// Replaces the order: cancels the order `old_order_num` with the KILL_ORDER transaction
+    /// and, after the reply that the cancellation is executed, sends the new order.
+    /// If the cancellation is not executed the new order is not sent.
+    /// Returns the transaction replies of the cancellation and of the new order.
+    pub async fn replace_order(
+        &self,
+        old_order_num: u64,
+        new: TransactionBuilder,
+    ) -> Result<(TransactionInfo, Option<TransactionInfo>), Trans2QuikError> {
+        let new_order_str = new.build()?;
+
+        let class_code = new
+            .get("CLASSCODE")
+            .ok_or(TransactionBuildError::MissingParam("CLASSCODE"))?;
+        let sec_code = new
+            .get("SECCODE")
+            .ok_or(TransactionBuildError::MissingParam("SECCODE"))?;
+
+        let kill_order_str = self
+            .transaction(Action::KillOrder)
+            .trans_id(next_trans_id())
+            .class_code(class_code)
+            .sec_code(sec_code)
+            .order_key(old_order_num)
+            .build()?;
+
+        let cancel = self
+            .send_async_transaction_and_wait(&kill_order_str)
+            .await?;
+
+        if !cancel.is_executed() {
+            error!(
+                "order {} is not cancelled, the new order is not sent: {}",
+                old_order_num, cancel.reply_message
+            );
+            return Ok((cancel, None));
+        }
 
-    let naive_time = NaiveTime::parse_from_str(&time_str, "%H%M%S")?;
+        let replacement = self.send_async_transaction_and_wait(&new_order_str).await?;
 
-    Ok(naive_time)
-}
+        Ok((cancel, Some(replacement)))
+    }
 
-/// Callback function for status monitoring connections.
-unsafe extern "C" fn connection_status_callback(
-    connection_event: c_long,
-    error_code: c_long,
-    error_message: *mut c_char,
-) {
-    let error_message = if !error_message.is_null() {
-        let c_str = CStr::from_ptr(error_message);
-        let bytes = c_str.to_bytes();
+    /**
+    Emergency closing of the positions: cancels all the orders in every instrument
+    `(class_code, sec_code)` of `instruments`, then sends the market order offsetting
+    every position `(class_code, sec_code, lots)` of `positions`, selling the long positions
+    and buying the short ones. The orders use the default account of the terminal.
+
+    Every transaction waits for its reply, and the failure of one doesn't stop the others.
+    Returns the results in the order of sending: the cancellations, then the market orders
+    of the non-zero positions.
+
+    # Example of use
+    ```ignore
+    let results = terminal
+        .flatten(&[("TQBR", "SBER")], &[("TQBR", "SBER", 10), ("TQBR", "GAZP", -5)])
+        .await;
+    ```
+    */
+    pub async fn flatten(
+        &self,
+        instruments: &[(&str, &str)],
+        positions: &[(&str, &str, i64)],
+    ) -> Vec<Result<TransactionInfo, Trans2QuikError>> {
+        let mut results = Vec::new();
+
+        for (class_code, sec_code) in instruments {
+            let kill_all_orders = self
+                .transaction(Action::KillAllOrders)
+                .trans_id(next_trans_id())
+                .class_code(class_code)
+                .sec_code(sec_code);
+
+            results.push(self.flatten_step(kill_all_orders).await);
+        }
 
-        let (decoded_str, _, _) = WINDOWS_1251.decode(bytes);
-        decoded_str.into_owned().to_owned()
-    } else {
-        String::from("error_message is null")
-    };
+        for (class_code, sec_code, lots) in positions {
+            let operation = match lots.signum() {
+                1 => IsSell::Sell,
+                -1 => IsSell::Buy,
+                _ => continue,
+            };
 
-    let trans2quik_result = Trans2QuikResult::from(connection_event);
+            let market_order = self
+                .transaction(Action::NewOrder)
+                .trans_id(next_trans_id())
+                .class_code(class_code)
+                .sec_code(sec_code)
+                .operation(operation)
+                .market()
+                .quantity(lots.saturating_abs());
 
-    info!(
-        "TRANS2QUIK_CONNECTION_STATUS_CALLBACK -> {:?}, error_code: {}, error_message: {}",
-        trans2quik_result, error_code, error_message
-    );
-}
+            results.push(self.flatten_step(market_order).await);
+        }
 
-/// Callback function for processing the received transaction information.
-/// Attention! The submission of asynchronous transactions using
-/// the callback function and synchronous transactions at the same time is prohibited.
-/// This is due to the fact that it is impossible to correctly call
-/// the callback function at a time when the synchronous transaction processing function has
-/// not finished its work yet.
-unsafe extern "C" fn transaction_reply_callback(
-    result_code: c_long,
-    error_code: c_long,
-    reply_code: c_long,
-    trans_id: c_long,
-    order_num: c_ulonglong,
-    reply_message: *mut c_char,
-    trans_reply_descriptor: intptr_t,
-) {
-    if let Some(terminal_instance) = TERMINAL_INSTANCE.lock().unwrap().as_ref() {
-        let terminal = terminal_instance.lock().unwrap();
+        results
+    }
 
-        let trans2quik_result = Trans2QuikResult::from(result_code);
+    async fn flatten_step(
+        &self,
+        transaction: TransactionBuilder,
+    ) -> Result<TransactionInfo, Trans2QuikError> {
+        let transaction_str = transaction.build()?;
+        let result = self.send_async_transaction_and_wait(&transaction_str).await;
 
-        let trans_id = TransId::from(trans_id);
+        if let Err(err) = &result {
+            error!("flatten: {} -> {}", transaction_str, err);
+        }
 
-        let reply_message = match decode_lpstr(reply_message) {
-            Ok(reply_message) => reply_message,
-            Err(e) => {
-                let error = format!("decode reply_message error: {:?}", e);
-                error!("{}", error);
-                error
-            }
+        result
+    }
+
+    /**
+    Waits for the first state of the order `order_num` received by the order_status_callback,
+    including the state received shortly before the call.
+    Returns `Trans2QuikError::OrderCanceled` if the order is cancelled before becoming active,
+    e.g. rejected by the exchange, and `Trans2QuikError::Timeout` if no state is received
+    within the `timeout`. The executed order is returned as is.
+
+    # Example of use
+    ```ignore
+    let reply = terminal.send_async_transaction_and_wait(&transaction_str).await?;
+    let order_info = terminal.wait_for_order(reply.order_num, Duration::from_secs(5)).await?;
+    ```
+    */
+    pub async fn wait_for_order(
+        &self,
+        order_num: u64,
+        timeout: Duration,
+    ) -> Result<OrderInfo, Trans2QuikError> {
+        self.wait_for_order_state(order_num, timeout, |_| true)
+            .await
+    }
+
+    /// Waits for the first state of the order `order_num` matching the `predicate`,
+    /// see `Terminal::wait_for_order`.
+    async fn wait_for_order_state<P>(
+        &self,
+        order_num: u64,
+        timeout: Duration,
+        predicate: P,
+    ) -> Result<OrderInfo, Trans2QuikError>
+    where
+        P: Fn(&OrderInfo) -> bool,
+    {
+        let matches = |order_info: &OrderInfo| {
+            order_info.order_num == order_num
+                && (order_info.status == Status::Canceled || predicate(order_info))
         };
 
-        let sec_code = (terminal.trans2quik_transaction_reply_sec_code)(trans_reply_descriptor);
+        // Subscribed before looking at the recent orders so that no state is missed.
+        let mut orders = ORDER_STATUS_BROADCAST.subscribe();
+
+        let recent = RECENT_ORDERS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|order_info| matches(order_info))
+            .cloned();
+
+        let order_info = match recent {
+            Some(order_info) => order_info,
+            None => {
+                let matching = async {
+                    loop {
+                        match orders.recv().await {
+                            Ok(order_info) if matches(&order_info) => return Ok(order_info),
+                            Ok(_) => {}
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!("wait_for_order skipped {} orders", skipped);
+                            }
+                            Err(broadcast::error::RecvError::Closed) => {
+                                return Err(Trans2QuikError::Timeout)
+                            }
+                        }
+                    }
+                };
 
-        let sec_code = match decode_lpstr(sec_code) {
-            Ok(sec_code) => sec_code,
-            Err(e) => {
-                let error = format!("decode sec_code error: {:?}", e);
-                error!("{}", error);
-                error
+                tokio::time::timeout(timeout, matching)
+                    .await
+                    .map_err(|_| Trans2QuikError::Timeout)??
             }
         };
 
-        let price = (terminal.trans2quik_transaction_reply_price)(trans_reply_descriptor);
-
-        info!("TRANS2QUIK_TRANSACTION_REPLY_CALLBACK -> {:?}, error_code: {}, reply_code: {}, trans_id: {:?}, order_num: {}, reply_message: {}, sec_code: {}, price: {}", trans2quik_result, error_code, reply_code, trans_id, order_num, reply_message, sec_code, price);
+        if order_info.status == Status::Canceled {
+            return Err(Trans2QuikError::OrderCanceled(order_num));
+        }
 
-        if let Some(sender) = TRANSACTION_REPLY_SENDER.lock().unwrap().as_ref() {
-            let transaction_info = TransactionInfo {
-                trans2quik_result,
-                error_code,
-                reply_code,
-                trans_id,
-                order_num,
-                reply_message,
-                sec_code,
-                price,
-            };
+        Ok(order_info)
+    }
 
-            if let Err(err) = sender.send(transaction_info) {
-                error!("transaction_reply_callback send error: {}", err);
-            }
-        } else {
-            error!("TRANSACTION_REPLY_SENDER is not initialized");
-        }
-    } else {
-        error!("TERMINAL_INSTANCE is not initialized");
+    /**
+    Sends the transaction synchronously for the immediate validation of the QUIK server,
+    and returns its reply together with the future of the execution report:
+    the state of the placed order when it is executed. The future returns
+    `Trans2QuikError::OrderCanceled` if the order is cancelled, `Trans2QuikError::OrderNotPlaced`
+    if the transaction placed no order, and `Trans2QuikError::Timeout` after the `timeout`.
+
+    The execution report is received by the order_status_callback, see `Terminal::start_orders`.
+    The library Trans2QUIK.dll doesn't allow to use the synchronous transactions together with the
+    transaction_reply_callback.
+
+    # Example of use
+    ```ignore
+    let (reply, execution) =
+        terminal.send_sync_transaction_with_report(&transaction_str, Duration::from_secs(60))?;
+    if !reply.reply_code.is_rejected() {
+        let order_info = execution.await?;
     }
-}
+    ```
+    */
+    pub fn send_sync_transaction_with_report(
+        &self,
+        transaction_str: &str,
+        timeout: Duration,
+    ) -> Result<
+        (
+            TransactionInfo,
+            impl Future<Output = Result<OrderInfo, Trans2QuikError>> + '_,
+        ),
+        Trans2QuikError,
+    > {
+        let transaction_info = self.send_sync_reply(transaction_str, WINDOWS_1251, false)?;
+
+        if transaction_info.trans2quik_result != Trans2QuikResult::Success {
+            return Err(Trans2QuikError::NotSent(transaction_info.trans2quik_result));
+        }
 
-/// Callback function to get information about the order parameters.
-unsafe extern "C" fn order_status_callback(
-    mode: c_long,
-    trans_id: c_long,
-    order_num: c_ulonglong,
-    class_code: *mut c_char,
-    sec_code: *mut c_char,
-    price: c_double,
-    balance: i64,
-    value: c_double,
-    is_sell: c_long,
-    status: c_long,
-    order_descriptor: intptr_t,
-) {
-    if let Some(terminal_instance) = TERMINAL_INSTANCE.lock().unwrap().as_ref() {
-        let terminal = terminal_instance.lock().unwrap();
+        let order_num = transaction_info.order_num;
+        let trans_id = transaction_info.trans_id.as_i32();
 
-        let mode = Mode::from(mode);
+        let execution = async move {
+            if order_num == 0 {
+                return Err(Trans2QuikError::OrderNotPlaced(trans_id));
+            }
 
-        let trans_id = TransId::from(trans_id);
+            let order_info = self
+                .wait_for_order_state(order_num, timeout, |order_info| {
+                    order_info.status == Status::Executed
+                })
+                .await?;
 
-        let class_code = match decode_lpstr(class_code) {
-            Ok(class_code) => class_code,
-            Err(e) => {
-                let error = format!("decode class_code error: {:?}", e);
-                error!("{}", error);
-                error
+            if order_info.status == Status::Canceled {
+                return Err(Trans2QuikError::OrderCanceled(order_num));
             }
-        };
 
-        let sec_code = match decode_lpstr(sec_code) {
-            Ok(sec_code) => sec_code,
-            Err(e) => {
-                let error = format!("decode sec_code error: {:?}", e);
-                error!("{}", error);
-                error
-            }
+            Ok(order_info)
         };
 
-        let is_sell = IsSell::from(is_sell);
+        Ok((transaction_info, execution))
+    }
 
-        let status = Status::from(status);
+    /**
+    Sends the order and cancels it if it is not executed within the `ttl`, e.g. for the
+    time-sliced execution. Waits for the transaction reply with the order number and returns it
+    together with the task cancelling the order. The task returns the reply of the KILL_ORDER
+    transaction, or `None` if the order is executed or cancelled before the `ttl`.
+    Returns `Trans2QuikError::OrderNotPlaced` if the transaction placed no order.
 
-        let date = (terminal.trans2quik_order_date)(order_descriptor);
+    The state of the order is received by the order_status_callback, see `Terminal::start_orders`.
 
-        let date = match format_date(date) {
-            Ok(date) => date,
-            Err(e) => {
-                error!("format_date error: {}", e);
-                NaiveDate::default()
-            }
-        };
+    # Example of use
+    ```ignore
+    let (reply, cancel) = terminal.send_order_with_ttl(order, Duration::from_secs(30)).await?;
+    if let Some(cancel) = cancel.await.unwrap()? {
+        // The order is not executed in 30 seconds.
+    }
+    ```
+    */
+    pub async fn send_order_with_ttl(
+        &self,
+        order: TransactionBuilder,
+        ttl: Duration,
+    ) -> Result<
+        (
+            TransactionInfo,
+            JoinHandle<Result<Option<TransactionInfo>, Trans2QuikError>>,
+        ),
+        Trans2QuikError,
+    > {
+        let order_str = order.build()?;
+
+        let class_code = order
+            .get("CLASSCODE")
+            .ok_or(TransactionBuildError::MissingParam("CLASSCODE"))?
+            .to_string();
+        let sec_code = order
+            .get("SECCODE")
+            .ok_or(TransactionBuildError::MissingParam("SECCODE"))?
+            .to_string();
+
+        let reply = self.send_async_transaction_and_wait(&order_str).await?;
+
+        if reply.order_num == 0 {
+            return Err(Trans2QuikError::OrderNotPlaced(reply.trans_id.as_i32()));
+        }
 
-        let time = (terminal.trans2quik_order_time)(order_descriptor);
+        let order_num = reply.order_num;
+        let terminal = self.clone();
 
-        let time = match format_time(time) {
-            Ok(time) => time,
-            Err(e) => {
-                error!("format_time error: {}", e);
-                NaiveTime::default()
+        let cancel = tokio::spawn(async move {
+            let executed = terminal
+                .wait_for_order_state(order_num, ttl, |order_info| {
+                    order_info.status == Status::Executed
+                })
+                .await;
+
+            match executed {
+                Ok(_) | Err(Trans2QuikError::OrderCanceled(_)) => return Ok(None),
+                Err(Trans2QuikError::Timeout) => {}
+                Err(err) => return Err(err),
             }
-        };
 
-        info!("TRANS2QUIK_ORDER_STATUS_CALLBACK -> mode: {:?}, trans_id: {:?}, order_num: {}, class_code: {}, sec_code: {}, price: {}, balance: {}, value: {}, is_sell: {:?}, status: {:?}, date: {}, time: {}", mode, trans_id, order_num, class_code, sec_code, price, balance, value, is_sell, status, date, time);
+            info!(
+                "order {} is not executed in {:?}, cancelling it",
+                order_num, ttl
+            );
+
+            let kill_order_str = terminal
+                .transaction(Action::KillOrder)
+                .trans_id(next_trans_id())
+                .class_code(&class_code)
+                .sec_code(&sec_code)
+                .order_key(order_num)
+                .build()?;
+
+            terminal
+                .send_async_transaction_and_wait(&kill_order_str)
+                .await
+                .map(Some)
+        });
+
+        Ok((reply, cancel))
+    }
 
-        if let Some(sender) = ORDER_STATUS_SENDER.lock().unwrap().as_ref() {
-            let order_info = OrderInfo {
-                mode,
-                trans_id,
-                order_num,
-                class_code,
-                sec_code,
-                price,
-                balance,
-                value,
-                is_sell,
-                status,
-                date,
-                time,
-            };
+    /// Cancels the order placed by the asynchronous transaction with the given TRANS_ID.
+    /// If the transaction reply with the order number has not arrived yet, waits for it
+    /// up to 5 seconds, then sends the KILL_ORDER transaction asynchronously.
+    /// Only the last 1024 NEW_ORDER transactions are remembered, `Trans2QuikError::UnknownTransId`
+    /// is returned for the others and for the transactions which replied without the order.
+    pub async fn cancel_by_trans_id(
+        &self,
+        trans_id: i32,
+    ) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let (class_code, sec_code, order_num) =
+            tokio::time::timeout(REPLY_TIMEOUT, wait_for_order_num(trans_id))
+                .await
+                .map_err(|_| Trans2QuikError::Timeout)??;
+
+        let transaction_str = TransactionBuilder::new(Action::KillOrder)
+            .trans_id(next_trans_id())
+            .class_code(&class_code)
+            .sec_code(&sec_code)
+            .order_key(order_num)
+            .build()?;
+
+        let trans2quik_result = self
+            .send_async_transaction_limited(&transaction_str)
+            .await?;
+
+        if trans2quik_result == Trans2QuikResult::Success {
+            SENT_ORDERS.lock().unwrap().remove(&(trans_id as c_long));
+        }
 
-            if let Err(err) = sender.send(order_info) {
-                error!("order_status_callback send error: {}", err);
-            }
-        } else {
-            error!("ORDER_SENDER is not initialized");
+        Ok(trans2quik_result)
+    }
+
+    /// А callback function for processing the received connection information.
+    /// The callback function already set by the terminal or its clones is not set again,
+    /// some versions of the library Trans2QUIK.dll would call it twice per event.
+    /// Returns `Trans2QuikResult::Success` then, see `Terminal::force_connection_status_callback`.
+    pub fn set_connection_status_callback(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+        if self.callback_state.lock().unwrap().connection_status {
+            info!("TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK -> already set");
+            return Ok(Trans2QuikResult::Success);
         }
-    } else {
-        error!("TERMINAL_INSTANCE is not initialized");
+
+        self.force_connection_status_callback()
     }
-}
 
-/// Callback function to get information about the transaction.
-unsafe extern "C" fn trade_status_callback(
-    mode: c_long,
-    trade_num: c_ulonglong,
-    order_num: c_ulonglong,
-    class_code: *mut c_char,
-    sec_code: *mut c_char,
-    price: c_double,
-    quantity: i64,
-    is_sell: c_long,
-    value: c_double,
-    trade_descriptor: intptr_t,
-) {
-    if let Some(terminal_instance) = TERMINAL_INSTANCE.lock().unwrap().as_ref() {
-        let terminal = terminal_instance.lock().unwrap();
+    /// Sets the callback function for processing the received connection information
+    /// even if it's already set, unlike `Terminal::set_connection_status_callback`.
+    pub fn force_connection_status_callback(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let mut error_code: c_long = 0;
+        let error_code_ptr = &mut error_code as *mut c_long;
 
-        let mode = Mode::from(mode);
+        let mut error_message = vec![0 as c_char; 256];
+        let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
 
-        let class_code = match decode_lpstr(class_code) {
-            Ok(class_code) => class_code,
-            Err(e) => {
-                let error = format!("decode class_code error: {:?}", e);
-                error!("{}", error);
-                error
-            }
+        let function_result = unsafe {
+            (self.trans2quik_set_connection_status_callback)(
+                connection_status_callback,
+                error_code_ptr,
+                error_message_ptr,
+                error_message.len() as c_long,
+            )
         };
 
-        let sec_code = match decode_lpstr(sec_code) {
-            Ok(sec_code) => sec_code,
+        let error_message = match extract_string_from_vec(error_message, WINDOWS_1251) {
+            Ok(message) => message,
             Err(e) => {
-                let error = format!("decode sec_code error: {:?}", e);
-                error!("{}", error);
-                error
+                error!("Warning: error_message contains invalid UTF-8: {}", e);
+                String::from("Invalid UTF-8 in error_message")
             }
         };
 
-        let is_sell = IsSell::from(is_sell);
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+        self.record_error(&trans2quik_result, error_code, &error_message);
+        if trans2quik_result == Trans2QuikResult::Success {
+            self.callback_state.lock().unwrap().connection_status = true;
+        }
 
-        let date = (terminal.trans2quik_trade_date)(trade_descriptor);
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK -> {:?}, error_code: {}, error_message: {}",
+                trans2quik_result,
+                ErrorCode::from(error_code),
+                error_message
+            ),
+        );
 
-        let date = match format_date(date) {
-            Ok(date) => date,
-            Err(e) => {
-                error!("format_date error: {}", e);
-                NaiveDate::default()
-            }
-        };
+        Ok(trans2quik_result)
+    }
+
+    /// Sets the callback function to receive information about the sent asynchronous transaction.
+    pub fn set_transactions_reply_callback(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let mut error_code: c_long = 0;
+        let error_code_ptr = &mut error_code as *mut c_long;
 
-        let time = (terminal.trans2quik_trade_time)(trade_descriptor);
+        let mut error_message = vec![0 as c_char; 256];
+        let error_message_ptr = error_message.as_mut_ptr() as *mut c_char;
 
-        let time = match format_time(time) {
-            Ok(time) => time,
+        let function_result = unsafe {
+            (self.trans2quik_set_transactions_reply_callback)(
+                transaction_reply_callback,
+                error_code_ptr,
+                error_message_ptr,
+                error_message.len() as c_long,
+            )
+        };
+
+        let error_message = match extract_string_from_vec(error_message, WINDOWS_1251) {
+            Ok(message) => message,
             Err(e) => {
-                error!("format_time error: {}", e);
-                NaiveTime::default()
+                error!("Warning: error_message contains invalid UTF-8: {}", e);
+                String::from("Invalid UTF-8 in error_message")
             }
         };
 
-        info!("TRANS2QUIK_TRADE_STATUS_CALLBACK -> mode: {:?}, trade_num: {}, order_num: {}, class_code: {}, sec_code: {}, price: {}, quantity: {}, is_sell: {:?}, value: {}, date: {}, time: {}", mode, trade_num, order_num, class_code, sec_code, price, quantity, is_sell, value, date, time);
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+        self.record_error(&trans2quik_result, error_code, &error_message);
+        if trans2quik_result == Trans2QuikResult::Success {
+            self.callback_state.lock().unwrap().transaction_reply = true;
+        }
 
-        if let Some(sender) = TRADE_STATUS_SENDER.lock().unwrap().as_ref() {
-            let trade_info = TradeInfo {
-                mode,
-                trade_num,
-                order_num,
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK -> {:?}, error_code: {}, error_message: {}",
+                trans2quik_result,
+                ErrorCode::from(error_code),
+                error_message
+            ),
+        );
+
+        Ok(trans2quik_result)
+    }
+
+    /// The function is used to create a list of classes and tools for subscribing to receive orders for them.
+    /// The instrument already subscribed to by the terminal is not subscribed again,
+    /// the failed subscription is interpreted by `SubscribeError::check`.
+    pub fn subscribe_orders(
+        &self,
+        class_code: &str,
+        sec_code: &str,
+    ) -> Result<SubscribeOutcome, Trans2QuikError> {
+        match self.call_subscribe_orders(class_code, sec_code)? {
+            Some(trans2quik_result) => {
+                SubscribeError::check(trans2quik_result)?;
+                Ok(SubscribeOutcome::Subscribed)
+            }
+            None => Ok(SubscribeOutcome::AlreadySubscribed),
+        }
+    }
+
+    /**
+    Subscribes to the orders of all the `instruments`, `(class_code, sec_code)`, like
+    `Terminal::subscribe_orders`, not stopping on the failed subscriptions. Returns the result of
+    TRANS2QUIK_SUBSCRIBE_ORDERS for every instrument in the order of the `instruments`:
+    `Trans2QuikResult::Success` for the instrument already subscribed to and
+    `Trans2QuikResult::WrongInputParams` for the codes with the nul byte, not passed to the library.
+
+    # Example of use
+    ```ignore
+    for ((class_code, sec_code), result) in terminal.subscribe_orders_best_effort(&instruments) {
+        if result != Trans2QuikResult::Success {
+            warn!("{} {} not subscribed: {:?}", class_code, sec_code, result);
+        }
+    }
+    ```
+    */
+    pub fn subscribe_orders_best_effort(
+        &self,
+        instruments: &[(&str, &str)],
+    ) -> Vec<((String, String), Trans2QuikResult)> {
+        instruments
+            .iter()
+            .map(|(class_code, sec_code)| {
+                let trans2quik_result = match self.call_subscribe_orders(class_code, sec_code) {
+                    Ok(Some(trans2quik_result)) => trans2quik_result,
+                    Ok(None) => Trans2QuikResult::Success,
+                    Err(err) => {
+                        error!(
+                            "TRANS2QUIK_SUBSCRIBE_ORDERS -> {}, class_code: {:?}, sec_code: {:?}",
+                            err, class_code, sec_code
+                        );
+                        Trans2QuikResult::WrongInputParams
+                    }
+                };
+
+                (
+                    (class_code.to_string(), sec_code.to_string()),
+                    trans2quik_result,
+                )
+            })
+            .collect()
+    }
+
+    /// Calls TRANS2QUIK_SUBSCRIBE_ORDERS and records the successful subscription, `None` if
+    /// the instrument is already subscribed to by the terminal.
+    fn call_subscribe_orders(
+        &self,
+        class_code: &str,
+        sec_code: &str,
+    ) -> Result<Option<Trans2QuikResult>, Trans2QuikError> {
+        let instrument = (class_code.to_string(), sec_code.to_string());
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .orders
+            .contains(&instrument)
+        {
+            info!(
+                "TRANS2QUIK_SUBSCRIBE_ORDERS -> already subscribed, class_code: {}, sec_code: {}",
+                class_code, sec_code
+            );
+            return Ok(None);
+        }
+
+        let class_code_c = CString::new(class_code)?;
+        let class_code_ptr = class_code_c.as_ptr() as *mut c_char;
+
+        let sec_code_c = CString::new(sec_code)?;
+        let sec_code_ptr = sec_code_c.as_ptr() as *mut c_char;
+
+        let function_result = fault::call("TRANS2QUIK_SUBSCRIBE_ORDERS", || unsafe {
+            (self.trans2quik_subscribe_orders)(class_code_ptr, sec_code_ptr)
+        });
+
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "TRANS2QUIK_SUBSCRIBE_ORDERS -> {:?}, class_code: {}, sec_code: {}",
+                trans2quik_result, class_code, sec_code
+            ),
+        );
+
+        if trans2quik_result == Trans2QuikResult::Success {
+            INSTRUMENT_STATS
+                .lock()
+                .unwrap()
+                .entry(instrument.clone())
+                .or_default()
+                .orders = 0;
+            self.subscriptions.lock().unwrap().orders.insert(instrument);
+        }
+
+        Ok(Some(trans2quik_result))
+    }
+
+    /// The function is used to create a list of classes and tools for subscribing to receive trades on them.
+    /// The instrument already subscribed to by the terminal is not subscribed again,
+    /// the failed subscription is interpreted by `SubscribeError::check`.
+    pub fn subscribe_trades(
+        &self,
+        class_code: &str,
+        sec_code: &str,
+    ) -> Result<SubscribeOutcome, Trans2QuikError> {
+        let instrument = (class_code.to_string(), sec_code.to_string());
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .trades
+            .contains(&instrument)
+        {
+            info!(
+                "TRANS2QUIK_SUBSCRIBE_TRADES -> already subscribed, class_code: {}, sec_code: {}",
+                class_code, sec_code
+            );
+            return Ok(SubscribeOutcome::AlreadySubscribed);
+        }
+
+        let class_code_c = CString::new(class_code)?;
+        let class_code_ptr = class_code_c.as_ptr() as *mut c_char;
+
+        let sec_code_c = CString::new(sec_code)?;
+        let sec_code_ptr = sec_code_c.as_ptr() as *mut c_char;
+
+        let function_result = fault::call("TRANS2QUIK_SUBSCRIBE_TRADES", || unsafe {
+            (self.trans2quik_subscribe_trades)(class_code_ptr, sec_code_ptr)
+        });
+
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+
+        log_result(
+            &trans2quik_result,
+            format_args!(
+                "TRANS2QUIK_SUBSCRIBE_TRADES -> {:?}, class_code: {}, sec_code: {}",
+                trans2quik_result, class_code, sec_code
+            ),
+        );
+
+        SubscribeError::check(trans2quik_result)?;
+        INSTRUMENT_STATS
+            .lock()
+            .unwrap()
+            .entry(instrument.clone())
+            .or_default()
+            .trades = 0;
+        self.subscriptions.lock().unwrap().trades.insert(instrument);
+
+        Ok(SubscribeOutcome::Subscribed)
+    }
+
+    /// The function starts the process of receiving requests for classes and tools defined
+    /// by the TRANS2QUIK_SUBSCRIBE_ORDERS function.
+    ///
+    /// The existing orders are received first with `Mode::InitialOrder`, the last of them
+    /// with `Mode::LastOrderReceived`, then the updates with `Mode::NewOrder`,
+    /// see `Terminal::orders_snapshot_complete`.
+    pub fn start_orders(&self) {
+        ORDERS_SNAPSHOT_COMPLETE.store(false, Ordering::SeqCst);
+        unsafe { (self.trans2quik_start_orders)(order_status_callback) }
+        self.callback_state.lock().unwrap().orders = true;
+    }
+
+    /// The function starts the process of receiving transactions with the parameters set
+    /// by the function TRANS2QUIK_SUBSCRIBE_TRADES.
+    ///
+    /// The callback functions of the library Trans2QUIK.dll are global for the process,
+    /// so they serve one `Terminal` and its clones. Returns `Trans2QuikError::TerminalInUse`
+    /// if another `Terminal` has started receiving the trades and has not disconnected.
+    pub fn start_trades(&self) -> Result<(), Trans2QuikError> {
+        {
+            let mut terminal_instance = TERMINAL_INSTANCE.lock().unwrap();
+
+            match terminal_instance.as_ref() {
+                Some(other) if other.instance_id != self.instance_id => {
+                    error!("TRANS2QUIK_START_TRADES -> another Terminal is already started");
+                    return Err(Trans2QuikError::TerminalInUse);
+                }
+                // Set once by the terminal or its clones.
+                Some(_) => {}
+                None => {
+                    *terminal_instance = Some(CallbackTerminal {
+                        instance_id: self.instance_id,
+                        getters: self.descriptor_getters(),
+                        library: Arc::clone(&self.library),
+                    })
+                }
+            }
+        }
+
+        unsafe { (self.trans2quik_start_trades)(trade_status_callback) };
+        self.callback_state.lock().unwrap().trades = true;
+
+        Ok(())
+    }
+
+    /// Sets the handler called for every transaction reply with the access to the
+    /// transaction reply descriptor, see [`TransactionReplyAccess`].
+    /// The handler is called from the thread of the library Trans2QUIK.dll before
+    /// the reply is sent to the TRANSACTION_REPLY_SENDER.
+    pub fn set_transaction_reply_handler<F>(&self, handler: F)
+    where
+        F: Fn(&TransactionInfo, &TransactionReplyAccess<'_>) + Send + Sync + 'static,
+    {
+        TRANSACTION_REPLY_HANDLER.store(Some(Arc::new(Box::new(handler))));
+    }
+
+    /// The function interrupts the operation of the TRANS2QUIK_START_ORDERS function and clears
+    /// the list of received tools generated by the function
+    /// TRANS2QUIK_SUBSCRIBE_ORDERS.
+    pub fn unsubscribe_orders(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let function_result = unsafe { (self.trans2quik_unsubscribe_orders)() };
+
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+        if trans2quik_result == Trans2QuikResult::Success {
+            self.callback_state.lock().unwrap().orders = false;
+            self.subscriptions.lock().unwrap().orders.clear();
+        }
+
+        log_result(
+            &trans2quik_result,
+            format_args!("TRANS2QUIK_UNSUBSCRIBE_ORDERS -> {:?}", trans2quik_result),
+        );
+
+        Ok(trans2quik_result)
+    }
+
+    /// The function interrupts the operation of the TRANS2QUIK_START_TRADES function and clears
+    /// the list of received tools generated by the function
+    /// TRANS2QUIK_SUBSCRIBE_TRADES.
+    pub fn unsubscribe_trades(&self) -> Result<Trans2QuikResult, Trans2QuikError> {
+        let function_result = unsafe { (self.trans2quik_unsubscribe_trades)() };
+
+        let trans2quik_result = Trans2QuikResult::from(function_result);
+        if trans2quik_result == Trans2QuikResult::Success {
+            self.callback_state.lock().unwrap().trades = false;
+            self.subscriptions.lock().unwrap().trades.clear();
+        }
+
+        log_result(
+            &trans2quik_result,
+            format_args!("TRANS2QUIK_UNSUBSCRIBE_TRADES -> {:?}", trans2quik_result),
+        );
+
+        Ok(trans2quik_result)
+    }
+}
+
+/// Loads the library Trans2QUIK.dll with the `LOAD_WITH_ALTERED_SEARCH_PATH` flag:
+/// the DLLs it depends on are searched in the directory of the library (the QUIK directory)
+/// rather than in the directory of the executable, otherwise the loading fails
+/// with the error 126 "The specified module could not be found".
+#[cfg(windows)]
+fn load_library(path_to_lib: &str) -> Result<Library, LibloadingError> {
+    use libloading::os::windows::{Library as WindowsLibrary, LOAD_WITH_ALTERED_SEARCH_PATH};
+
+    let library =
+        unsafe { WindowsLibrary::load_with_flags(path_to_lib, LOAD_WITH_ALTERED_SEARCH_PATH)? };
+    Ok(library.into())
+}
+
+/// Loads the library, there is no Trans2QUIK.dll outside Windows,
+/// this allows to build and test the crate on other platforms.
+#[cfg(not(windows))]
+fn load_library(path_to_lib: &str) -> Result<Library, LibloadingError> {
+    unsafe { Library::new(path_to_lib) }
+}
+
+/// Loads the symbols of the library Trans2QUIK.dll remembering the names of the loaded ones.
+struct SymbolLoader<'a> {
+    library: &'a Library,
+    loaded: Vec<&'static str>,
+}
+
+impl<'a> SymbolLoader<'a> {
+    fn new(library: &'a Library) -> Self {
+        SymbolLoader {
+            library,
+            loaded: Vec::new(),
+        }
+    }
+
+    fn load<T>(&mut self, name: &'static str) -> Result<T, LibloadingError>
+    where
+        T: Copy,
+    {
+        let symbol = load_symbol::<T>(self.library, name.as_bytes())?;
+        self.loaded.push(name);
+        Ok(symbol)
+    }
+
+    /// Loads the symbol missing in some versions of the library, `None` if it is missing.
+    fn load_optional<T>(&mut self, name: &'static str) -> Option<T>
+    where
+        T: Copy,
+    {
+        match self.load::<T>(name) {
+            Ok(symbol) => Some(symbol),
+            Err(e) => {
+                warn!("{} is not loaded: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+/// Loads the symbol from the library Trans2QUIK.dll
+fn load_symbol<T>(library: &Library, name: &[u8]) -> Result<T, LibloadingError>
+where
+    T: Copy,
+{
+    unsafe {
+        let symbol: Symbol<T> = library.get(name)?;
+        Ok(*symbol)
+    }
+}
+
+/// Returns the value of the parameter from the transaction string, e.g. `TRANS_ID` from
+/// `ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER`.
+fn transaction_param<'a>(transaction_str: &'a str, name: &str) -> Option<&'a str> {
+    transaction_str.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Checks the parameters required in any transaction string.
+fn validate_transaction_str(transaction_str: &str) -> Result<(), TransactionBuildError> {
+    for required in ["ACTION", "TRANS_ID"] {
+        if transaction_param(transaction_str, required).is_none() {
+            return Err(TransactionBuildError::MissingParam(required));
+        }
+    }
+
+    Ok(())
+}
+
+/// Remembers the order sent by the asynchronous NEW_ORDER transaction to resolve its order
+/// number from the transaction reply. Up to `SENT_ORDERS_CAPACITY` orders are remembered.
+fn track_sent_order(transaction_str: &str) -> Option<c_long> {
+    if !transaction_param(transaction_str, "ACTION")
+        .is_some_and(|action| action.eq_ignore_ascii_case("NEW_ORDER"))
+    {
+        return None;
+    }
+
+    let trans_id = transaction_param(transaction_str, "TRANS_ID")
+        .and_then(|trans_id| trans_id.parse::<c_long>().ok());
+    let class_code = transaction_param(transaction_str, "CLASSCODE");
+    let sec_code = transaction_param(transaction_str, "SECCODE");
+
+    if let (Some(trans_id), Some(class_code), Some(sec_code)) = (trans_id, class_code, sec_code) {
+        let mut sent_orders = SENT_ORDERS.lock().unwrap();
+        if sent_orders.len() >= SENT_ORDERS_CAPACITY {
+            let oldest = sent_orders
+                .iter()
+                .min_by_key(|(_, sent_order)| sent_order.seq)
+                .map(|(trans_id, _)| *trans_id);
+            if let Some(oldest) = oldest {
+                sent_orders.remove(&oldest);
+            }
+        }
+        sent_orders.insert(
+            trans_id,
+            SentOrder {
+                class_code: class_code.to_string(),
+                sec_code: sec_code.to_string(),
+                order_num: None,
+                seq: SENT_ORDER_SEQ.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+        return Some(trans_id);
+    }
+
+    None
+}
+
+/// Waits for the transaction reply of the transaction sent with the TRANS_ID
+/// by `Terminal::send_async_transaction_for_reply`.
+async fn wait_for_reply(
+    trans_id: c_long,
+    reply: oneshot::Receiver<TransactionInfo>,
+) -> Result<TransactionInfo, Trans2QuikError> {
+    match tokio::time::timeout(REPLY_TIMEOUT, reply).await {
+        Ok(Ok(transaction_info)) => Ok(transaction_info),
+        _ => {
+            REPLY_WAITERS.lock().unwrap().remove(&trans_id);
+            Err(Trans2QuikError::Timeout)
+        }
+    }
+}
+
+/// Waits for the transaction reply with the order number of the order sent with the TRANS_ID.
+async fn wait_for_order_num(trans_id: i32) -> Result<(String, String, u64), Trans2QuikError> {
+    let mut waiting = false;
+
+    loop {
+        let notified = SENT_ORDERS_NOTIFY.notified();
+
+        match SENT_ORDERS.lock().unwrap().get(&(trans_id as c_long)) {
+            // The reply without the order number removed the order while waiting.
+            None if waiting => return Err(Trans2QuikError::OrderNotPlaced(trans_id)),
+            None => return Err(Trans2QuikError::UnknownTransId(trans_id)),
+            Some(SentOrder {
                 class_code,
                 sec_code,
-                price,
-                quantity,
-                is_sell,
-                value,
-                date,
-                time,
-            };
+                order_num: Some(order_num),
+                ..
+            }) => return Ok((class_code.clone(), sec_code.clone(), *order_num)),
+            Some(_) => waiting = true,
+        }
+
+        notified.await;
+    }
+}
+
+/// The first TRANS_ID of the transactions generated by the crate, the number of seconds since
+/// midnight multiplied by 10000 so that the identifiers don't repeat after a restart.
+fn initial_trans_id() -> i32 {
+    Local::now().num_seconds_from_midnight() as i32 * 10000
+}
+
+/// TRANS_ID for the transactions generated by the crate, e.g. KILL_ORDER.
+fn next_trans_id() -> i32 {
+    TRANS_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Extract String from `Vec<i8>`.
+fn extract_string_from_vec(
+    vec_i8: Vec<i8>,
+    encoding: &'static Encoding,
+) -> Result<String, FromUtf8Error> {
+    let vec_u8: Vec<u8> = vec_i8.into_iter().map(|byte| byte as u8).collect();
+
+    let null_pos = vec_u8
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(vec_u8.len());
+
+    let vec_u8_trimmed = &vec_u8[..null_pos];
+
+    let (decoded_str, _, _) = encoding.decode(vec_u8_trimmed);
+
+    Ok(decoded_str.into_owned())
+}
+
+/// Encodes the string in WINDOWS-1251 expected by the library Trans2QUIK.dll,
+/// e.g. the Cyrillic comments of the transactions.
+fn encode_lpstr(string: &str) -> Result<CString, NulError> {
+    let (encoded, _, _) = WINDOWS_1251.encode(string);
+
+    CString::new(encoded.into_owned())
+}
+
+fn decode_lpstr(code: *mut c_char) -> Result<String, DecodeLpstrError> {
+    if code.is_null() {
+        return Err(DecodeLpstrError::NullPointer);
+    }
+
+    // Safe block to convert C string to Rust slice
+    let c_str = unsafe { CStr::from_ptr(code) };
+
+    // Attempt to convert the C string to bytes
+    let bytes = c_str.to_bytes();
+
+    // Decode the bytes using WINDOWS-1251 encoding, the malformed bytes are replaced
+    let (decoded_str, _, had_errors) = WINDOWS_1251.decode(bytes);
+
+    if had_errors {
+        DECODE_FAILED.with(|failed| failed.set(true));
+        warn!(
+            "lossy WINDOWS-1251 decoding of b\"{}\" as {:?}",
+            escape_bytes(bytes),
+            decoded_str
+        );
+    }
+
+    // Convert the Cow<str> to String and return
+    Ok(decoded_str.into_owned())
+}
+
+/// Decodes the string field of the callback function, the empty string if it can't be decoded
+/// so that no error text gets into the data.
+fn decode_field(code: *mut c_char, field: &str) -> String {
+    match decode_lpstr(code) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("decode {} error: {}", field, e);
+            DECODE_FAILED.with(|failed| failed.set(true));
+            String::new()
+        }
+    }
+}
+
+/// Whether the event of the callback function is dropped in the strict mode since its strings
+/// failed to decode, see `Terminal::set_strict_decoding`. Resets the failure for the next event.
+fn drop_undecoded_event(callback: &str) -> bool {
+    if !DECODE_FAILED.with(|failed| failed.replace(false))
+        || !STRICT_DECODING.load(Ordering::Relaxed)
+    {
+        return false;
+    }
+
+    let dropped = UNDECODED_EVENTS_DROPPED.fetch_add(1, Ordering::Relaxed) + 1;
+    warn!(
+        "{} event dropped, its strings failed to decode, dropped: {}",
+        callback, dropped
+    );
+    true
+}
+
+/// Converts the date in the format yyyymmdd, `0` means there is no date.
+fn format_date(date: c_long) -> Result<Option<NaiveDate>, DateTimeError> {
+    if date == 0 {
+        return Ok(None);
+    }
+
+    if date < 0 {
+        return Err(DateTimeError::InvalidDate);
+    }
+
+    let date_str = format!("{:08}", date);
+
+    let naive_date = NaiveDate::parse_from_str(&date_str, "%Y%m%d")?;
+
+    Ok(Some(naive_date))
+}
+
+/// Converts the time in the format hhmmss, `0` means there is no time.
+fn format_time(time: c_long) -> Result<Option<NaiveTime>, DateTimeError> {
+    if time == 0 {
+        return Ok(None);
+    }
+
+    if time < 0 {
+        return Err(DateTimeError::InvalidTime);
+    }
+
+    let time_str = format!("{:06}", time);
+
+    let naive_time = NaiveTime::parse_from_str(&time_str, "%H%M%S")?;
+
+    Ok(Some(naive_time))
+}
+
+/// Converts the date to the format yyyymmdd of QUIK, the inverse of the dates
+/// of the callback functions, e.g. `20241115`.
+pub fn to_quik_date(date: NaiveDate) -> i32 {
+    let (month, day) = (date.month() as i32, date.day() as i32);
+    date.year() * 10000 + month * 100 + day
+}
+
+/// Converts the time to the format hhmmss of QUIK, the inverse of the times
+/// of the callback functions, e.g. `93005`. The fractions of the second are dropped.
+pub fn to_quik_time(time: NaiveTime) -> i32 {
+    let (hour, minute, second) = (
+        time.hour() as i32,
+        time.minute() as i32,
+        time.second() as i32,
+    );
+    hour * 10000 + minute * 100 + second
+}
+
+/// Converts the UID of the user returned by the library Trans2QUIK.dll, `0` if it is negative.
+fn format_uid(uid: c_long) -> u64 {
+    u64::try_from(uid).unwrap_or_else(|_| {
+        error!("invalid uid: {}", uid);
+        0
+    })
+}
+
+/// Randomizes the delay within `[delay / 2, delay]`.
+fn jitter(delay: Duration) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos());
+    let fraction = f64::from(nanos % 1000) / 1000.0;
+
+    delay / 2 + (delay / 2).mul_f64(fraction)
+}
+
+/// The identifier of the new `Terminal`.
+fn next_instance_id() -> u64 {
+    NEXT_INSTANCE_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// The price or the value for the log, see `Terminal::set_log_decimal_comma`.
+fn log_decimal(value: f64) -> String {
+    format_log_decimal(value, LOG_DECIMAL_COMMA.load(Ordering::Relaxed))
+}
+
+fn format_log_decimal(value: f64, comma: bool) -> String {
+    let formatted = value.to_string();
+
+    if comma {
+        formatted.replace('.', ",")
+    } else {
+        formatted
+    }
+}
+
+/// Sends the copy of the event to the broadcast subscribers, `false` if there are none.
+fn broadcast_event<T: Clone>(sender: &broadcast::Sender<T>, event: &T) -> bool {
+    sender.receiver_count() > 0 && sender.send(event.clone()).is_ok()
+}
+
+/// The event of the callback function delivered to the channels and the broadcast subscribers.
+#[derive(Debug)]
+enum CallbackEvent {
+    TransactionReply(TransactionInfo),
+    Order(OrderInfo),
+    Trade(TradeInfo),
+}
+
+/// The events withheld while the delivery is paused, see `Terminal::pause_events`.
+#[derive(Debug, Default)]
+struct PausedEvents {
+    policy: PausePolicy,
+    events: VecDeque<CallbackEvent>,
+    dropped: u64,
+}
+
+/// Delivers the event, or buffers or drops it if the delivery is paused.
+fn deliver_or_withhold(event: CallbackEvent) {
+    if EVENTS_PAUSED.load(Ordering::SeqCst) {
+        let mut paused_events = PAUSED_EVENTS.lock().unwrap();
+
+        // Not resumed while waiting for the lock.
+        if EVENTS_PAUSED.load(Ordering::SeqCst) {
+            match paused_events.policy {
+                PausePolicy::Buffer => paused_events.events.push_back(event),
+                PausePolicy::Drop => paused_events.dropped += 1,
+            }
+            return;
+        }
+    }
+
+    deliver_event(event);
+}
+
+/// Sends the event to the broadcast subscribers and to the channel.
+fn deliver_event(event: CallbackEvent) {
+    match event {
+        CallbackEvent::TransactionReply(transaction_info) => {
+            let broadcast = broadcast_event(&TRANSACTION_REPLY_BROADCAST, &transaction_info)
+                | broadcast_event(
+                    &EVENT_BROADCAST,
+                    &Event::TransactionReply(transaction_info.clone()),
+                );
+
+            if let Some(sender) = TRANSACTION_REPLY_SENDER.load().as_ref() {
+                if let Err(err) = sender.send(transaction_info) {
+                    error!("transaction_reply_callback send error: {}", err);
+                }
+            } else if !broadcast {
+                error!("TRANSACTION_REPLY_SENDER is not initialized");
+            }
+        }
+        CallbackEvent::Order(order_info) => {
+            let suppressed = SUPPRESS_INITIAL_ORDERS.load(Ordering::Relaxed)
+                && matches!(
+                    order_info.mode,
+                    Mode::InitialOrder | Mode::LastOrderReceived
+                );
+            let broadcast = broadcast_event(&ORDER_STATUS_BROADCAST, &order_info)
+                | broadcast_event(&EVENT_BROADCAST, &Event::Order(order_info.clone()));
+
+            if !suppressed {
+                if let Some(sender) = ORDER_STATUS_SENDER.load().as_ref() {
+                    if let Err(err) = sender.send(order_info) {
+                        error!("order_status_callback send error: {}", err);
+                    }
+                } else if !broadcast {
+                    error!("ORDER_SENDER is not initialized");
+                }
+            }
+        }
+        CallbackEvent::Trade(trade_info) => {
+            let broadcast = broadcast_event(&TRADE_STATUS_BROADCAST, &trade_info)
+                | broadcast_event(&EVENT_BROADCAST, &Event::Trade(trade_info.clone()));
+
+            if let Some(sender) = TRADE_STATUS_SENDER.load().as_ref() {
+                if let Err(err) = sender.send(trade_info) {
+                    error!("trade_status_callback send error: {}", err);
+                }
+            } else if !broadcast {
+                error!("TRADE_SENDER is not initialized");
+            }
+        }
+    }
+}
+
+/// The terminal receiving the callback functions, stored once by `Terminal::start_trades`
+/// instead of the clone of the whole terminal.
+struct CallbackTerminal {
+    instance_id: u64,
+    getters: DescriptorGetters,
+    /// Keeps the library loaded while it calls the callback functions, e.g. after the terminal
+    /// is dropped, and loads the getters of `TransactionReplyAccess`.
+    library: Arc<Library>,
+}
+
+/// The functions of the library Trans2QUIK.dll reading the descriptors passed to the callback
+/// functions, copied from the `Terminal` started by `Terminal::start_trades`.
+#[derive(Clone, Copy)]
+struct DescriptorGetters {
+    trans2quik_transaction_reply_sec_code:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+    trans2quik_transaction_reply_price:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> c_double,
+    trans2quik_transaction_reply_firm_id:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+    trans2quik_transaction_reply_client_code:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+    trans2quik_transaction_reply_account:
+        unsafe extern "C" fn(trans_reply_descriptor: intptr_t) -> *mut c_char,
+    trans2quik_order_date: unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long,
+    trans2quik_order_time: unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long,
+    trans2quik_order_qty: unsafe extern "C" fn(order_descriptor: intptr_t) -> i64,
+    trans2quik_order_exec_type: Option<unsafe extern "C" fn(order_descriptor: intptr_t) -> c_long>,
+    trans2quik_trade_date: unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long,
+    trans2quik_trade_time: unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long,
+    trans2quik_trade_settle_code: unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char,
+    trans2quik_trade_settle_time:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+    trans2quik_trade_is_marginal:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+    trans2quik_trade_userid:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+    trans2quik_trade_firm_id:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+    trans2quik_trade_uid: Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> c_long>,
+    trans2quik_trade_currency:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+    trans2quik_trade_settle_currency:
+        Option<unsafe extern "C" fn(trade_descriptor: intptr_t) -> *mut c_char>,
+}
+
+impl DescriptorGetters {
+    /// The date of the order, `None` if the library Trans2QUIK.dll returned no date,
+    /// the error if it returned a malformed date.
+    /// The descriptor is valid only within the order_status_callback.
+    fn order_date(&self, order_descriptor: intptr_t) -> Result<Option<NaiveDate>, DateTimeError> {
+        format_date(unsafe { (self.trans2quik_order_date)(order_descriptor) })
+    }
+
+    /// The time of the order, `None` if the library Trans2QUIK.dll returned no time,
+    /// the error if it returned a malformed time.
+    /// The descriptor is valid only within the order_status_callback.
+    fn order_time(&self, order_descriptor: intptr_t) -> Result<Option<NaiveTime>, DateTimeError> {
+        format_time(unsafe { (self.trans2quik_order_time)(order_descriptor) })
+    }
+}
+
+/// The descriptor getters of the terminal receiving the callback functions. The lock is released
+/// before the event is processed, so the handlers can call the terminal, e.g. `Terminal::disconnect`.
+fn current_descriptor_getters() -> Option<DescriptorGetters> {
+    TERMINAL_INSTANCE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|terminal| terminal.getters)
+}
+
+/// The descriptor getters and the library of the terminal receiving the callback functions,
+/// see `current_descriptor_getters`.
+fn current_callback_library() -> Option<(DescriptorGetters, Arc<Library>)> {
+    TERMINAL_INSTANCE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|terminal| (terminal.getters, Arc::clone(&terminal.library)))
+}
+
+/// Runs the body of the callback function catching its panic, see `callback_panic::catch`.
+/// The panic leaves the mutexes locked by the callback function poisoned, the following
+/// callback functions would panic locking them, so the poison is cleared.
+fn catch_callback_panic<F: FnOnce()>(callback: &str, body: F) {
+    if !callback_panic::catch(callback, body) {
+        return;
+    }
+
+    TERMINAL_INSTANCE.clear_poison();
+    SENT_ORDERS.clear_poison();
+    REPLY_WAITERS.clear_poison();
+    PENDING_TRANS_IDS.clear_poison();
+    LAST_CONNECTION_EVENT.clear_poison();
+    RECENT_ORDERS.clear_poison();
+    LAST_TRADE_TIMES.clear_poison();
+    INSTRUMENT_STATS.clear_poison();
+}
+
+/**
+Clears the process-global state of the crate: the `Terminal` receiving the callback functions,
+the senders of the channels, the transactions waiting for the replies, the rest of the state
+of the callback functions and the faults injected with `inject_fault`. Allows the tests driving the callback functions to run isolated
+from each other, such tests must not run concurrently.
+
+Available in the tests of the crate and with the feature `test-util`.
+*/
+#[cfg(any(test, feature = "test-util"))]
+pub fn reset_globals() {
+    *TERMINAL_INSTANCE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    TRANSACTION_REPLY_SENDER.store(None);
+    ORDER_STATUS_SENDER.store(None);
+    TRADE_STATUS_SENDER.store(None);
+    TRANSACTION_REPLY_HANDLER.store(None);
+    SENT_ORDERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    REPLY_WAITERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    PENDING_TRANS_IDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    *LAST_CONNECTION_EVENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = None;
+    CONN_STATE.store(Arc::new(ConnState::Disconnected));
+    clear_faults();
+    RECENT_ORDERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    TERMINAL_INSTANCE.clear_poison();
+    SENT_ORDERS.clear_poison();
+    REPLY_WAITERS.clear_poison();
+    PENDING_TRANS_IDS.clear_poison();
+    LAST_CONNECTION_EVENT.clear_poison();
+    RECENT_ORDERS.clear_poison();
+    LAST_TRADE_TIMES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    LAST_TRADE_TIMES.clear_poison();
+    INSTRUMENT_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    INSTRUMENT_STATS.clear_poison();
+    *PAUSED_EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = PausedEvents::default();
+    PAUSED_EVENTS.clear_poison();
+    EVENTS_PAUSED.store(false, Ordering::SeqCst);
+    LOG_DECIMAL_COMMA.store(false, Ordering::Relaxed);
+    STRICT_DECODING.store(false, Ordering::Relaxed);
+    UNDECODED_EVENTS_DROPPED.store(0, Ordering::Relaxed);
+    SUPPRESS_INITIAL_ORDERS.store(false, Ordering::Relaxed);
+    ORDERS_SNAPSHOT_COMPLETE.store(false, Ordering::SeqCst);
+    callback_panic::reset();
+    watchdog::reset();
+}
+
+/// Callback function for status monitoring connections.
+unsafe extern "C" fn connection_status_callback(
+    connection_event: c_long,
+    error_code: c_long,
+    error_message: *mut c_char,
+) {
+    catch_callback_panic("connection_status_callback", || {
+        on_connection_status(connection_event, error_code, error_message)
+    });
+}
+
+/// The body of `connection_status_callback`.
+unsafe fn on_connection_status(
+    connection_event: c_long,
+    error_code: c_long,
+    error_message: *mut c_char,
+) {
+    watchdog::touch();
+    telemetry::callback_event("connection");
+
+    let error_message = if !error_message.is_null() {
+        let c_str = CStr::from_ptr(error_message);
+        let bytes = c_str.to_bytes();
+
+        let (decoded_str, _, _) = WINDOWS_1251.decode(bytes);
+        decoded_str.into_owned().to_owned()
+    } else {
+        String::from("error_message is null")
+    };
+
+    let trans2quik_result = Trans2QuikResult::from(connection_event);
+
+    log_result(
+        &trans2quik_result,
+        format_args!(
+            "TRANS2QUIK_CONNECTION_STATUS_CALLBACK -> {:?}, error_code: {}, error_message: {}",
+            trans2quik_result,
+            ErrorCode::from(error_code),
+            error_message
+        ),
+    );
+
+    // c_long is i32 on Windows only.
+    #[allow(clippy::unnecessary_cast)]
+    let event =
+        ConnectionEvent::from_callback(connection_event as i32, error_code as i32, error_message);
+    if let Some(event) = event {
+        telemetry::connection_event(&event);
+
+        // No receivers if nobody awaits the disconnection.
+        let _ = CONNECTION_EVENTS.send(event.clone());
+        broadcast_event(&EVENT_BROADCAST, &Event::Connection(event.clone()));
+        CONN_STATE.rcu(|state| Arc::new(state.after(&event)));
+        *LAST_CONNECTION_EVENT.lock().unwrap() = Some(event);
+    }
+}
+
+/// Callback function for processing the received transaction information.
+/// Attention! The submission of asynchronous transactions using
+/// the callback function and synchronous transactions at the same time is prohibited.
+/// This is due to the fact that it is impossible to correctly call
+/// the callback function at a time when the synchronous transaction processing function has
+/// not finished its work yet.
+unsafe extern "C" fn transaction_reply_callback(
+    result_code: c_long,
+    error_code: c_long,
+    reply_code: c_long,
+    trans_id: c_long,
+    order_num: c_ulonglong,
+    reply_message: *mut c_char,
+    trans_reply_descriptor: intptr_t,
+) {
+    catch_callback_panic("transaction_reply_callback", || {
+        on_transaction_reply(
+            result_code,
+            error_code,
+            reply_code,
+            trans_id,
+            order_num,
+            reply_message,
+            trans_reply_descriptor,
+        )
+    });
+}
+
+/// The body of `transaction_reply_callback`.
+unsafe fn on_transaction_reply(
+    result_code: c_long,
+    error_code: c_long,
+    reply_code: c_long,
+    trans_id: c_long,
+    order_num: c_ulonglong,
+    reply_message: *mut c_char,
+    trans_reply_descriptor: intptr_t,
+) {
+    watchdog::touch();
+    telemetry::callback_event("transaction_reply");
+
+    PENDING_TRANS_IDS.lock().unwrap().remove(&trans_id);
+    PENDING_TRANS_IDS_NOTIFY.notify_waiters();
+
+    if let Some((getters, library)) = current_callback_library() {
+        let trans2quik_result = Trans2QuikResult::from(result_code);
+
+        {
+            let mut sent_orders = SENT_ORDERS.lock().unwrap();
+            if order_num == 0 {
+                // The transaction placed no order, there is nothing to cancel.
+                sent_orders.remove(&trans_id);
+            } else if let Some(sent_order) = sent_orders.get_mut(&trans_id) {
+                sent_order.order_num = Some(order_num);
+            }
+        }
+        SENT_ORDERS_NOTIFY.notify_waiters();
+
+        let reply_waiter = REPLY_WAITERS.lock().unwrap().remove(&trans_id);
+
+        let trans_id = TransId::from(trans_id);
+
+        DECODE_FAILED.with(|failed| failed.set(false));
+
+        let reply_message = decode_field(reply_message, "reply_message");
+
+        let descriptor = ReplyDescriptor::new(&getters, &library, trans_reply_descriptor);
+
+        let sec_code = descriptor.sec_code();
+
+        let price = descriptor.price();
+
+        let firm_id = descriptor.firm_id();
+
+        let client_code = descriptor.client_code();
+
+        let account = descriptor.account();
+
+        if drop_undecoded_event("transaction_reply_callback") {
+            return;
+        }
+
+        info!("TRANS2QUIK_TRANSACTION_REPLY_CALLBACK -> {:?}, error_code: {}, reply_code: {:?}, trans_id: {:?}, order_num: {}, reply_message: {}, sec_code: {}, price: {}, firm_id: {}, client_code: {}, account: {}", trans2quik_result, ErrorCode::from(error_code), ReplyCode::from(reply_code), trans_id, order_num, reply_message, sec_code, log_decimal(price), firm_id, client_code, account);
+
+        let reply_code = ReplyCode::from(reply_code);
+        telemetry::transaction_reply(&reply_code);
+
+        let rejection_reason = match reply_code.is_rejected() {
+            true => Some(classify_rejection(&reply_message)),
+            false => None,
+        };
+
+        let transaction_info = TransactionInfo {
+            trans2quik_result,
+            error_code: ErrorCode::from(error_code),
+            reply_code,
+            trans_id,
+            order_num,
+            reply_message,
+            sec_code,
+            price,
+            firm_id,
+            client_code,
+            account,
+            rejection_reason,
+            seq: TRANSACTION_REPLY_SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+        };
+
+        if let Some(waiter) = reply_waiter {
+            let _ = waiter.send(transaction_info.clone());
+        }
+
+        if let Some(handler) = TRANSACTION_REPLY_HANDLER.load_full() {
+            handler(&transaction_info, &TransactionReplyAccess::new(descriptor));
+        }
+
+        deliver_or_withhold(CallbackEvent::TransactionReply(transaction_info));
+    } else {
+        error!("TERMINAL_INSTANCE is not initialized");
+    }
+}
+
+/// Callback function to get information about the order parameters.
+unsafe extern "C" fn order_status_callback(
+    mode: c_long,
+    trans_id: c_long,
+    order_num: c_ulonglong,
+    class_code: *mut c_char,
+    sec_code: *mut c_char,
+    price: c_double,
+    balance: i64,
+    value: c_double,
+    is_sell: c_long,
+    status: c_long,
+    order_descriptor: intptr_t,
+) {
+    catch_callback_panic("order_status_callback", || {
+        on_order_status(
+            mode,
+            trans_id,
+            order_num,
+            class_code,
+            sec_code,
+            price,
+            balance,
+            value,
+            is_sell,
+            status,
+            order_descriptor,
+        )
+    });
+}
+
+/// The body of `order_status_callback`.
+#[allow(clippy::too_many_arguments)]
+unsafe fn on_order_status(
+    mode: c_long,
+    trans_id: c_long,
+    order_num: c_ulonglong,
+    class_code: *mut c_char,
+    sec_code: *mut c_char,
+    price: c_double,
+    balance: i64,
+    value: c_double,
+    is_sell: c_long,
+    status: c_long,
+    order_descriptor: intptr_t,
+) {
+    watchdog::touch();
+    telemetry::callback_event("orders");
+
+    if let Some(getters) = current_descriptor_getters() {
+        let mode = Mode::from(mode);
+
+        let trans_id = TransId::from(trans_id);
+
+        DECODE_FAILED.with(|failed| failed.set(false));
+
+        let class_code = decode_field(class_code, "class_code");
+
+        let sec_code = decode_field(sec_code, "sec_code");
+
+        if drop_undecoded_event("order_status_callback") {
+            return;
+        }
+
+        let is_sell = IsSell::from(is_sell);
+
+        let status = Status::from(status);
+
+        let date = match getters.order_date(order_descriptor) {
+            Ok(date) => date,
+            Err(e) => {
+                error!("order_date error: {}", e);
+                None
+            }
+        };
+
+        let time = match getters.order_time(order_descriptor) {
+            Ok(time) => time,
+            Err(e) => {
+                error!("order_time error: {}", e);
+                None
+            }
+        };
+
+        let qty = (getters.trans2quik_order_qty)(order_descriptor);
+
+        let exec_condition = getters
+            .trans2quik_order_exec_type
+            .map_or(ExecCondition::Unknown, |order_exec_type| {
+                ExecCondition::from(order_exec_type(order_descriptor))
+            });
+
+        info!("TRANS2QUIK_ORDER_STATUS_CALLBACK -> mode: {:?}, trans_id: {:?}, order_num: {}, class_code: {}, sec_code: {}, price: {}, qty: {}, balance: {}, value: {}, is_sell: {}, status: {:?}, date: {:?}, time: {:?}, exec_condition: {:?}", mode, trans_id, order_num, class_code, sec_code, log_decimal(price), qty, balance, log_decimal(value), is_sell, status, date, time, exec_condition);
+
+        let order_info = OrderInfo {
+            mode,
+            trans_id,
+            order_num,
+            class_code,
+            sec_code,
+            price,
+            qty,
+            balance,
+            value,
+            is_sell,
+            status,
+            date,
+            time,
+            exec_condition,
+            seq: ORDER_STATUS_SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+        };
+
+        {
+            let mut recent_orders = RECENT_ORDERS.lock().unwrap();
+            if recent_orders.len() == RECENT_ORDERS_CAPACITY {
+                recent_orders.pop_front();
+            }
+            recent_orders.push_back(order_info.clone());
+        }
+
+        {
+            let mut instrument_stats = INSTRUMENT_STATS.lock().unwrap();
+            let stats = instrument_stats
+                .entry((order_info.class_code.clone(), order_info.sec_code.clone()))
+                .or_default();
+            stats.orders += 1;
+            stats.last_event = Some(Instant::now());
+        }
+
+        let snapshot_complete = order_info.mode == Mode::LastOrderReceived;
+
+        deliver_or_withhold(CallbackEvent::Order(order_info));
+
+        // After the last initial order is sent to the channels.
+        if snapshot_complete {
+            ORDERS_SNAPSHOT_COMPLETE.store(true, Ordering::SeqCst);
+            ORDERS_SNAPSHOT_NOTIFY.notify_waiters();
+        }
+    } else {
+        error!("TERMINAL_INSTANCE is not initialized");
+    }
+}
+
+/// Callback function to get information about the transaction.
+unsafe extern "C" fn trade_status_callback(
+    mode: c_long,
+    trade_num: c_ulonglong,
+    order_num: c_ulonglong,
+    class_code: *mut c_char,
+    sec_code: *mut c_char,
+    price: c_double,
+    quantity: i64,
+    is_sell: c_long,
+    value: c_double,
+    trade_descriptor: intptr_t,
+) {
+    catch_callback_panic("trade_status_callback", || {
+        on_trade_status(
+            mode,
+            trade_num,
+            order_num,
+            class_code,
+            sec_code,
+            price,
+            quantity,
+            is_sell,
+            value,
+            trade_descriptor,
+        )
+    });
+}
+
+/// The body of `trade_status_callback`.
+#[allow(clippy::too_many_arguments)]
+unsafe fn on_trade_status(
+    mode: c_long,
+    trade_num: c_ulonglong,
+    order_num: c_ulonglong,
+    class_code: *mut c_char,
+    sec_code: *mut c_char,
+    price: c_double,
+    quantity: i64,
+    is_sell: c_long,
+    value: c_double,
+    trade_descriptor: intptr_t,
+) {
+    watchdog::touch();
+    telemetry::callback_event("trades");
+
+    if let Some(getters) = current_descriptor_getters() {
+        let mode = Mode::from(mode);
+
+        DECODE_FAILED.with(|failed| failed.set(false));
+
+        let class_code = decode_field(class_code, "class_code");
+
+        let sec_code = decode_field(sec_code, "sec_code");
+
+        let is_sell = IsSell::from(is_sell);
+
+        let date = (getters.trans2quik_trade_date)(trade_descriptor);
+
+        let date = match format_date(date) {
+            Ok(date) => date,
+            Err(e) => {
+                error!("format_date error: {}", e);
+                None
+            }
+        };
+
+        let time = (getters.trans2quik_trade_time)(trade_descriptor);
+
+        let time = match format_time(time) {
+            Ok(time) => time,
+            Err(e) => {
+                error!("format_time error: {}", e);
+                None
+            }
+        };
+
+        let settle_code = (getters.trans2quik_trade_settle_code)(trade_descriptor);
+
+        let settle_code = decode_field(settle_code, "settle_code");
+
+        let settle_time = getters
+            .trans2quik_trade_settle_time
+            .and_then(
+                |trade_settle_time| match format_time(trade_settle_time(trade_descriptor)) {
+                    Ok(settle_time) => settle_time,
+                    Err(e) => {
+                        error!("format_time error: {}", e);
+                        None
+                    }
+                },
+            );
+
+        let is_marginal = getters
+            .trans2quik_trade_is_marginal
+            .map(|trade_is_marginal| trade_is_marginal(trade_descriptor) != 0);
+
+        let trade_kind = TradeKind::classify(&class_code, is_marginal);
+
+        // Empty if the library doesn't export the getter.
+        let decode_optional = |getter: Option<unsafe extern "C" fn(intptr_t) -> *mut c_char>,
+                               field| {
+            getter.map_or_else(String::new, |getter| {
+                decode_field(getter(trade_descriptor), field)
+            })
+        };
+
+        let user_id = decode_optional(getters.trans2quik_trade_userid, "user_id");
+
+        let firm_id = decode_optional(getters.trans2quik_trade_firm_id, "firm_id");
+
+        let price_currency = decode_optional(getters.trans2quik_trade_currency, "price_currency");
+
+        let settle_currency =
+            decode_optional(getters.trans2quik_trade_settle_currency, "settle_currency");
+
+        let uid = getters
+            .trans2quik_trade_uid
+            .map_or(0, |trade_uid| format_uid(trade_uid(trade_descriptor)));
+
+        if drop_undecoded_event("trade_status_callback") {
+            return;
+        }
+
+        info!("TRANS2QUIK_TRADE_STATUS_CALLBACK -> mode: {:?}, trade_num: {}, order_num: {}, class_code: {}, sec_code: {}, price: {}, quantity: {}, is_sell: {}, value: {}, date: {:?}, time: {:?}, settle_code: {}, settle_time: {:?}, trade_kind: {:?}, user_id: {}, firm_id: {}, uid: {}, price_currency: {}, settle_currency: {}", mode, trade_num, order_num, class_code, sec_code, log_decimal(price), quantity, is_sell, log_decimal(value), date, time, settle_code, settle_time, trade_kind, user_id, firm_id, uid, price_currency, settle_currency);
+
+        let trade_info = TradeInfo {
+            mode,
+            trade_num,
+            order_num,
+            class_code,
+            sec_code,
+            price,
+            quantity,
+            is_sell,
+            value,
+            date,
+            time,
+            settle_code,
+            settle_time,
+            trade_kind,
+            user_id,
+            firm_id,
+            price_currency,
+            settle_currency,
+            uid,
+            seq: TRADE_STATUS_SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+        };
+
+        if trade_info.value_diverges() {
+            warn!(
+                "trade {}: value {} differs from price * quantity {}, accrued interest or lot size",
+                trade_num,
+                value,
+                trade_info.computed_value()
+            );
+        }
+
+        {
+            let mut instrument_stats = INSTRUMENT_STATS.lock().unwrap();
+            let stats = instrument_stats
+                .entry((trade_info.class_code.clone(), trade_info.sec_code.clone()))
+                .or_default();
+            stats.trades += 1;
+            stats.last_event = Some(Instant::now());
+        }
+
+        if trade_info.mode == Mode::NewOrder {
+            LAST_TRADE_TIMES.lock().unwrap().insert(
+                (trade_info.class_code.clone(), trade_info.sec_code.clone()),
+                Instant::now(),
+            );
+        }
+
+        deliver_or_withhold(CallbackEvent::Trade(trade_info));
+    } else {
+        error!("TERMINAL_INSTANCE is not initialized");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trans2quik_result_conversion() {
+        assert_eq!(Trans2QuikResult::from(0), Trans2QuikResult::Success);
+        assert_eq!(Trans2QuikResult::from(1), Trans2QuikResult::Failed);
+        assert_eq!(
+            Trans2QuikResult::from(2),
+            Trans2QuikResult::TerminalNotFound
+        );
+        assert_eq!(
+            Trans2QuikResult::from(3),
+            Trans2QuikResult::DllVersionNotSupported
+        );
+        assert_eq!(
+            Trans2QuikResult::from(4),
+            Trans2QuikResult::AlreadyConnectedToQuik
+        );
+        assert_eq!(Trans2QuikResult::from(5), Trans2QuikResult::WrongSyntax);
+        assert_eq!(
+            Trans2QuikResult::from(6),
+            Trans2QuikResult::QuikNotConnected
+        );
+        assert_eq!(Trans2QuikResult::from(7), Trans2QuikResult::DllNotConnected);
+        assert_eq!(Trans2QuikResult::from(8), Trans2QuikResult::QuikConnected);
+        assert_eq!(
+            Trans2QuikResult::from(9),
+            Trans2QuikResult::QuikDisconnected
+        );
+        assert_eq!(Trans2QuikResult::from(10), Trans2QuikResult::DllConnected);
+        assert_eq!(
+            Trans2QuikResult::from(11),
+            Trans2QuikResult::DllDisconnected
+        );
+        assert_eq!(
+            Trans2QuikResult::from(12),
+            Trans2QuikResult::MemoryAllocationError
+        );
+        assert_eq!(
+            Trans2QuikResult::from(13),
+            Trans2QuikResult::WrongConnectionHandle
+        );
+        assert_eq!(
+            Trans2QuikResult::from(14),
+            Trans2QuikResult::WrongInputParams
+        );
+        assert_eq!(Trans2QuikResult::from(999), Trans2QuikResult::Unknown(999));
+    }
+
+    #[test]
+    fn test_load_library_invalid_path() {
+        assert!(load_library("/invalid/path/to/nonexistent/trans2quik.dll").is_err());
+
+        let result = Terminal::new("/invalid/path/to/nonexistent/trans2quik.dll", r"C:\QUIK");
+        assert!(matches!(result, Err(Trans2QuikError::LibLoading(_))));
+    }
+
+    #[test]
+    fn test_new_with_options_restores_current_dir() {
+        let _test_lock = current_dir::test_lock();
+        let current_dir = std::env::current_dir().unwrap();
+        let path_to_lib = std::env::temp_dir().join("nonexistent_trans2quik.dll");
+        let options = TerminalOptions {
+            set_current_dir: true,
+        };
+
+        let result = Terminal::new_with_options(path_to_lib.to_str().unwrap(), r"C:\QUIK", options);
+
+        assert!(matches!(result, Err(Trans2QuikError::LibLoading(_))));
+        assert_eq!(std::env::current_dir().unwrap(), current_dir);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_from_library_without_symbols() {
+        // The process itself is a library without the functions of Trans2QUIK.dll.
+        let library: Arc<Library> = Arc::new(libloading::os::unix::Library::this().into());
+
+        let result = Terminal::from_library(Arc::clone(&library), String::from(r"C:\QUIK"));
+
+        match result {
+            Err(Trans2QuikError::LibLoading(err)) => {
+                assert!(err.to_string().contains("TRANS2QUIK_CONNECT"))
+            }
+            _ => panic!("expected the error of loading TRANS2QUIK_CONNECT"),
+        }
+        assert_eq!(Arc::strong_count(&library), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_symbol_loader() {
+        let library: Library = libloading::os::unix::Library::this().into();
+        let mut symbols = SymbolLoader::new(&library);
+
+        symbols.load::<unsafe extern "C" fn()>("malloc").unwrap();
+        assert!(symbols
+            .load::<unsafe extern "C" fn()>("TRANS2QUIK_CONNECT")
+            .is_err());
+        symbols.load::<unsafe extern "C" fn()>("free").unwrap();
+
+        assert_eq!(symbols.loaded, vec!["malloc", "free"]);
+    }
+
+    #[test]
+    fn test_loaded_symbols() {
+        let terminal = mock::terminal();
+        let loaded_symbols = terminal.loaded_symbols();
+
+        for name in [
+            "TRANS2QUIK_CONNECT",
+            "TRANS2QUIK_SEND_ASYNC_TRANSACTION",
+            "TRANS2QUIK_TRADE_SETTLE_CODE",
+            "TRANS2QUIK_TRADE_USERID",
+            "TRANS2QUIK_TRADE_FIRMID",
+            "TRANS2QUIK_TRADE_UID",
+            "TRANS2QUIK_TRADE_CURRENCY",
+            "TRANS2QUIK_TRADE_SETTLE_CURRENCY",
+            "TRANS2QUIK_ORDER_QTY",
+        ] {
+            assert!(loaded_symbols.contains(&name), "{} is not loaded", name);
+        }
+    }
+
+    #[test]
+    fn test_trans2quikerror_from_libloadingerror() {
+        // Attempt to load a non-existent library to produce a LibloadingError
+        let libloading_error = unsafe {
+            match Library::new("/invalid/path/to/nonexistent/library") {
+                Ok(_) => panic!("Expected an error, but library loaded successfully."),
+                Err(e) => e,
+            }
+        };
+
+        // Convert it into a Trans2QuikError
+        let trans2quik_error: Trans2QuikError = Trans2QuikError::from(libloading_error);
+
+        // Assert that it matches the expected enumeration variant
+        if let Trans2QuikError::LibLoading(_) = trans2quik_error {
+            // Passed: we correctly converted the error
+        } else {
+            panic!("Expected Trans2QuikError::LibLoading variant.");
+        }
+    }
+
+    #[test]
+    fn test_trans2quikerror_from_nulerror() {
+        // Create a NulError by attempting to construct a CString with an embedded null byte
+        let nul_err = CString::new("Invalid\0String").unwrap_err();
+
+        // Convert it into a Trans2QuikError
+        let trans2quik_error: Trans2QuikError = Trans2QuikError::from(nul_err);
+
+        // Assert that it matches the expected enumeration variant
+        matches!(trans2quik_error, Trans2QuikError::NulError(_));
+    }
+
+    #[test]
+    fn test_display_for_trans2quikerror() {
+        // Test conversion and display message for NulError
+        let nul_err = CString::new("Invalid\0String").unwrap_err();
+        let trans2quik_error_nul: Trans2QuikError = Trans2QuikError::from(nul_err);
+
+        // Test display format for NulError
+        let expected_display_nul = format!("{:?}", trans2quik_error_nul);
+        assert_eq!(expected_display_nul, format!("{}", trans2quik_error_nul));
+
+        // For LibloadingError: simulate a common error scenario
+        // Open a library with an invalid path to trigger a DlOpen error
+        let libloading_error = unsafe {
+            match Library::new("/invalid/path/to/nonexistent/lib") {
+                Ok(_) => panic!("Expected an error, but library loaded successfully"),
+                Err(e) => e,
+            }
+        };
+        let trans2quik_error_lib: Trans2QuikError = Trans2QuikError::from(libloading_error);
+
+        // Test display format for LibLoading error
+        let expected_display_lib = format!("{:?}", trans2quik_error_lib);
+        assert_eq!(expected_display_lib, format!("{}", trans2quik_error_lib));
+    }
+
+    #[test]
+    fn test_transaction_reply_handler_access() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let handler_replies = Arc::clone(&replies);
+        terminal.set_transaction_reply_handler(move |transaction_info, access| {
+            let firm_id = access.string("TRANS2QUIK_TRANSACTION_REPLY_FIRMID");
+            handler_replies.lock().unwrap().push((
+                transaction_info.trans_id == TransId::Id(7),
+                transaction_info.sec_code.clone(),
+                transaction_info.price,
+                firm_id.ok(),
+                matches!(
+                    access.string("TRANS2QUIK_TRANSACTION_REPLY_UNKNOWN"),
+                    Err(Trans2QuikError::UnknownGetter(_))
+                ) && matches!(
+                    access.integer("TRANS2QUIK_DISCONNECT"),
+                    Err(Trans2QuikError::UnknownGetter(_))
+                ) && matches!(
+                    access.double("TRANS2QUIK_TRANSACTION_REPLY_FIRMID"),
+                    Err(Trans2QuikError::UnknownGetter(_))
+                ),
+            ));
+        });
+
+        mock::transaction_reply(mock::Reply {
+            trans_id: 7,
+            sec_code: "SBER",
+            price: 250.5,
+            firm_id: "MC0002500000",
+            ..Default::default()
+        });
+
+        let replies = replies.lock().unwrap();
+        assert_eq!(
+            *replies,
+            vec![(
+                true,
+                String::from("SBER"),
+                250.5,
+                Some(String::from("MC0002500000")),
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn test_transaction_param() {
+        let transaction_str = "ACTION=NEW_ORDER; TRANS_ID=12; CLASSCODE=TQBR; SECCODE=SBER";
+
+        assert_eq!(transaction_param(transaction_str, "TRANS_ID"), Some("12"));
+        assert_eq!(transaction_param(transaction_str, "SECCODE"), Some("SBER"));
+        assert_eq!(transaction_param(transaction_str, "PRICE"), None);
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+    }
+
+    fn replacement_order() -> TransactionBuilder {
+        TransactionBuilder::new(Action::NewOrder)
+            .trans_id(7)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .account("L01-00000F00")
+            .operation(IsSell::Buy)
+            .price(251.0)
+            .quantity(1)
+    }
+
+    fn reply_to(transaction_str: &str, reply_code: c_long) -> Option<mock::Reply> {
+        let trans_id = transaction_param(transaction_str, "TRANS_ID")?;
+        Some(mock::Reply {
+            reply_code,
+            trans_id: trans_id.parse().ok()?,
+            order_num: 54321,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_replace_order() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 3));
+
+        let (cancel, replacement) = runtime()
+            .block_on(terminal.replace_order(12345, replacement_order()))
+            .unwrap();
+
+        assert!(cancel.is_executed());
+        let replacement = replacement.unwrap();
+        assert!(replacement.is_executed());
+        assert_eq!(replacement.trans_id, TransId::Id(7));
+
+        let sent = mock::sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].starts_with("ACTION=KILL_ORDER;"));
+        assert_eq!(transaction_param(&sent[0], "ORDER_KEY"), Some("12345"));
+        assert!(sent[1].starts_with("ACTION=NEW_ORDER;"));
+    }
+
+    #[test]
+    fn test_send_idempotent() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 3));
+
+        let first = runtime()
+            .block_on(terminal.send_idempotent("buy-sber", replacement_order()))
+            .unwrap();
+        let second = runtime()
+            .block_on(terminal.send_idempotent("buy-sber", replacement_order().trans_id(8)))
+            .unwrap();
+
+        assert_eq!(first.order_num, 54321);
+        assert_eq!(second.order_num, first.order_num);
+        assert_eq!(second.trans_id, TransId::Id(7));
+        assert_eq!(mock::sent().len(), 1);
+
+        runtime()
+            .block_on(terminal.send_idempotent("buy-sber-2", replacement_order().trans_id(8)))
+            .unwrap();
+        assert_eq!(mock::sent().len(), 2);
+    }
+
+    #[test]
+    fn test_send_idempotent_dropped_after_sending() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        runtime().block_on(async {
+            // The caller gives up before the reply arrives.
+            let first = tokio::time::timeout(
+                Duration::from_millis(20),
+                terminal.send_idempotent("buy-sber", replacement_order()),
+            )
+            .await;
+            assert!(first.is_err());
+
+            // The retry doesn't send the order again.
+            let retry = terminal
+                .send_idempotent("buy-sber", replacement_order().trans_id(8))
+                .await;
+            assert!(matches!(retry, Err(Trans2QuikError::Timeout)));
+        });
+
+        assert_eq!(mock::sent().len(), 1);
+    }
+
+    #[test]
+    fn test_replace_order_cancel_rejected() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 4));
+
+        let (cancel, replacement) = runtime()
+            .block_on(terminal.replace_order(12345, replacement_order()))
+            .unwrap();
+
+        assert!(!cancel.is_executed());
+        assert!(replacement.is_none());
+
+        let sent = mock::sent();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("ACTION=KILL_ORDER;"));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal().with_default_account("L01-00000F00", "OPEN12345");
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 3));
+
+        let results = runtime().block_on(terminal.flatten(
+            &[("TQBR", "SBER"), ("TQBR", "GAZP")],
+            &[
+                ("TQBR", "SBER", 10),
+                ("TQBR", "LKOH", 0),
+                ("TQBR", "GAZP", -5),
+            ],
+        ));
+
+        assert_eq!(results.len(), 4);
+        assert!(results
+            .iter()
+            .all(|result| result.as_ref().unwrap().is_executed()));
+
+        let sent = mock::sent();
+        let summary: Vec<_> = sent
+            .iter()
+            .map(|transaction_str| {
+                (
+                    transaction_param(transaction_str, "ACTION").unwrap(),
+                    transaction_param(transaction_str, "SECCODE").unwrap(),
+                    transaction_param(transaction_str, "OPERATION"),
+                    transaction_param(transaction_str, "QUANTITY"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("KILL_ALL_ORDERS", "SBER", None, None),
+                ("KILL_ALL_ORDERS", "GAZP", None, None),
+                ("NEW_ORDER", "SBER", Some("S"), Some("10")),
+                ("NEW_ORDER", "GAZP", Some("B"), Some("5")),
+            ]
+        );
+        assert_eq!(transaction_param(&sent[2], "TYPE"), Some("M"));
+        assert_eq!(transaction_param(&sent[2], "PRICE"), Some("0"));
+
+        // The quantity saturates instead of overflowing.
+        runtime().block_on(terminal.flatten(&[], &[("TQBR", "SBER", i64::MIN)]));
+        let sent = mock::sent();
+        assert_eq!(transaction_param(&sent[4], "OPERATION"), Some("B"));
+        assert_eq!(
+            transaction_param(&sent[4], "QUANTITY"),
+            Some("9223372036854775807")
+        );
+    }
+
+    #[test]
+    fn test_fault_injection() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let transaction_str = replacement_order().build().unwrap();
+
+        // The injected results don't reach the library.
+        for _ in 0..2 {
+            inject_fault(
+                "TRANS2QUIK_SEND_ASYNC_TRANSACTION",
+                Fault::result(Trans2QuikResult::QuikNotConnected),
+            );
+        }
+        let trans2quik_result = runtime()
+            .block_on(terminal.send_async_transaction_retry(
+                &transaction_str,
+                3,
+                Duration::from_millis(1),
+            ))
+            .unwrap();
+        assert_eq!(trans2quik_result, Trans2QuikResult::Success);
+        assert_eq!(mock::sent().len(), 1);
+
+        inject_fault(
+            "TRANS2QUIK_SEND_SYNC_TRANSACTION",
+            Fault::result(Trans2QuikResult::WrongSyntax),
+        );
+        assert_eq!(
+            terminal.send_sync_transaction(&transaction_str).unwrap(),
+            Trans2QuikResult::WrongSyntax
+        );
+
+        inject_fault(
+            "TRANS2QUIK_CONNECT",
+            Fault::delay(Duration::from_millis(50)),
+        );
+        let started = Instant::now();
+        assert_eq!(terminal.connect().unwrap(), Trans2QuikResult::Success);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(mock::connections().len(), 1);
+
+        inject_fault(
+            "TRANS2QUIK_SUBSCRIBE_ORDERS",
+            Fault::result(Trans2QuikResult::QuikNotConnected),
+        );
+        clear_faults();
+        terminal.subscribe_orders("TQBR", "SBER").unwrap();
+    }
+
+    #[test]
+    fn test_send_async_transaction_retry() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let transaction_str = replacement_order().build().unwrap();
+
+        mock::set_send_results(&[6, 7]);
+        let trans2quik_result = runtime()
+            .block_on(terminal.send_async_transaction_retry(
+                &transaction_str,
+                3,
+                Duration::from_millis(1),
+            ))
+            .unwrap();
+        assert_eq!(trans2quik_result, Trans2QuikResult::Success);
+        assert_eq!(mock::sent().len(), 3);
+
+        mock::set_send_results(&[6, 6, 6, 6]);
+        let trans2quik_result = runtime()
+            .block_on(terminal.send_async_transaction_retry(
+                &transaction_str,
+                2,
+                Duration::from_millis(1),
+            ))
+            .unwrap();
+        assert_eq!(trans2quik_result, Trans2QuikResult::QuikNotConnected);
+        assert_eq!(mock::sent().len(), 6);
+
+        // Not retried.
+        mock::set_send_results(&[5]);
+        let trans2quik_result = runtime()
+            .block_on(terminal.send_async_transaction_retry(
+                &transaction_str,
+                2,
+                Duration::from_millis(1),
+            ))
+            .unwrap();
+        assert_eq!(trans2quik_result, Trans2QuikResult::WrongSyntax);
+        assert_eq!(mock::sent().len(), 7);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_millis(100);
+
+        for _ in 0..100 {
+            let jittered = jitter(delay);
+            assert!(jittered >= delay / 2 && jittered <= delay);
+        }
+    }
+
+    #[test]
+    fn test_last_connection_event() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        assert_eq!(terminal.last_connection_event(), None);
+
+        mock::connection_status(10);
+        assert_eq!(
+            terminal.last_connection_event(),
+            Some(ConnectionEvent::DllConnected)
+        );
+
+        mock::connection_status(9);
+        assert_eq!(
+            terminal.last_connection_event(),
+            Some(ConnectionEvent::QuikDisconnected(None))
+        );
+
+        // Not a connection event.
+        mock::connection_status(1);
+        assert_eq!(
+            terminal.last_connection_event(),
+            Some(ConnectionEvent::QuikDisconnected(None))
+        );
+    }
+
+    #[test]
+    fn test_conn_state() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        assert_eq!(terminal.conn_state(), ConnState::Disconnected);
+
+        terminal.connect().unwrap();
+        assert_eq!(terminal.conn_state(), ConnState::DllConnected);
+
+        mock::connection_status(8);
+        assert_eq!(terminal.conn_state(), ConnState::QuikConnected);
+
+        // Already connected, the state is kept.
+        mock::set_connect_results(&[4]);
+        terminal.connect().unwrap();
+        mock::connection_status(10);
+        assert_eq!(terminal.conn_state(), ConnState::QuikConnected);
+
+        mock::connection_status(9);
+        assert_eq!(terminal.conn_state(), ConnState::DllConnected);
+
+        mock::connection_status(11);
+        assert_eq!(terminal.conn_state(), ConnState::Disconnected);
+
+        mock::connection_status(10);
+        assert_eq!(terminal.conn_state(), ConnState::DllConnected);
+
+        // The failed connection doesn't change the state.
+        terminal.disconnect().unwrap();
+        mock::set_connect_results(&[1]);
+        terminal.connect().unwrap();
+        assert_eq!(terminal.conn_state(), ConnState::Disconnected);
+    }
+
+    #[test]
+    fn test_conn_state_auto_reconnect() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        runtime().block_on(async {
+            let reconnect = terminal.enable_auto_reconnect(Duration::from_millis(20));
+            terminal.connect().unwrap();
+            mock::connection_status(8);
+
+            mock::set_connect_results(&[1]);
+            mock::connection_status(11);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(terminal.conn_state(), ConnState::Reconnecting);
+
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while terminal.conn_state() != ConnState::DllConnected {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(mock::connections().len(), 3);
+
+            // Stopped while reconnecting.
+            mock::set_quik_connected_results(&[6; 100]);
+            mock::connection_status(9);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(terminal.conn_state(), ConnState::Reconnecting);
+            reconnect.stop();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(terminal.conn_state(), ConnState::DllConnected);
+        });
+    }
+
+    #[test]
+    fn test_next_disconnect() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        runtime().block_on(async {
+            let disconnect = terminal.next_disconnect();
+            tokio::pin!(disconnect);
+
+            // The first poll subscribes to the connection events.
+            tokio::select! {
+                biased;
+                event = &mut disconnect => panic!("unexpected {:?}", event),
+                _ = tokio::task::yield_now() => {}
+            }
+
+            mock::connection_status(10);
+            mock::connection_status(8);
+            mock::connection_error(9, 1236, "Соединение разорвано");
+
+            assert_eq!(
+                disconnect.await,
+                ConnectionEvent::QuikDisconnected(Some((
+                    1236,
+                    String::from("Соединение разорвано")
+                )))
+            );
+        });
+    }
+
+    #[test]
+    fn test_connection_event_error() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        // The error code of the connection is not meaningful.
+        mock::connection_error(8, 1236, "Соединение разорвано");
+        assert_eq!(
+            terminal.last_connection_event(),
+            Some(ConnectionEvent::QuikConnected)
+        );
+
+        mock::connection_error(11, 1236, "Соединение разорвано");
+        assert_eq!(
+            terminal.last_connection_event(),
+            Some(ConnectionEvent::DllDisconnected(Some((
+                1236,
+                String::from("Соединение разорвано")
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_send_transactions_from_file() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/transactions.tri");
+
+        let results = terminal
+            .send_transactions_from_file(&path, SendMode::Async)
+            .unwrap();
+
+        let lines: Vec<usize> = results.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![1, 2, 4, 5]);
+        assert!(matches!(results[0].1, Ok(Trans2QuikResult::Success)));
+        assert!(matches!(
+            results[2].1,
+            Err(Trans2QuikError::TransactionBuild(
+                TransactionBuildError::MissingParam("ACTION")
+            ))
+        ));
+
+        let sent = mock::sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(transaction_param(&sent[1], "COMMENT"), Some("Робот"));
+        assert!(sent[2].starts_with("ACTION=KILL_ORDER;"));
+
+        let missing =
+            terminal.send_transactions_from_file(Path::new("missing.tri"), SendMode::Sync);
+        assert!(matches!(missing, Err(Trans2QuikError::Io(_))));
+    }
+
+    #[test]
+    fn test_pending_transactions() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        terminal.set_transactions_reply_callback().unwrap();
+
+        for trans_id in 1..=3 {
+            terminal
+                .send_async_transaction(&format!("ACTION=NEW_ORDER; TRANS_ID={}", trans_id))
+                .unwrap();
+        }
+        assert_eq!(terminal.pending_transactions(), 3);
+
+        for trans_id in 1..=2 {
+            mock::transaction_reply(mock::Reply {
+                trans_id,
+                ..Default::default()
+            });
+        }
+        assert_eq!(terminal.pending_transactions(), 1);
+    }
+
+    #[test]
+    fn test_pending_transactions_without_reply_callback() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.set_missing_reply_callback(MissingReplyCallback::FireAndForget);
+
+        terminal
+            .send_async_transaction("ACTION=NEW_ORDER; TRANS_ID=1")
+            .unwrap();
+        assert_eq!(terminal.pending_transactions(), 0);
+    }
+
+    #[test]
+    fn test_drain_pending() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        terminal.set_transactions_reply_callback().unwrap();
+
+        runtime().block_on(async {
+            for trans_id in 1..=2 {
+                terminal
+                    .send_async_transaction(&format!("ACTION=NEW_ORDER; TRANS_ID={}", trans_id))
+                    .unwrap();
+            }
+
+            let replies = tokio::spawn(async {
+                for trans_id in 1..=2 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    mock::transaction_reply(mock::Reply {
+                        trans_id,
+                        ..Default::default()
+                    });
+                }
+            });
+
+            terminal
+                .drain_pending(Duration::from_secs(1))
+                .await
+                .unwrap();
+            replies.await.unwrap();
+
+            terminal
+                .send_async_transaction("ACTION=NEW_ORDER; TRANS_ID=3")
+                .unwrap();
+            assert!(matches!(
+                terminal.drain_pending(Duration::from_millis(20)).await,
+                Err(Trans2QuikError::Timeout)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.set_rate_limit(20);
+
+        // The first 20 transactions go at once, the next 5 wait for 50ms each.
+        let start = Instant::now();
+        for _ in 0..25 {
+            terminal.send_async_transaction("ACTION=NEW_ORDER").unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(mock::sent().len(), 25);
+
+        terminal.set_rate_limit_mode(RateLimitMode::WouldBlock);
+        let clone = terminal.clone();
+        for _ in 0..20 {
+            let _ = clone.send_async_transaction("ACTION=NEW_ORDER");
+        }
+        assert!(matches!(
+            terminal.send_async_transaction("ACTION=NEW_ORDER"),
+            Err(Trans2QuikError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    fn test_async_rate_limit_does_not_block() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.set_rate_limit(20);
+
+        let ticked = Arc::new(AtomicBool::new(false));
+        runtime().block_on(async {
+            let task_ticked = Arc::clone(&ticked);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                task_ticked.store(true, Ordering::SeqCst);
+            });
+
+            // The 21st transaction waits for the limiter about 50ms.
+            for trans_id in 1..=21 {
+                let transaction_str = format!("ACTION=NEW_ORDER; TRANS_ID={}", trans_id);
+                terminal
+                    .send_async_transaction_retry(&transaction_str, 0, Duration::ZERO)
+                    .await
+                    .unwrap();
+            }
+
+            // The other task ran while waiting.
+            assert!(ticked.load(Ordering::SeqCst));
+        });
+
+        assert_eq!(mock::sent().len(), 21);
+    }
+
+    #[test]
+    fn test_cancel_by_trans_id_waits_for_order_num() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        runtime().block_on(async {
+            terminal
+                .send_async_transaction(
+                    "ACTION=NEW_ORDER; TRANS_ID=5; CLASSCODE=TQBR; SECCODE=SBER; OPERATION=B; PRICE=250,5; QUANTITY=1",
+                )
+                .unwrap();
+
+            let cancel_terminal = terminal.clone();
+            let cancel =
+                tokio::spawn(async move { cancel_terminal.cancel_by_trans_id(5).await });
+
+            // The reply with the order number arrives after the cancel request.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            mock::transaction_reply(mock::Reply {
+                trans_id: 5,
+                order_num: 12345,
+                ..Default::default()
+            });
+
+            let result = cancel.await.unwrap().unwrap();
+            assert_eq!(result, Trans2QuikResult::Success);
+
+            let unknown = terminal.cancel_by_trans_id(6).await;
+            assert!(matches!(unknown, Err(Trans2QuikError::UnknownTransId(6))));
+        });
+
+        let sent = mock::sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1].starts_with("ACTION=KILL_ORDER;"));
+        assert_eq!(transaction_param(&sent[1], "CLASSCODE"), Some("TQBR"));
+        assert_eq!(transaction_param(&sent[1], "SECCODE"), Some("SBER"));
+        assert_eq!(transaction_param(&sent[1], "ORDER_KEY"), Some("12345"));
+    }
+
+    #[test]
+    fn test_sent_orders_tracking() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        terminal
+            .send_async_transaction(
+                "ACTION=KILL_ORDER; TRANS_ID=5; CLASSCODE=TQBR; SECCODE=SBER; ORDER_KEY=1",
+            )
+            .unwrap();
+        assert!(SENT_ORDERS.lock().unwrap().is_empty());
+
+        terminal
+            .send_async_transaction("ACTION=NEW_ORDER; TRANS_ID=6; CLASSCODE=TQBR; SECCODE=SBER")
+            .unwrap();
+        assert!(SENT_ORDERS.lock().unwrap().contains_key(&6));
+
+        // The rejected transaction placed no order.
+        mock::transaction_reply(mock::Reply {
+            trans_id: 6,
+            order_num: 0,
+            reply_code: 4,
+            ..Default::default()
+        });
+        assert!(SENT_ORDERS.lock().unwrap().is_empty());
+
+        for trans_id in 1..=SENT_ORDERS_CAPACITY + 1 {
+            track_sent_order(&format!(
+                "ACTION=NEW_ORDER; TRANS_ID={}; CLASSCODE=TQBR; SECCODE=SBER",
+                trans_id
+            ));
+        }
+        let sent_orders = SENT_ORDERS.lock().unwrap();
+        assert_eq!(sent_orders.len(), SENT_ORDERS_CAPACITY);
+        assert!(!sent_orders.contains_key(&1));
+    }
+
+    #[test]
+    fn test_error_code_description() {
+        assert_eq!(ErrorCode::from(0).description(), "No error");
+        assert_eq!(ErrorCode::from(5).description(), "Access is denied");
+        assert_eq!(
+            ErrorCode::from(233).description(),
+            "No process is on the other end of the pipe"
+        );
+        assert_eq!(ErrorCode::from(-42).description(), "Unknown error code");
+        assert_eq!(
+            ErrorCode::from(2).to_string(),
+            "2 (The system cannot find the file specified)"
+        );
+    }
+
+    #[test]
+    fn test_is_sell_round_trip() {
+        assert_eq!(IsSell::from(0), IsSell::Buy);
+        assert_eq!(IsSell::from(1), IsSell::Sell);
+        assert_eq!(IsSell::Buy.as_c_long(), 0);
+        assert_eq!(IsSell::Sell.as_c_long(), 1);
+
+        for is_sell in [IsSell::Buy, IsSell::Sell] {
+            assert_eq!(IsSell::from(is_sell.as_c_long()), is_sell);
+        }
+
+        assert_eq!(IsSell::Buy.to_string(), "Buy");
+        assert_eq!(IsSell::Sell.to_string(), "Sell");
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn test_reply_code() {
+        assert_eq!(ReplyCode::from(0), ReplyCode::Sent);
+        assert_eq!(ReplyCode::from(3), ReplyCode::Executed);
+        assert_eq!(ReplyCode::from(4), ReplyCode::Rejected);
+        assert_eq!(ReplyCode::from(6), ReplyCode::LimitsExceeded);
+        assert_eq!(ReplyCode::from(13), ReplyCode::CrossTrade);
+        assert_eq!(ReplyCode::from(42), ReplyCode::Unknown(42));
+
+        for code in [0, 1, 2, 3, 4, 5, 6, 10, 11, 12, 13, 42] {
+            assert_eq!(ReplyCode::from(code).as_i32(), code as i32);
+        }
+    }
+
+    #[test]
+    fn test_transaction_info_typed_codes() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRANSACTION_REPLY_SENDER.store(Some(Arc::new(sender.into())));
+
+        mock::transaction_reply(mock::Reply {
+            error_code: 233,
+            reply_code: 6,
+            trans_id: 0,
+            ..Default::default()
+        });
+
+        let transaction_info = receiver.try_recv().unwrap();
+        assert_eq!(
+            transaction_info.rejection_reason,
+            Some(RejectionReason::Other(String::new()))
+        );
+        assert_eq!(transaction_info.error_code, ErrorCode(233));
+        assert_eq!(transaction_info.error_code.as_i32(), 233);
+        assert_eq!(transaction_info.reply_code, ReplyCode::LimitsExceeded);
+        assert_eq!(transaction_info.reply_code.as_i32(), 6);
+        assert_eq!(transaction_info.trans_id, TransId::Unknown(0));
+        assert_eq!(transaction_info.trans_id.as_i32(), 0);
+        assert!(!transaction_info.is_executed());
+    }
+
+    #[test]
+    fn test_encode_lpstr() {
+        let transaction_str = TransactionBuilder::new(Action::KillOrder)
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .order_key(12345)
+            .comment("Робот")
+            .build()
+            .unwrap();
+
+        let encoded = encode_lpstr(&transaction_str).unwrap();
+        assert!(encoded
+            .as_bytes()
+            .ends_with(&[b'=', 0xD0, 0xEE, 0xE1, 0xEE, 0xF2]));
+        assert_eq!(
+            decode_lpstr(encoded.as_ptr() as *mut c_char).unwrap(),
+            transaction_str
+        );
+
+        assert!(encode_lpstr("COMMENT=a\0b").is_err());
+    }
+
+    #[test]
+    fn test_transaction_info_rejection_reason() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRANSACTION_REPLY_SENDER.store(Some(Arc::new(sender.into())));
+
+        mock::transaction_reply(mock::Reply {
+            reply_code: 4,
+            reply_message: "(161) Цена заявки вне лимитов по инструменту",
+            ..Default::default()
+        });
+        mock::transaction_reply(mock::Reply {
+            reply_message: "Заявка зарегистрирована",
+            ..Default::default()
+        });
+
+        let rejected = receiver.try_recv().unwrap();
+        assert_eq!(
+            rejected.rejection_reason,
+            Some(RejectionReason::PriceOutOfLimits)
+        );
+
+        let executed = receiver.try_recv().unwrap();
+        assert_eq!(executed.rejection_reason, None);
+    }
+
+    #[test]
+    fn test_decode_field() {
+        // 0x98 is not assigned in WINDOWS-1251.
+        let malformed = CString::new(vec![0x98, 0xD1, 0xC1, 0xC5, 0xD0]).unwrap();
+        let decoded = decode_field(malformed.as_ptr() as *mut c_char, "sec_code");
+        assert!(decoded.ends_with("СБЕР"));
+
+        assert_eq!(decode_field(std::ptr::null_mut(), "sec_code"), "");
+    }
+
+    #[test]
+    fn test_strict_decoding() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let (order_sender, mut receiver) = mpsc::unbounded_channel();
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
+
+        let class_code = CString::new("TQBR").unwrap();
+        // The null pointer instead of sec_code.
+        let malformed_order = || {
+            let order = mock::Order::default();
+            unsafe {
+                order_status_callback(
+                    order.mode,
+                    order.trans_id,
+                    order.order_num,
+                    class_code.as_ptr() as *mut c_char,
+                    std::ptr::null_mut(),
+                    order.price,
+                    order.balance,
+                    order.value,
+                    order.is_sell,
+                    order.status,
+                    mock::order_descriptor(&order),
+                )
+            }
+        };
+
+        malformed_order();
+        assert_eq!(receiver.try_recv().unwrap().sec_code, "");
+
+        terminal.set_strict_decoding(true);
+        malformed_order();
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(terminal.undecoded_events_dropped(), 1);
+
+        // The valid events are delivered.
+        mock::order(mock::Order::default());
+        assert_eq!(receiver.try_recv().unwrap().sec_code, "SBER");
+        mock::trade(mock::Trade::default());
+        assert_eq!(terminal.undecoded_events_dropped(), 1);
+    }
+
+    #[test]
+    fn test_decode_lpstr_error_bytes() {
+        let err =
+            DecodeLpstrError::from(CString::new(vec![0xD1, 0xC1, 0, b'E', b'R']).unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            "Nul byte at position 2 of the string b\"\\xd1\\xc1\\x00ER\""
+        );
+        assert_eq!(
+            DecodeLpstrError::NullPointer.to_string(),
+            "Null pointer instead of the string"
+        );
+
+        let long = escape_bytes(&[0x98; 100]);
+        assert!(long.starts_with(&"\\x98".repeat(ESCAPED_BYTES_LIMIT)));
+        assert!(long.ends_with("... (100 bytes)"));
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0).unwrap(), None);
+        assert_eq!(
+            format_date(20241115).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 11, 15)
+        );
+        assert!(matches!(
+            format_date(20241345),
+            Err(DateTimeError::ParseError(_))
+        ));
+        assert!(matches!(format_date(-1), Err(DateTimeError::InvalidDate)));
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0).unwrap(), None);
+        assert_eq!(
+            format_time(93005).unwrap(),
+            NaiveTime::from_hms_opt(9, 30, 5)
+        );
+        assert!(matches!(
+            format_time(256100),
+            Err(DateTimeError::ParseError(_))
+        ));
+        assert!(matches!(format_time(-1), Err(DateTimeError::InvalidTime)));
+    }
+
+    #[test]
+    fn test_to_quik_date_time() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 5).unwrap();
+        assert_eq!(to_quik_date(date), 20240105);
+        assert_eq!(
+            format_date(c_long::from(to_quik_date(date))).unwrap(),
+            Some(date)
+        );
+        assert_eq!(
+            to_quik_date(format_date(20241115).unwrap().unwrap()),
+            20241115
+        );
+
+        let time = NaiveTime::from_hms_opt(9, 30, 5).unwrap();
+        assert_eq!(to_quik_time(time), 93005);
+        assert_eq!(
+            format_time(c_long::from(to_quik_time(time))).unwrap(),
+            Some(time)
+        );
+        assert_eq!(to_quik_time(format_time(235959).unwrap().unwrap()), 235959);
+        assert_eq!(
+            to_quik_time(NaiveTime::from_hms_milli_opt(10, 0, 0, 500).unwrap()),
+            100000
+        );
+    }
+
+    #[test]
+    fn test_order_date_time_accessors() {
+        let _guard = mock::lock();
+        let getters = mock::terminal().descriptor_getters();
+
+        let descriptor = mock::order_descriptor(&mock::Order {
+            date: 0,
+            time: 0,
+            ..Default::default()
+        });
+        assert_eq!(getters.order_date(descriptor).unwrap(), None);
+        assert_eq!(getters.order_time(descriptor).unwrap(), None);
+
+        let descriptor = mock::order_descriptor(&mock::Order {
+            date: 20241115,
+            time: 93005,
+            ..Default::default()
+        });
+        assert_eq!(
+            getters.order_date(descriptor).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 11, 15)
+        );
+        assert_eq!(
+            getters.order_time(descriptor).unwrap(),
+            NaiveTime::from_hms_opt(9, 30, 5)
+        );
+
+        let descriptor = mock::order_descriptor(&mock::Order {
+            date: 20241340,
+            time: 256100,
+            ..Default::default()
+        });
+        assert!(matches!(
+            getters.order_date(descriptor),
+            Err(DateTimeError::ParseError(_))
+        ));
+        assert!(matches!(
+            getters.order_time(descriptor),
+            Err(DateTimeError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_order_and_trade_date_time() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let (order_sender, mut order_receiver) = tokio::sync::mpsc::unbounded_channel();
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
+        let (trade_sender, mut trade_receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.into())));
+
+        mock::order(mock::Order::default());
+        mock::order(mock::Order {
+            date: 0,
+            time: 0,
+            ..Default::default()
+        });
+        mock::trade(mock::Trade {
+            date: 20241340,
+            ..Default::default()
+        });
+
+        let order_info = order_receiver.try_recv().unwrap();
+        assert_eq!(order_info.date, NaiveDate::from_ymd_opt(2024, 11, 15));
+        assert_eq!(order_info.time, NaiveTime::from_hms_opt(10, 30, 0));
+
+        let order_info = order_receiver.try_recv().unwrap();
+        assert_eq!(order_info.date, None);
+        assert_eq!(order_info.time, None);
+
+        let trade_info = trade_receiver.try_recv().unwrap();
+        assert_eq!(trade_info.date, None);
+        assert_eq!(trade_info.time, NaiveTime::from_hms_opt(10, 30, 0));
+    }
+
+    #[test]
+    fn test_trade_status_bounded_channel_overflow() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let (trade_sender, mut trade_receiver) = bounded_channel(2, OverflowPolicy::DropNewest);
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.clone())));
+
+        for trade_num in 1..=5 {
+            mock::trade(mock::Trade {
+                trade_num,
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(trade_sender.dropped(), 3);
+        assert_eq!(trade_receiver.try_recv().unwrap().trade_num, 1);
+        assert_eq!(trade_receiver.try_recv().unwrap().trade_num, 2);
+        assert!(trade_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_trade_settle_code() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let (trade_sender, mut trade_receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.into())));
+
+        mock::trade(mock::Trade {
+            settle_code: "Y0",
+            ..Default::default()
+        });
+
+        let trade_info = trade_receiver.try_recv().unwrap();
+        assert_eq!(trade_info.settle_code, "Y0");
+    }
+
+    #[test]
+    fn test_trade_settle_time() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut trades = terminal.subscribe_trades_broadcast();
+
+        mock::trade(mock::Trade {
+            settle_time: 190000,
+            ..Default::default()
+        });
+        mock::trade(mock::Trade::default());
+        assert_eq!(
+            trades.try_recv().unwrap().settle_time,
+            NaiveTime::from_hms_opt(19, 0, 0)
+        );
+        assert_eq!(trades.try_recv().unwrap().settle_time, None);
+
+        // The library without TRANS2QUIK_TRADE_SETTLE_TIME.
+        reset_globals();
+        let terminal = Terminal {
+            trans2quik_trade_settle_time: None,
+            ..mock::terminal()
+        };
+        terminal.start_trades().unwrap();
+        mock::trade(mock::Trade {
+            settle_time: 190000,
+            ..Default::default()
+        });
+        assert_eq!(trades.try_recv().unwrap().settle_time, None);
+    }
+
+    #[test]
+    fn test_trade_kind() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut trades = terminal.subscribe_trades_broadcast();
+
+        mock::trade(mock::Trade::default());
+        mock::trade(mock::Trade {
+            is_marginal: 1,
+            ..Default::default()
+        });
+        mock::trade(mock::Trade {
+            class_code: "EQRP_INFO",
+            ..Default::default()
+        });
+        let kinds: Vec<TradeKind> = (0..3)
+            .map(|_| trades.try_recv().unwrap().trade_kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![TradeKind::Spot, TradeKind::Margin, TradeKind::Repo]
+        );
+
+        // The library without TRANS2QUIK_TRADE_IS_MARGINAL.
+        reset_globals();
+        let terminal = Terminal {
+            trans2quik_trade_is_marginal: None,
+            ..mock::terminal()
+        };
+        terminal.start_trades().unwrap();
+        mock::trade(mock::Trade {
+            is_marginal: 1,
+            ..Default::default()
+        });
+        assert_eq!(trades.try_recv().unwrap().trade_kind, TradeKind::Unknown);
+    }
+
+    #[test]
+    fn test_trade_currency() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut trades = terminal.subscribe_trades_broadcast();
+
+        mock::trade(mock::Trade {
+            class_code: "CETS",
+            sec_code: "CNYRUB_TOM",
+            price_currency: "SUR",
+            settle_currency: "CNY",
+            ..Default::default()
+        });
+
+        let trade_info = trades.try_recv().unwrap();
+        assert_eq!(trade_info.price_currency, "SUR");
+        assert_eq!(trade_info.settle_currency, "CNY");
+    }
+
+    #[test]
+    fn test_order_fill() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let (order_sender, mut order_receiver) = tokio::sync::mpsc::unbounded_channel();
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
+
+        mock::order(mock::Order {
+            qty: 10,
+            balance: 4,
+            ..Default::default()
+        });
+        mock::order(mock::Order {
+            qty: 0,
+            balance: 0,
+            ..Default::default()
+        });
+
+        let order_info = order_receiver.try_recv().unwrap();
+        assert_eq!(order_info.qty, 10);
+        assert_eq!(order_info.filled(), 6);
+        assert_eq!(order_info.fill_ratio(), 0.6);
+
+        let order_info = order_receiver.try_recv().unwrap();
+        assert_eq!(order_info.filled(), 0);
+        assert_eq!(order_info.fill_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_trade_signed_quantity() {
+        let buy = TradeInfo {
+            quantity: 10,
+            is_sell: IsSell::Buy,
+            ..Default::default()
+        };
+        assert_eq!(buy.signed_quantity(), 10);
+
+        let sell = TradeInfo {
+            quantity: 10,
+            is_sell: IsSell::Sell,
+            ..Default::default()
+        };
+        assert_eq!(sell.signed_quantity(), -10);
+
+        let signed_sell = TradeInfo {
+            quantity: -10,
+            is_sell: IsSell::Sell,
+            ..Default::default()
+        };
+        assert_eq!(signed_sell.signed_quantity(), -10);
+
+        let min_buy = TradeInfo {
+            quantity: i64::MIN,
+            is_sell: IsSell::Buy,
+            ..Default::default()
+        };
+        assert_eq!(min_buy.signed_quantity(), i64::MAX);
+    }
+
+    #[test]
+    fn test_trade_computed_value() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let (trade_sender, mut trade_receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.into())));
+
+        mock::trade(mock::Trade {
+            price: 250.5,
+            quantity: 10,
+            value: 2505.0,
+            ..Default::default()
+        });
+        // The bond with the accrued interest.
+        mock::trade(mock::Trade {
+            price: 98.5,
+            quantity: 2,
+            value: 2003.4,
+            ..Default::default()
+        });
+
+        let trade_info = trade_receiver.try_recv().unwrap();
+        assert_eq!(trade_info.computed_value(), 2505.0);
+        assert!(!trade_info.value_diverges());
+
+        let trade_info = trade_receiver.try_recv().unwrap();
+        assert_eq!(trade_info.computed_value(), 197.0);
+        assert_eq!(trade_info.value, 2003.4);
+        assert!(trade_info.value_diverges());
+    }
+
+    #[test]
+    fn test_broadcast_subscribers() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let (order_sender, mut order_receiver) = tokio::sync::mpsc::unbounded_channel();
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
+
+        let mut logger = terminal.subscribe_orders_broadcast();
+        let mut strategy = terminal.subscribe_orders_broadcast();
+
+        for order_num in 1..=2 {
+            mock::order(mock::Order {
+                order_num,
+                ..Default::default()
+            });
+        }
+
+        for receiver in [&mut logger, &mut strategy] {
+            assert_eq!(receiver.try_recv().unwrap().order_num, 1);
+            assert_eq!(receiver.try_recv().unwrap().order_num, 2);
+            assert!(receiver.try_recv().is_err());
+        }
+        assert_eq!(order_receiver.try_recv().unwrap().order_num, 1);
+        assert_eq!(order_receiver.try_recv().unwrap().order_num, 2);
+
+        let mut trades = terminal.subscribe_trades_broadcast();
+        mock::trade(mock::Trade::default());
+        assert_eq!(trades.try_recv().unwrap().trade_num, 1);
+    }
+
+    #[test]
+    fn test_subscribe_error() {
+        assert_eq!(SubscribeError::check(Trans2QuikResult::Success), Ok(()));
+        assert_eq!(
+            SubscribeError::check(Trans2QuikResult::Failed),
+            Err(SubscribeError::UnknownInstrument)
+        );
+        assert_eq!(
+            SubscribeError::check(Trans2QuikResult::QuikNotConnected),
+            Err(SubscribeError::QuikNotConnected)
+        );
+        assert_eq!(
+            SubscribeError::check(Trans2QuikResult::DllNotConnected),
+            Err(SubscribeError::DllNotConnected)
+        );
+        assert_eq!(
+            SubscribeError::check(Trans2QuikResult::MemoryAllocationError),
+            Err(SubscribeError::Other(
+                Trans2QuikResult::MemoryAllocationError
+            ))
+        );
+
+        let terminal = mock::terminal();
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+    }
+
+    #[test]
+    fn test_format_log_decimal() {
+        assert_eq!(format_log_decimal(250.5, false), "250.5");
+        assert_eq!(format_log_decimal(250.5, true), "250,5");
+        assert_eq!(format_log_decimal(7103.0, true), "7103");
+        assert_eq!(format_log_decimal(-0.0001, true), "-0,0001");
+    }
+
+    #[test]
+    fn test_send_async_transaction_bytes() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.set_transactions_reply_callback().unwrap();
+
+        let mut bytes = b"ACTION=NEW_ORDER; TRANS_ID=11; COMMENT=".to_vec();
+        // "Робот" in WINDOWS-1251.
+        bytes.extend_from_slice(&[0xD0, 0xEE, 0xE1, 0xEE, 0xF2]);
+
+        let trans2quik_result = terminal.send_async_transaction_bytes(&bytes).unwrap();
+        assert_eq!(trans2quik_result, Trans2QuikResult::Success);
+        assert_eq!(
+            mock::sent(),
+            vec!["ACTION=NEW_ORDER; TRANS_ID=11; COMMENT=Робот"]
+        );
+        assert_eq!(terminal.pending_transactions(), 1);
+
+        assert!(matches!(
+            terminal.send_async_transaction_bytes(b"ACTION=NEW_ORDER;\0 TRANS_ID=12"),
+            Err(Trans2QuikError::NulError(_))
+        ));
+        assert_eq!(mock::sent().len(), 1);
+    }
+
+    #[test]
+    fn test_callback_panic_hook() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let hook_messages = Arc::clone(&messages);
+        terminal.set_callback_panic_hook(Box::new(move |info| {
+            hook_messages.lock().unwrap().push(info.to_string());
+        }));
+        terminal.set_transaction_reply_handler(|_, _| panic!("handler failed"));
+
+        mock::transaction_reply(mock::Reply {
+            trans_id: 1,
+            ..Default::default()
+        });
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("handler failed"));
+
+        // The following callbacks are not affected by the panic.
+        terminal.set_transaction_reply_handler(|_, _| {});
+        mock::transaction_reply(mock::Reply {
+            trans_id: 2,
+            ..Default::default()
+        });
+        assert_eq!(messages.len(), 1);
+
+        // Panics outside of the callback functions are not reported.
+        let _ = std::panic::catch_unwind(|| panic!("not a callback"));
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn test_set_instrument_info() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal().with_default_account("L01-00000F00", "OPEN12345");
+        let table =
+            InstrumentTable::from_csv("SPBFUT;SiZ6;1;1\nTQBR;SBER;10;0,01".as_bytes()).unwrap();
+        terminal.clone().set_instrument_info(table);
+
+        let transaction_str = terminal
+            .transaction(Action::NewOrder)
+            .trans_id(1)
+            .class_code("SPBFUT")
+            .sec_code("SiZ6")
+            .operation(IsSell::Sell)
+            .price(95123.4)
+            .quantity_units(2)
+            .build()
+            .unwrap();
+
+        assert!(transaction_str
+            .ends_with("; PRICE=95123; QUANTITY=2; ACCOUNT=L01-00000F00; CLIENT_CODE=OPEN12345"));
+    }
+
+    #[test]
+    fn test_subscribe_orders_best_effort() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.subscribe_orders("TQBR", "SBER").unwrap();
+
+        // SBRE and SPBFUT are unknown to the terminal.
+        mock::set_subscribe_results(&[1, 0, 1]);
+        let results = terminal.subscribe_orders_best_effort(&[
+            ("TQBR", "SBER"),
+            ("TQBR", "SBRE"),
+            ("TQBR", "GAZP"),
+            ("TQBR", "LK\0OH"),
+            ("SPBFUT", "SiZ4"),
+        ]);
+
+        let results: Vec<_> = results
+            .iter()
+            .map(|((class_code, sec_code), result)| {
+                (class_code.as_str(), sec_code.as_str(), result.clone())
+            })
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("TQBR", "SBER", Trans2QuikResult::Success),
+                ("TQBR", "SBRE", Trans2QuikResult::Failed),
+                ("TQBR", "GAZP", Trans2QuikResult::Success),
+                ("TQBR", "LK\0OH", Trans2QuikResult::WrongInputParams),
+                ("SPBFUT", "SiZ4", Trans2QuikResult::Failed),
+            ]
+        );
+
+        // Only the successful subscriptions are recorded.
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "GAZP").unwrap(),
+            SubscribeOutcome::AlreadySubscribed
+        );
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "SBRE").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+    }
+
+    #[test]
+    fn test_subscribe_twice() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let clone = terminal.clone();
+
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+        assert_eq!(
+            clone.subscribe_orders("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::AlreadySubscribed
+        );
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "GAZP").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+        assert_eq!(
+            terminal.subscribe_trades("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+
+        terminal.unsubscribe_orders().unwrap();
+        assert_eq!(
+            terminal.subscribe_orders("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::Subscribed
+        );
+        assert_eq!(
+            terminal.subscribe_trades("TQBR", "SBER").unwrap(),
+            SubscribeOutcome::AlreadySubscribed
+        );
+    }
+
+    #[test]
+    fn test_connect_to() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        terminal.connect().unwrap();
+        terminal.connect_to(r"D:\QUIK Junior").unwrap();
+
+        assert_eq!(
+            mock::connections(),
+            vec![String::from(r"C:\QUIK"), String::from(r"D:\QUIK Junior")]
+        );
+        assert!(matches!(
+            Terminal::load("invalid_path.dll"),
+            Err(Trans2QuikError::LibLoading(_))
+        ));
+    }
+
+    #[test]
+    fn test_event_seq() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut orders = terminal.subscribe_orders_broadcast();
+        let mut trades = terminal.subscribe_trades_broadcast();
+        let mut replies = terminal.subscribe_transaction_replies_broadcast();
+
+        for n in 1..=3 {
+            mock::order(mock::Order {
+                order_num: n,
+                ..Default::default()
+            });
+            mock::trade(mock::Trade {
+                trade_num: n,
+                ..Default::default()
+            });
+            mock::transaction_reply(mock::Reply {
+                trans_id: n as c_long,
+                ..Default::default()
+            });
+        }
+
+        let order_seqs: Vec<u64> = (0..3).map(|_| orders.try_recv().unwrap().seq).collect();
+        let trade_seqs: Vec<u64> = (0..3).map(|_| trades.try_recv().unwrap().seq).collect();
+        let reply_seqs: Vec<u64> = (0..3).map(|_| replies.try_recv().unwrap().seq).collect();
+
+        for seqs in [order_seqs, trade_seqs, reply_seqs] {
+            assert_eq!(seqs[1], seqs[0] + 1);
+            assert_eq!(seqs[2], seqs[1] + 1);
+        }
+    }
+
+    #[test]
+    fn test_last_error() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        terminal.connect().unwrap();
+        assert_eq!(terminal.last_error(), None);
+
+        mock::set_send_results(&[1]);
+        let result = terminal
+            .send_async_transaction("ACTION=KILL_ORDER; TRANS_ID=1")
+            .unwrap();
+        assert_eq!(result, Trans2QuikResult::Failed);
+
+        terminal
+            .send_async_transaction("ACTION=KILL_ORDER; TRANS_ID=2")
+            .unwrap();
+        assert_eq!(
+            terminal.clone().last_error(),
+            Some((1, String::from("Transaction not sent")))
+        );
+    }
+
+    #[test]
+    fn test_orders_snapshot_complete() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut orders = terminal.subscribe_orders_broadcast();
+        terminal.start_orders();
+
+        runtime().block_on(async {
+            let snapshot = terminal.orders_snapshot_complete();
+            tokio::pin!(snapshot);
+            tokio::select! {
+                biased;
+                _ = &mut snapshot => panic!("snapshot completed before the orders"),
+                _ = tokio::task::yield_now() => {}
+            }
+
+            for (order_num, mode) in [(1, 1), (2, 1), (3, 2)] {
+                mock::order(mock::Order {
+                    mode,
+                    order_num,
+                    ..Default::default()
+                });
+            }
+            snapshot.await;
+
+            let modes: Vec<Mode> = (0..3).map(|_| orders.try_recv().unwrap().mode).collect();
+            assert_eq!(
+                modes,
+                vec![
+                    Mode::InitialOrder,
+                    Mode::InitialOrder,
+                    Mode::LastOrderReceived
+                ]
+            );
+
+            // Resolves immediately after the snapshot.
+            mock::order(mock::Order {
+                order_num: 4,
+                ..Default::default()
+            });
+            terminal.orders_snapshot_complete().await;
+        });
+    }
+
+    #[test]
+    fn test_duplicate_trans_id() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let transaction_str = "ACTION=KILL_ORDER; TRANS_ID=7";
+
+        runtime().block_on(async {
+            let first = terminal.send_async_transaction_and_wait(transaction_str);
+            tokio::pin!(first);
+            tokio::select! {
+                biased;
+                result = &mut first => panic!("unexpected {:?}", result),
+                _ = tokio::task::yield_now() => {}
+            }
+
+            assert!(matches!(
+                terminal
+                    .send_async_transaction_and_wait(transaction_str)
+                    .await,
+                Err(Trans2QuikError::DuplicateTransId(7))
+            ));
+            assert_eq!(mock::sent().len(), 1);
+
+            mock::transaction_reply(reply_to(transaction_str, 3).unwrap());
+            assert!(first.await.unwrap().is_executed());
+        });
+
+        // The waiter of the dropped future doesn't block the TRANS_ID.
+        runtime().block_on(async {
+            let dropped = terminal.send_async_transaction_and_wait(transaction_str);
+            let _ = tokio::time::timeout(Duration::from_millis(1), dropped).await;
+
+            mock::set_auto_reply(|transaction| reply_to(transaction, 3));
+            assert!(terminal
+                .send_async_transaction_and_wait(transaction_str)
+                .await
+                .is_ok());
+        });
+    }
+
+    #[test]
+    fn test_send_sync() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        let transaction_info = terminal.send_sync(replacement_order()).unwrap();
+        assert!(transaction_info.is_executed());
+        assert_eq!(transaction_info.order_num, mock::SYNC_ORDER_NUM);
+        assert_eq!(transaction_info.trans_id, TransId::Id(7));
+
+        mock::set_sync_reply(4, "Недостаточно средств");
+        match terminal.send_sync(replacement_order()) {
+            Err(Trans2QuikError::Rejected(transaction_info)) => {
+                assert_eq!(transaction_info.reply_code, ReplyCode::Rejected);
+                assert_eq!(transaction_info.order_num, 0);
+                assert_eq!(transaction_info.reply_message, "Недостаточно средств");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let invalid = TransactionBuilder::new(Action::NewOrder).trans_id(8);
+        assert!(matches!(
+            terminal.send_sync(invalid),
+            Err(Trans2QuikError::TransactionBuild(_))
+        ));
+        assert_eq!(mock::sent().len(), 2);
+    }
+
+    #[test]
+    fn test_send_sync_transaction_with_report() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let transaction_str =
+            "ACTION=NEW_ORDER; TRANS_ID=5; CLASSCODE=TQBR; SECCODE=SBER; PRICE=250,5";
+        let order_num = mock::SYNC_ORDER_NUM;
+
+        runtime().block_on(async {
+            let (reply, execution) = terminal
+                .send_sync_transaction_with_report(transaction_str, Duration::from_secs(5))
+                .unwrap();
+            assert!(reply.is_executed());
+            assert_eq!(reply.trans_id.as_i32(), 5);
+            assert_eq!(reply.order_num, order_num);
+            assert_eq!(reply.sec_code, "SBER");
+            assert_eq!(reply.price, 250.5);
+            assert_eq!(reply.reply_message, "Order accepted");
+
+            // Received while the synchronous transaction is sent.
+            mock::order(mock::Order {
+                order_num,
+                status: 1,
+                ..Default::default()
+            });
+
+            tokio::pin!(execution);
+            tokio::select! {
+                biased;
+                result = &mut execution => panic!("unexpected {:?}", result),
+                _ = tokio::task::yield_now() => {}
+            }
+
+            mock::order(mock::Order {
+                order_num,
+                status: 3,
+                ..Default::default()
+            });
+            assert_eq!(execution.await.unwrap().status, Status::Executed);
+        });
+    }
+
+    #[test]
+    fn test_info_default() {
+        let order_info = OrderInfo {
+            order_num: 1,
+            qty: 10,
+            balance: 4,
+            ..Default::default()
+        };
+        assert_eq!(order_info.mode, Mode::Unknown);
+        assert_eq!(order_info.status, Status::Active);
+        assert_eq!(order_info.trans_id, TransId::Unknown(0));
+        assert_eq!(order_info.filled(), 6);
+
+        let trade_info = TradeInfo {
+            price: 250.5,
+            quantity: 2,
+            is_sell: IsSell::Sell,
+            ..Default::default()
+        };
+        assert_eq!(trade_info.computed_value(), 501.0);
+        assert_eq!(trade_info.date, None);
+        assert!(trade_info.settle_code.is_empty());
+
+        let transaction_info = TransactionInfo {
+            reply_code: ReplyCode::Executed,
+            trans2quik_result: Trans2QuikResult::Success,
+            ..Default::default()
+        };
+        assert!(transaction_info.is_executed());
+        assert!(!TransactionInfo::default().is_executed());
+        assert_eq!(transaction_info.error_code, ErrorCode(0));
+    }
 
-            if let Err(err) = sender.send(trade_info) {
-                error!("trade_status_callback send error: {}", err);
-            }
-        } else {
-            error!("TRADE_SENDER is not initialized");
+    #[test]
+    fn test_result_severity() {
+        assert_eq!(Trans2QuikResult::Success.severity(), Severity::Info);
+        assert_eq!(Trans2QuikResult::QuikConnected.severity(), Severity::Info);
+        assert_eq!(Trans2QuikResult::DllConnected.severity(), Severity::Info);
+        assert_eq!(
+            Trans2QuikResult::QuikNotConnected.severity(),
+            Severity::Warning
+        );
+        assert_eq!(
+            Trans2QuikResult::DllNotConnected.severity(),
+            Severity::Warning
+        );
+        assert_eq!(Trans2QuikResult::WrongSyntax.severity(), Severity::Error);
+        assert_eq!(
+            Trans2QuikResult::MemoryAllocationError.severity(),
+            Severity::Error
+        );
+        assert_eq!(
+            Trans2QuikResult::WrongInputParams.severity(),
+            Severity::Error
+        );
+        assert_eq!(Trans2QuikResult::from(100).severity(), Severity::Error);
+        assert!(Severity::Info < Severity::Warning && Severity::Warning < Severity::Error);
+    }
+
+    #[test]
+    fn test_auto_reconnect_stop() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        async fn wait_for_connections(count: usize) {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while mock::connections().len() < count {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap();
         }
-    } else {
-        error!("TERMINAL_INSTANCE is not initialized");
+
+        runtime().block_on(async {
+            let reconnect = terminal.enable_auto_reconnect(Duration::from_millis(10));
+
+            mock::connection_status(11);
+            wait_for_connections(1).await;
+
+            reconnect.stop();
+            assert!(reconnect.is_stopped());
+            mock::connection_status(11);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(mock::connections().len(), 1);
+
+            // Stopped by the disconnection.
+            let reconnect = terminal.enable_auto_reconnect(Duration::from_millis(10));
+            terminal.disconnect().unwrap();
+            assert!(reconnect.is_stopped());
+            mock::connection_status(11);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(mock::connections().len(), 1);
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_auto_reconnect_does_not_block() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        mock::set_connect_delay(Duration::from_millis(300));
+
+        runtime().block_on(async {
+            let reconnect = terminal.enable_auto_reconnect(Duration::from_millis(10));
+            mock::connection_status(11);
+
+            // The tasks of the runtime run while TRANS2QUIK_CONNECT hangs.
+            let started = Instant::now();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(started.elapsed() < Duration::from_millis(200));
+
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while mock::connections().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap();
+            reconnect.stop();
+        });
+    }
 
     #[test]
-    fn test_trans2quik_result_conversion() {
-        assert_eq!(Trans2QuikResult::from(0), Trans2QuikResult::Success);
-        assert_eq!(Trans2QuikResult::from(1), Trans2QuikResult::Failed);
+    fn test_missing_trans_id() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        // Not correlated without the transaction_reply_callback.
+        terminal
+            .send_async_transaction("ACTION=KILL_ALL_ORDERS; CLASSCODE=TQBR")
+            .unwrap();
+
+        terminal.set_transactions_reply_callback().unwrap();
+        for transaction_str in [
+            "ACTION=KILL_ALL_ORDERS; CLASSCODE=TQBR",
+            "ACTION=KILL_ALL_ORDERS; TRANS_ID=0; CLASSCODE=TQBR",
+            "ACTION=KILL_ALL_ORDERS; TRANS_ID=abc; CLASSCODE=TQBR",
+        ] {
+            assert!(matches!(
+                terminal.send_async_transaction(transaction_str),
+                Err(Trans2QuikError::MissingTransId)
+            ));
+        }
+        assert_eq!(mock::sent().len(), 1);
+
         assert_eq!(
-            Trans2QuikResult::from(2),
-            Trans2QuikResult::TerminalNotFound
+            terminal
+                .send_async_transaction("ACTION=KILL_ALL_ORDERS; TRANS_ID=1; CLASSCODE=TQBR")
+                .unwrap(),
+            Trans2QuikResult::Success
         );
+        assert_eq!(mock::sent().len(), 2);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        use metrics_util::MetricKind;
+
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 4));
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            terminal
+                .send_async_transaction("ACTION=KILL_ALL_ORDERS; TRANS_ID=1; CLASSCODE=TQBR")
+                .unwrap();
+            terminal
+                .send_sync_transaction("ACTION=KILL_ALL_ORDERS; TRANS_ID=2; CLASSCODE=TQBR")
+                .unwrap();
+            mock::order(mock::Order::default());
+            mock::trade(mock::Trade::default());
+            mock::trade(mock::Trade::default());
+            mock::connection_status(11);
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let counter = |name: &str, label: Option<(&str, &str)>| -> u64 {
+            snapshot
+                .iter()
+                .filter(|(key, _, _, _)| {
+                    key.kind() == MetricKind::Counter
+                        && key.key().name() == name
+                        && label.is_none_or(|(label_key, label_value)| {
+                            key.key()
+                                .labels()
+                                .any(|l| l.key() == label_key && l.value() == label_value)
+                        })
+                })
+                .map(|(_, _, _, value)| match value {
+                    DebugValue::Counter(count) => *count,
+                    _ => 0,
+                })
+                .sum()
+        };
+
         assert_eq!(
-            Trans2QuikResult::from(3),
-            Trans2QuikResult::DllVersionNotSupported
+            counter(
+                "trans2quik_transactions_sent_total",
+                Some(("mode", "async"))
+            ),
+            1
         );
         assert_eq!(
-            Trans2QuikResult::from(4),
-            Trans2QuikResult::AlreadyConnectedToQuik
+            counter("trans2quik_transactions_sent_total", Some(("mode", "sync"))),
+            1
         );
-        assert_eq!(Trans2QuikResult::from(5), Trans2QuikResult::WrongSyntax);
+        assert_eq!(counter("trans2quik_transaction_replies_total", None), 2);
+        assert_eq!(counter("trans2quik_transaction_rejections_total", None), 1);
+        for (channel, count) in [
+            ("transaction_reply", 1),
+            ("orders", 1),
+            ("trades", 2),
+            ("connection", 1),
+        ] {
+            assert_eq!(
+                counter(
+                    "trans2quik_callback_events_total",
+                    Some(("channel", channel))
+                ),
+                count
+            );
+        }
         assert_eq!(
-            Trans2QuikResult::from(6),
-            Trans2QuikResult::QuikNotConnected
+            counter("trans2quik_disconnects_total", Some(("source", "dll"))),
+            1
         );
-        assert_eq!(Trans2QuikResult::from(7), Trans2QuikResult::DllNotConnected);
-        assert_eq!(Trans2QuikResult::from(8), Trans2QuikResult::QuikConnected);
+        assert!(snapshot.iter().any(|(key, _, _, _)| {
+            key.kind() == MetricKind::Histogram
+                && key.key().name() == "trans2quik_send_sync_duration_seconds"
+        }));
+    }
+
+    #[test]
+    fn test_reset_globals() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let other = mock::terminal();
+
+        terminal.start_trades().unwrap();
+        let _receivers = Terminal::channels();
+        mock::connection_status(10);
+        assert!(matches!(
+            other.start_trades(),
+            Err(Trans2QuikError::TerminalInUse)
+        ));
+
+        reset_globals();
+
+        assert!(TERMINAL_INSTANCE.lock().unwrap().is_none());
+        assert!(TRANSACTION_REPLY_SENDER.load().is_none());
+        assert!(ORDER_STATUS_SENDER.load().is_none());
+        assert!(TRADE_STATUS_SENDER.load().is_none());
+        assert_eq!(other.last_connection_event(), None);
+        other.start_trades().unwrap();
+    }
+
+    #[test]
+    fn test_auto_reconnect_events() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        async fn wait_until(condition: impl Fn() -> bool) {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while !condition() {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+
+        runtime().block_on(async {
+            let reconnect = terminal.enable_auto_reconnect(Duration::from_millis(1));
+
+            // QUIK reconnects to the server itself.
+            mock::set_quik_connected_results(&[6, 6]);
+            mock::connection_status(9);
+            wait_until(|| mock::quik_connected_checks() == 3).await;
+            assert!(mock::connections().is_empty());
+
+            mock::connection_status(11);
+            wait_until(|| mock::connections().len() == 1).await;
+            assert_eq!(mock::quik_connected_checks(), 3);
+
+            reconnect.stop();
+        });
+    }
+
+    #[test]
+    fn test_concurrent_callbacks() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut receivers = Terminal::channels();
+
+        // The handler calling the terminal doesn't deadlock the callbacks.
+        let handler_terminal = terminal.clone();
+        terminal.set_transaction_reply_handler(move |_, _| {
+            handler_terminal.start_trades().unwrap();
+        });
+
+        const THREADS: usize = 4;
+        const EVENTS: usize = 300;
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                std::thread::spawn(move || {
+                    for event in 0..EVENTS {
+                        match (thread + event) % 3 {
+                            0 => mock::order(mock::Order::default()),
+                            1 => mock::trade(mock::Trade::default()),
+                            _ => mock::transaction_reply(mock::Reply::default()),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut orders = 0;
+        while receivers.order_status.try_recv().is_ok() {
+            orders += 1;
+        }
+        let mut trades = 0;
+        while receivers.trade_status.try_recv().is_ok() {
+            trades += 1;
+        }
+        let mut replies = 0;
+        while receivers.transaction_reply.try_recv().is_ok() {
+            replies += 1;
+        }
+
+        assert_eq!(orders, THREADS * EVENTS / 3);
+        assert_eq!(trades, THREADS * EVENTS / 3);
+        assert_eq!(replies, THREADS * EVENTS / 3);
+    }
+
+    #[test]
+    fn test_subscribe_and_snapshot() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        runtime().block_on(async {
+            let subscribe = terminal.subscribe_and_snapshot(&[("TQBR", "SBER")]);
+            tokio::pin!(subscribe);
+
+            // The first poll subscribes and starts the orders.
+            tokio::select! {
+                biased;
+                _ = &mut subscribe => panic!("resolved before the snapshot"),
+                _ = tokio::task::yield_now() => {}
+            }
+
+            for (order_num, mode) in [(1, 1), (2, 1), (5, 0), (3, 2), (4, 0)] {
+                mock::order(mock::Order {
+                    mode,
+                    order_num,
+                    ..Default::default()
+                });
+            }
+
+            let (snapshot, updates) = subscribe.await.unwrap();
+            tokio::pin!(updates);
+
+            let order_nums: Vec<u64> = snapshot.iter().map(|order| order.order_num).collect();
+            assert_eq!(order_nums, vec![1, 2, 3]);
+            assert_eq!(updates.next().await.unwrap().order_num, 5);
+            assert_eq!(updates.next().await.unwrap().order_num, 4);
+
+            mock::order(mock::Order {
+                order_num: 6,
+                ..Default::default()
+            });
+            assert_eq!(updates.next().await.unwrap().order_num, 6);
+        });
+    }
+
+    #[test]
+    fn test_suppress_initial_orders() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        terminal.set_suppress_initial_orders(true);
+
+        let (order_sender, mut order_receiver) = tokio::sync::mpsc::unbounded_channel();
+        ORDER_STATUS_SENDER.store(Some(Arc::new(order_sender.into())));
+        let mut broadcast = terminal.subscribe_orders_broadcast();
+
+        for (order_num, mode) in [(1, 1), (2, 2), (3, 0)] {
+            mock::order(mock::Order {
+                mode,
+                order_num,
+                ..Default::default()
+            });
+        }
+
+        let order_info = order_receiver.try_recv().unwrap();
+        assert_eq!((order_info.order_num, order_info.mode), (3, Mode::NewOrder));
+        assert!(order_receiver.try_recv().is_err());
+        assert_eq!(broadcast.try_recv().unwrap().mode, Mode::InitialOrder);
+
+        runtime().block_on(terminal.orders_snapshot_complete());
+
+        terminal.set_suppress_initial_orders(false);
+        mock::order(mock::Order {
+            mode: 1,
+            order_num: 4,
+            ..Default::default()
+        });
+        assert_eq!(order_receiver.try_recv().unwrap().mode, Mode::InitialOrder);
+    }
+
+    #[test]
+    fn test_send_order_with_ttl() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        mock::set_auto_reply(|transaction_str| reply_to(transaction_str, 3));
+
+        runtime().block_on(async {
+            // Executed before the TTL.
+            let (reply, cancel) = terminal
+                .send_order_with_ttl(replacement_order(), Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert_eq!(reply.order_num, 54321);
+
+            mock::order(mock::Order {
+                order_num: 54321,
+                status: 3,
+                ..Default::default()
+            });
+            assert!(cancel.await.unwrap().unwrap().is_none());
+            assert_eq!(mock::sent().len(), 1);
+
+            // Still active after the TTL, the execution comes too late.
+            reset_globals();
+            terminal.start_trades().unwrap();
+            let (_, cancel) = terminal
+                .send_order_with_ttl(replacement_order().trans_id(8), Duration::from_millis(10))
+                .await
+                .unwrap();
+
+            mock::order(mock::Order {
+                order_num: 54321,
+                status: 1,
+                ..Default::default()
+            });
+            let kill_order = cancel.await.unwrap().unwrap().unwrap();
+            assert!(kill_order.is_executed());
+            mock::order(mock::Order {
+                order_num: 54321,
+                status: 3,
+                ..Default::default()
+            });
+
+            let sent = mock::sent();
+            assert_eq!(sent.len(), 3);
+            assert!(sent[2].starts_with("ACTION=KILL_ORDER;"));
+            assert_eq!(transaction_param(&sent[2], "ORDER_KEY"), Some("54321"));
+        });
+    }
+
+    #[test]
+    fn test_transaction_reply_account() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut replies = terminal.subscribe_transaction_replies_broadcast();
+
+        mock::transaction_reply(mock::Reply {
+            firm_id: "ФИРМА-01",
+            client_code: "Клиент 7",
+            account: "L01-00000F00",
+            ..Default::default()
+        });
+        mock::transaction_reply(mock::Reply::default());
+
+        let transaction_info = replies.try_recv().unwrap();
+        assert_eq!(transaction_info.firm_id, "ФИРМА-01");
+        assert_eq!(transaction_info.client_code, "Клиент 7");
+        assert_eq!(transaction_info.account, "L01-00000F00");
+
+        let transaction_info = replies.try_recv().unwrap();
+        assert!(transaction_info.firm_id.is_empty());
+        assert!(transaction_info.client_code.is_empty());
+        assert!(transaction_info.account.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_decimal_separator() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let transaction_str =
+            "ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER; PRICE=250.5; QUANTITY=1";
+
+        let err = terminal.send_sync_transaction(transaction_str).unwrap_err();
+        assert!(matches!(
+            &err,
+            Trans2QuikError::WrongDecimalSeparator("PRICE", value) if value == "250.5"
+        ));
         assert_eq!(
-            Trans2QuikResult::from(9),
-            Trans2QuikResult::QuikDisconnected
+            err.to_string(),
+            "PRICE=250.5 uses the dot as the decimal separator, use PRICE=250,5"
         );
-        assert_eq!(Trans2QuikResult::from(10), Trans2QuikResult::DllConnected);
+        assert!(matches!(
+            terminal.send_async_transaction(
+                "ACTION=NEW_STOP_ORDER; TRANS_ID=2; PRICE=250,5; STOPPRICE=251.0"
+            ),
+            Err(Trans2QuikError::WrongDecimalSeparator("STOPPRICE", _))
+        ));
+        assert!(mock::sent().is_empty());
+
+        assert!(terminal
+            .send_sync_transaction(&transaction_str.replace("250.5", "250,5"))
+            .is_ok());
+
+        terminal.set_check_decimal_separator(false);
+        assert!(terminal.send_sync_transaction(transaction_str).is_ok());
+        assert_eq!(mock::sent().len(), 2);
+    }
+
+    #[test]
+    fn test_delivered() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        assert_eq!(terminal.orders_delivered(), 0);
+
+        let mut receivers = Terminal::channels();
+        let (trade_sender, _trade_receiver) = bounded_channel(2, OverflowPolicy::DropNewest);
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.clone())));
+
+        for order_num in 1..=3 {
+            mock::order(mock::Order {
+                order_num,
+                ..Default::default()
+            });
+        }
+        for _ in 0..4 {
+            mock::trade(mock::Trade::default());
+        }
+        mock::transaction_reply(mock::Reply::default());
+
+        assert_eq!(terminal.orders_delivered(), 3);
+        assert_eq!(terminal.trades_delivered(), 2);
+        assert_eq!(trade_sender.dropped(), 2);
+        assert_eq!(terminal.transaction_replies_delivered(), 1);
+
+        // The closed channel delivers nothing.
+        receivers.order_status.close();
+        mock::order(mock::Order::default());
+        assert_eq!(terminal.orders_delivered(), 3);
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
         assert_eq!(
-            Trans2QuikResult::from(11),
-            Trans2QuikResult::DllDisconnected
+            terminal.connect_timeout(Duration::from_secs(5)).unwrap(),
+            Trans2QuikResult::Success
+        );
+        assert_eq!(mock::connections().len(), 1);
+
+        mock::set_connect_delay(Duration::from_millis(200));
+        let started = Instant::now();
+        assert!(matches!(
+            terminal.connect_timeout(Duration::from_millis(20)),
+            Err(Trans2QuikError::Timeout)
+        ));
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        // The abandoned call completes on the worker thread.
+        while mock::connections().len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_pause_events() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut receivers = Terminal::channels();
+
+        terminal.pause_events();
+        mock::order(mock::Order {
+            order_num: 1,
+            ..Default::default()
+        });
+        mock::trade(mock::Trade::default());
+        mock::order(mock::Order {
+            order_num: 2,
+            ..Default::default()
+        });
+        assert!(receivers.order_status.try_recv().is_err());
+        assert!(receivers.trade_status.try_recv().is_err());
+        // Still tracked while paused.
+        assert_eq!(RECENT_ORDERS.lock().unwrap().len(), 2);
+
+        terminal.resume_events();
+        assert_eq!(receivers.order_status.try_recv().unwrap().order_num, 1);
+        assert_eq!(receivers.order_status.try_recv().unwrap().order_num, 2);
+        assert!(receivers.trade_status.try_recv().is_ok());
+
+        mock::order(mock::Order {
+            order_num: 3,
+            ..Default::default()
+        });
+        assert_eq!(receivers.order_status.try_recv().unwrap().order_num, 3);
+
+        terminal.set_pause_policy(PausePolicy::Drop);
+        terminal.pause_events();
+        mock::order(mock::Order {
+            order_num: 4,
+            ..Default::default()
+        });
+        terminal.resume_events();
+        mock::order(mock::Order {
+            order_num: 5,
+            ..Default::default()
+        });
+        assert_eq!(receivers.order_status.try_recv().unwrap().order_num, 5);
+        assert!(receivers.order_status.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_ping() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        mock::set_sync_reply(4, "Не найдена заявка для удаления");
+        mock::set_sync_delay(Duration::from_millis(50));
+        let latency = terminal
+            .ping("TQBR", "GAZP", Duration::from_secs(5))
+            .unwrap();
+        assert!(latency >= Duration::from_millis(50));
+        assert!(latency < Duration::from_secs(5));
+
+        let sent = mock::sent();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with("ACTION=KILL_ORDER;"));
+        assert_eq!(transaction_param(&sent[0], "CLASSCODE"), Some("TQBR"));
+        assert_eq!(transaction_param(&sent[0], "SECCODE"), Some("GAZP"));
+        assert_eq!(transaction_param(&sent[0], "ORDER_KEY"), Some("0"));
+
+        mock::set_sync_delay(Duration::from_millis(300));
+        let started = Instant::now();
+        assert!(matches!(
+            terminal.ping("TQBR", "GAZP", Duration::from_millis(20)),
+            Err(Trans2QuikError::Timeout)
+        ));
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        // The abandoned transaction completes on the worker thread.
+        while mock::sent().len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The wait for the rate limiter is not counted.
+        mock::set_sync_delay(Duration::ZERO);
+        terminal.set_rate_limit(10);
+        for _ in 0..10 {
+            terminal.send_async_transaction("ACTION=NEW_ORDER").unwrap();
+        }
+        let started = Instant::now();
+        let latency = terminal
+            .ping("TQBR", "GAZP", Duration::from_secs(5))
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(latency < Duration::from_millis(50));
+        terminal.set_rate_limit(0);
+
+        // Not sent while the asynchronous transaction is pending.
+        terminal.set_transactions_reply_callback().unwrap();
+        terminal
+            .send_async_transaction("ACTION=NEW_ORDER; TRANS_ID=2")
+            .unwrap();
+        assert!(matches!(
+            terminal.ping("TQBR", "GAZP", Duration::from_secs(5)),
+            Err(Trans2QuikError::PendingTransactions(1))
+        ));
+        assert_eq!(mock::sent().len(), 14);
+    }
+
+    #[test]
+    fn test_exec_condition() {
+        assert_eq!(ExecCondition::from(1), ExecCondition::FillOrKill);
+        assert_eq!(ExecCondition::from(2), ExecCondition::PutInQueue);
+        assert_eq!(ExecCondition::from(3), ExecCondition::CancelBalance);
+        assert_eq!(ExecCondition::from(0), ExecCondition::Unknown);
+        assert_eq!(ExecCondition::from(4), ExecCondition::Unknown);
+
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut orders = terminal.subscribe_orders_broadcast();
+
+        mock::order(mock::Order {
+            exec_type: 3,
+            ..Default::default()
+        });
+        assert_eq!(
+            orders.try_recv().unwrap().exec_condition,
+            ExecCondition::CancelBalance
         );
+
+        // The library without TRANS2QUIK_ORDER_EXEC_TYPE.
+        reset_globals();
+        let terminal = Terminal {
+            trans2quik_order_exec_type: None,
+            ..mock::terminal()
+        };
+        terminal.start_trades().unwrap();
+        mock::order(mock::Order {
+            exec_type: 3,
+            ..Default::default()
+        });
         assert_eq!(
-            Trans2QuikResult::from(12),
-            Trans2QuikResult::MemoryAllocationError
+            orders.try_recv().unwrap().exec_condition,
+            ExecCondition::Unknown
         );
+    }
+
+    #[test]
+    fn test_events() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut events = Box::pin(terminal.events());
+
+        mock::transaction_reply(mock::Reply::default());
+        mock::order(mock::Order::default());
+        mock::connection_status(9);
+        mock::trade(mock::Trade::default());
+        mock::order(mock::Order {
+            order_num: 2,
+            ..Default::default()
+        });
+
+        runtime().block_on(async {
+            assert!(matches!(
+                events.next().await,
+                Some(Event::TransactionReply(_))
+            ));
+            assert!(matches!(
+                events.next().await,
+                Some(Event::Order(OrderInfo { order_num: 1, .. }))
+            ));
+            assert!(matches!(
+                events.next().await,
+                Some(Event::Connection(ConnectionEvent::QuikDisconnected(_)))
+            ));
+            assert!(matches!(events.next().await, Some(Event::Trade(_))));
+            assert!(matches!(
+                events.next().await,
+                Some(Event::Order(OrderInfo { order_num: 2, .. }))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_start_trades_twice() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let references = Arc::strong_count(&terminal.library);
+
+        terminal.start_trades().unwrap();
+        terminal.clone().start_trades().unwrap();
+
+        // Stored once, not cloned again by the following calls.
+        assert_eq!(Arc::strong_count(&terminal.library), references);
         assert_eq!(
-            Trans2QuikResult::from(13),
-            Trans2QuikResult::WrongConnectionHandle
+            TERMINAL_INSTANCE
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .instance_id,
+            terminal.instance_id
         );
+
+        mock::order(mock::Order {
+            qty: 10,
+            ..Default::default()
+        });
+        assert_eq!(RECENT_ORDERS.lock().unwrap()[0].qty, 10);
+    }
+
+    #[test]
+    fn test_send_with_encoding() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let transaction_str = replacement_order().build().unwrap();
+
+        mock::set_message_encoding(encoding_rs::KOI8_R);
+        mock::set_sync_reply(4, "Недостаточно средств");
+        let transaction_info = terminal
+            .send_sync_transaction_with_encoding(&transaction_str, Some(encoding_rs::KOI8_R))
+            .unwrap();
+        assert_eq!(transaction_info.reply_message, "Недостаточно средств");
+
+        let transaction_info = terminal
+            .send_sync_transaction_with_encoding(&transaction_str, None)
+            .unwrap();
+        assert_ne!(transaction_info.reply_message, "Недостаточно средств");
+
+        mock::set_send_results(&[1]);
+        mock::set_async_error_message("Нет связи");
+        let encoding = Encoding::for_label(b"koi8-r");
         assert_eq!(
-            Trans2QuikResult::from(14),
-            Trans2QuikResult::WrongInputParams
+            terminal
+                .send_async_transaction_with_encoding(&transaction_str, encoding)
+                .unwrap(),
+            Trans2QuikResult::Failed
         );
-        assert_eq!(Trans2QuikResult::from(999), Trans2QuikResult::Unknown);
+        assert_eq!(terminal.last_error().unwrap().1, "Нет связи");
     }
 
     #[test]
-    fn test_trans2quikerror_from_libloadingerror() {
-        // Attempt to load a non-existent library to produce a LibloadingError
-        let libloading_error = unsafe {
-            match Library::new("/invalid/path/to/nonexistent/library") {
-                Ok(_) => panic!("Expected an error, but library loaded successfully."),
-                Err(e) => e,
-            }
-        };
+    fn test_is_market_active() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let within = Duration::from_millis(100);
+
+        mock::trade(mock::Trade {
+            sec_code: "GAZP",
+            ..Default::default()
+        });
+        // The initial trade is not counted.
+        mock::trade(mock::Trade {
+            mode: 1,
+            sec_code: "LKOH",
+            ..Default::default()
+        });
+        std::thread::sleep(Duration::from_millis(150));
+        mock::trade(mock::Trade::default());
+
+        assert!(terminal.is_market_active("TQBR", "SBER", within));
+        assert!(!terminal.is_market_active("TQBR", "GAZP", within));
+        assert!(terminal.is_market_active("TQBR", "GAZP", Duration::from_secs(60)));
+        assert!(!terminal.is_market_active("TQBR", "LKOH", Duration::from_secs(60)));
+        assert!(!terminal.is_market_active("SPBFUT", "SBER", within));
+    }
 
-        // Convert it into a Trans2QuikError
-        let trans2quik_error: Trans2QuikError = Trans2QuikError::from(libloading_error);
+    #[test]
+    fn test_instrument_stats() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.subscribe_orders("TQBR", "SBER").unwrap();
+        terminal.subscribe_trades("TQBR", "SBER").unwrap();
+        terminal.subscribe_orders("TQBR", "SBRE").unwrap();
+        terminal.start_orders();
+        terminal.start_trades().unwrap();
+
+        mock::order(mock::Order::default());
+        mock::order(mock::Order {
+            sec_code: "GAZP",
+            ..Default::default()
+        });
+        mock::trade(mock::Trade::default());
+        mock::trade(mock::Trade::default());
+
+        let stats = terminal.instrument_stats("TQBR", "SBER");
+        assert_eq!(stats.orders, 1);
+        assert_eq!(stats.trades, 2);
+        assert!(stats.last_event.is_some());
+        assert_eq!(terminal.instrument_stats("TQBR", "GAZP").orders, 1);
+
+        // The instrument with the typo delivers nothing.
+        assert_eq!(
+            terminal.instrument_stats("TQBR", "SBRE"),
+            InstrumentStats::default()
+        );
+    }
 
-        // Assert that it matches the expected enumeration variant
-        if let Trans2QuikError::LibLoading(_) = trans2quik_error {
-            // Passed: we correctly converted the error
-        } else {
-            panic!("Expected Trans2QuikError::LibLoading variant.");
-        }
+    #[test]
+    fn test_ensure_connected() {
+        assert_eq!(
+            ConnectOutcome::from_result(&Trans2QuikResult::from(0)),
+            Some(ConnectOutcome::Connected)
+        );
+        assert_eq!(
+            ConnectOutcome::from_result(&Trans2QuikResult::from(4)),
+            Some(ConnectOutcome::AlreadyConnected)
+        );
+        assert_eq!(
+            ConnectOutcome::from_result(&Trans2QuikResult::from(2)),
+            None
+        );
+
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        mock::set_connect_results(&[0, 4, 2]);
+        assert_eq!(
+            terminal.ensure_connected().unwrap(),
+            ConnectOutcome::Connected
+        );
+        assert_eq!(
+            terminal.ensure_connected().unwrap(),
+            ConnectOutcome::AlreadyConnected
+        );
+        assert!(matches!(
+            terminal.ensure_connected(),
+            Err(Trans2QuikError::NotConnected(
+                Trans2QuikResult::TerminalNotFound
+            ))
+        ));
     }
 
     #[test]
-    fn test_trans2quikerror_from_nulerror() {
-        // Create a NulError by attempting to construct a CString with an embedded null byte
-        let nul_err = CString::new("Invalid\0String").unwrap_err();
+    fn test_missing_reply_callback() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let transaction_str =
+            "ACTION=KILL_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER; ORDER_KEY=12345";
 
-        // Convert it into a Trans2QuikError
-        let trans2quik_error: Trans2QuikError = Trans2QuikError::from(nul_err);
+        // Warned by default.
+        assert_eq!(
+            terminal.send_async_transaction(transaction_str).unwrap(),
+            Trans2QuikResult::Success
+        );
 
-        // Assert that it matches the expected enumeration variant
-        matches!(trans2quik_error, Trans2QuikError::NulError(_));
+        terminal.set_missing_reply_callback(MissingReplyCallback::Error);
+        assert!(matches!(
+            terminal.send_async_transaction(transaction_str),
+            Err(Trans2QuikError::ReplyCallbackNotSet)
+        ));
+        assert_eq!(mock::sent().len(), 1);
+
+        terminal.set_transactions_reply_callback().unwrap();
+        terminal.send_async_transaction(transaction_str).unwrap();
+        assert_eq!(mock::sent().len(), 2);
     }
 
     #[test]
-    fn test_display_for_trans2quikerror() {
-        // Test conversion and display message for NulError
-        let nul_err = CString::new("Invalid\0String").unwrap_err();
-        let trans2quik_error_nul: Trans2QuikError = Trans2QuikError::from(nul_err);
+    fn test_callback_state() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let clone = terminal.clone();
+        assert_eq!(terminal.callback_state(), CallbackState::default());
+
+        terminal.set_connection_status_callback().unwrap();
+        terminal.set_transactions_reply_callback().unwrap();
+        terminal.start_orders();
+        clone.start_trades().unwrap();
+        assert_eq!(
+            terminal.callback_state(),
+            CallbackState {
+                connection_status: true,
+                transaction_reply: true,
+                orders: true,
+                trades: true,
+            }
+        );
 
-        // Test display format for NulError
-        let expected_display_nul = format!("{:?}", trans2quik_error_nul);
-        assert_eq!(expected_display_nul, format!("{}", trans2quik_error_nul));
+        terminal.unsubscribe_orders().unwrap();
+        terminal.unsubscribe_trades().unwrap();
+        let callback_state = clone.callback_state();
+        assert!(!callback_state.orders);
+        assert!(!callback_state.trades);
+        assert!(callback_state.connection_status);
+    }
 
-        // For LibloadingError: simulate a common error scenario
-        // Open a library with an invalid path to trigger a DlOpen error
-        let libloading_error = unsafe {
-            match Library::new("/invalid/path/to/nonexistent/lib") {
-                Ok(_) => panic!("Expected an error, but library loaded successfully"),
-                Err(e) => e,
+    #[test]
+    fn test_set_connection_status_callback_twice() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+
+        assert_eq!(
+            terminal.set_connection_status_callback().unwrap(),
+            Trans2QuikResult::Success
+        );
+        assert_eq!(
+            terminal.clone().set_connection_status_callback().unwrap(),
+            Trans2QuikResult::Success
+        );
+        assert_eq!(mock::connection_callback_sets(), 1);
+
+        terminal.force_connection_status_callback().unwrap();
+        assert_eq!(mock::connection_callback_sets(), 2);
+    }
+
+    #[test]
+    fn test_wait_for_order() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+
+        // Received before waiting.
+        mock::order(mock::Order {
+            order_num: 1,
+            status: 1,
+            ..Default::default()
+        });
+
+        runtime().block_on(async {
+            let order_info = terminal
+                .wait_for_order(1, Duration::from_secs(5))
+                .await
+                .unwrap();
+            assert_eq!(order_info.status, Status::Active);
+
+            let rejected = terminal.wait_for_order(2, Duration::from_secs(5));
+            tokio::pin!(rejected);
+            tokio::select! {
+                biased;
+                result = &mut rejected => panic!("unexpected {:?}", result),
+                _ = tokio::task::yield_now() => {}
             }
+            mock::order(mock::Order {
+                order_num: 3,
+                ..Default::default()
+            });
+            mock::order(mock::Order {
+                order_num: 2,
+                status: 2,
+                ..Default::default()
+            });
+            assert!(matches!(
+                rejected.await,
+                Err(Trans2QuikError::OrderCanceled(2))
+            ));
+
+            assert!(matches!(
+                terminal.wait_for_order(4, Duration::from_millis(10)).await,
+                Err(Trans2QuikError::Timeout)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_trade_user_id() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let (trade_sender, mut trade_receiver) = tokio::sync::mpsc::unbounded_channel();
+        TRADE_STATUS_SENDER.store(Some(Arc::new(trade_sender.into())));
+
+        mock::trade(mock::Trade {
+            user_id: "Трейдер-2",
+            uid: 4321,
+            ..Default::default()
+        });
+
+        let trade_info = trade_receiver.try_recv().unwrap();
+        assert_eq!(trade_info.user_id, "Трейдер-2");
+        assert_eq!(trade_info.uid, 4321);
+    }
+
+    #[test]
+    fn test_trade_without_optional_getters() {
+        let _guard = mock::lock();
+        // The library without the getters of the trader, the firm and the currencies.
+        let terminal = Terminal {
+            trans2quik_trade_userid: None,
+            trans2quik_trade_firm_id: None,
+            trans2quik_trade_uid: None,
+            trans2quik_trade_currency: None,
+            trans2quik_trade_settle_currency: None,
+            ..mock::terminal()
         };
-        let trans2quik_error_lib: Trans2QuikError = Trans2QuikError::from(libloading_error);
+        terminal.start_trades().unwrap();
+        let mut trades = terminal.subscribe_trades_broadcast();
+
+        mock::trade(mock::Trade {
+            user_id: "Трейдер-2",
+            firm_id: "MC0002500000",
+            uid: 4321,
+            price_currency: "USD",
+            settle_currency: "SUR",
+            ..Default::default()
+        });
+
+        let trade_info = trades.try_recv().unwrap();
+        assert_eq!(trade_info.user_id, "");
+        assert_eq!(trade_info.firm_id, "");
+        assert_eq!(trade_info.uid, 0);
+        assert_eq!(trade_info.price_currency, "");
+        assert_eq!(trade_info.settle_currency, "");
+    }
 
-        // Test display format for LibLoading error
-        let expected_display_lib = format!("{:?}", trans2quik_error_lib);
-        assert_eq!(expected_display_lib, format!("{}", trans2quik_error_lib));
+    #[test]
+    fn test_trade_firm_id() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        terminal.start_trades().unwrap();
+        let mut trades = terminal.subscribe_trades_broadcast();
+
+        mock::trade(mock::Trade {
+            firm_id: "MC0002500000",
+            ..Default::default()
+        });
+        mock::trade(mock::Trade {
+            firm_id: "Фирма-1",
+            ..Default::default()
+        });
+
+        assert_eq!(trades.try_recv().unwrap().firm_id, "MC0002500000");
+        assert_eq!(trades.try_recv().unwrap().firm_id, "Фирма-1");
+    }
+
+    #[test]
+    fn test_format_uid() {
+        assert_eq!(format_uid(0), 0);
+        assert_eq!(format_uid(2147483647), 2147483647);
+        assert_eq!(format_uid(-1), 0);
+    }
+
+    #[test]
+    fn test_channels_from_dll_thread() {
+        let _guard = mock::lock();
+        mock::terminal().start_trades().unwrap();
+
+        // No tokio runtime in the test thread nor in the thread of the library.
+        let mut receivers = Terminal::channels();
+
+        std::thread::spawn(|| {
+            mock::transaction_reply(mock::Reply {
+                trans_id: 7,
+                ..Default::default()
+            });
+            mock::order(mock::Order {
+                order_num: 8,
+                ..Default::default()
+            });
+            mock::trade(mock::Trade {
+                trade_num: 9,
+                ..Default::default()
+            });
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            receivers
+                .transaction_reply
+                .try_recv()
+                .unwrap()
+                .trans_id
+                .as_i32(),
+            7
+        );
+        assert_eq!(receivers.order_status.try_recv().unwrap().order_num, 8);
+        assert_eq!(receivers.trade_status.blocking_recv().unwrap().trade_num, 9);
+    }
+
+    #[test]
+    fn test_callback_watchdog() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        assert_eq!(terminal.last_callback_age(), None);
+
+        let (stall_sender, stall_receiver) = std::sync::mpsc::channel();
+        terminal.set_callback_watchdog(Duration::from_millis(50), move |idle| {
+            stall_sender.send(idle).unwrap();
+        });
+
+        mock::connection_status(8);
+        assert!(terminal.last_callback_age().unwrap() < Duration::from_millis(50));
+
+        let idle = stall_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(idle > Duration::from_millis(50));
+        // Reported once per stall.
+        assert!(stall_receiver
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+
+        mock::connection_status(8);
+        assert!(stall_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        terminal.stop_callback_watchdog();
+        mock::connection_status(8);
+        assert!(stall_receiver
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn test_second_terminal_rejected() {
+        let _guard = mock::lock();
+        let terminal = mock::terminal();
+        let other = mock::terminal();
+
+        terminal.start_trades().unwrap();
+        // The clones share the callbacks.
+        terminal.clone().start_trades().unwrap();
+        assert!(matches!(
+            other.start_trades(),
+            Err(Trans2QuikError::TerminalInUse)
+        ));
+
+        terminal.disconnect().unwrap();
+        other.start_trades().unwrap();
+    }
+
+    #[test]
+    fn test_terminal_default_account() {
+        let terminal = mock::terminal().with_default_account("L01-00000F00", "OPEN12345");
+
+        let transaction_str = terminal
+            .transaction(Action::KillOrder)
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .order_key(12345)
+            .client_code("OPEN54321")
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.contains("ACCOUNT=L01-00000F00"));
+        assert!(transaction_str.contains("CLIENT_CODE=OPEN54321"));
+        assert!(!transaction_str.contains("CLIENT_CODE=OPEN12345"));
     }
 }
@@ -0,0 +1,847 @@
+//! Test double of the library Trans2QUIK.dll.
+//!
+//! The `Terminal` is built from stub functions instead of the symbols of the library,
+//! the callback functions are called directly as the library Trans2QUIK.dll would do,
+//! with the strings encoded in WINDOWS-1251.
+use super::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::MutexGuard;
+
+lazy_static! {
+    static ref MOCK_LOCK: Mutex<()> = Mutex::new(());
+    static ref STATE: Mutex<MockState> = Mutex::new(MockState::default());
+}
+
+#[derive(Default)]
+struct MockState {
+    /// The QUIK directories passed to TRANS2QUIK_CONNECT.
+    connections: Vec<String>,
+    /// Transactions passed to TRANS2QUIK_SEND_SYNC_TRANSACTION and TRANS2QUIK_SEND_ASYNC_TRANSACTION.
+    sent: Vec<String>,
+    /// Data available through the transaction reply descriptors.
+    replies: HashMap<intptr_t, ReplyDescriptor>,
+    /// Data available through the order descriptors.
+    orders: HashMap<intptr_t, OrderDescriptor>,
+    /// Data available through the trade descriptors.
+    trades: HashMap<intptr_t, TradeDescriptor>,
+    next_descriptor: intptr_t,
+    /// Reply of the library to the asynchronous transaction.
+    auto_reply: Option<fn(&str) -> Option<Reply>>,
+    /// Results of the next asynchronous transactions, then `Trans2QuikResult::Success`.
+    send_results: VecDeque<c_long>,
+    /// Results of the next subscriptions, then `Trans2QuikResult::Success`.
+    subscribe_results: VecDeque<c_long>,
+    /// Results of the next calls of TRANS2QUIK_IS_QUIK_CONNECTED,
+    /// then `Trans2QuikResult::QuikConnected`.
+    quik_connected_results: VecDeque<c_long>,
+    /// The number of the calls of TRANS2QUIK_IS_QUIK_CONNECTED.
+    quik_connected_checks: usize,
+    /// The number of the calls of TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK.
+    connection_callback_sets: usize,
+    /// Results of the next calls of TRANS2QUIK_CONNECT, then `Trans2QuikResult::Success`.
+    connect_results: VecDeque<c_long>,
+    /// The reply code and the message of the next synchronous transactions,
+    /// the executed transaction placing the order `SYNC_ORDER_NUM` by default.
+    sync_reply: Option<(c_long, &'static str)>,
+    /// The duration of TRANS2QUIK_CONNECT, e.g. of the handshake with the QUIK terminal.
+    connect_delay: Duration,
+    /// The duration of TRANS2QUIK_SEND_SYNC_TRANSACTION, e.g. of the round trip to the QUIK server.
+    sync_delay: Duration,
+    /// The encoding of the messages of the library, WINDOWS-1251 by default.
+    message_encoding: Option<&'static Encoding>,
+    /// The error message of the asynchronous transactions not sent.
+    async_error_message: Option<&'static str>,
+}
+
+struct ReplyDescriptor {
+    sec_code: CString,
+    price: c_double,
+    firm_id: CString,
+    client_code: CString,
+    account: CString,
+}
+
+struct OrderDescriptor {
+    date: c_long,
+    time: c_long,
+    qty: i64,
+    exec_type: c_long,
+}
+
+struct TradeDescriptor {
+    date: c_long,
+    time: c_long,
+    settle_code: CString,
+    settle_time: c_long,
+    is_marginal: c_long,
+    user_id: CString,
+    firm_id: CString,
+    uid: c_long,
+    price_currency: CString,
+    settle_currency: CString,
+}
+
+/// Transaction reply passed to the transaction_reply_callback.
+pub(crate) struct Reply {
+    pub result_code: c_long,
+    pub error_code: c_long,
+    pub reply_code: c_long,
+    pub trans_id: c_long,
+    pub order_num: c_ulonglong,
+    pub reply_message: &'static str,
+    pub sec_code: &'static str,
+    pub price: c_double,
+    pub firm_id: &'static str,
+    pub client_code: &'static str,
+    pub account: &'static str,
+}
+
+impl Default for Reply {
+    fn default() -> Self {
+        Reply {
+            result_code: 0,
+            error_code: 0,
+            reply_code: 3,
+            trans_id: 1,
+            order_num: 1,
+            reply_message: "",
+            sec_code: "SBER",
+            price: 0.0,
+            firm_id: "",
+            client_code: "",
+            account: "",
+        }
+    }
+}
+
+/// Order passed to the order_status_callback.
+pub(crate) struct Order {
+    pub mode: c_long,
+    pub trans_id: c_long,
+    pub order_num: c_ulonglong,
+    pub class_code: &'static str,
+    pub sec_code: &'static str,
+    pub price: c_double,
+    pub qty: i64,
+    pub balance: i64,
+    pub value: c_double,
+    pub is_sell: c_long,
+    pub status: c_long,
+    pub date: c_long,
+    pub time: c_long,
+    pub exec_type: c_long,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order {
+            mode: 0,
+            trans_id: 1,
+            order_num: 1,
+            class_code: "TQBR",
+            sec_code: "SBER",
+            price: 0.0,
+            qty: 0,
+            balance: 0,
+            value: 0.0,
+            is_sell: 0,
+            status: 1,
+            date: 20241115,
+            time: 103000,
+            exec_type: 2,
+        }
+    }
+}
+
+/// Trade passed to the trade_status_callback.
+pub(crate) struct Trade {
+    pub mode: c_long,
+    pub trade_num: c_ulonglong,
+    pub order_num: c_ulonglong,
+    pub class_code: &'static str,
+    pub sec_code: &'static str,
+    pub price: c_double,
+    pub quantity: i64,
+    pub is_sell: c_long,
+    pub value: c_double,
+    pub date: c_long,
+    pub time: c_long,
+    pub settle_code: &'static str,
+    pub settle_time: c_long,
+    pub is_marginal: c_long,
+    pub user_id: &'static str,
+    pub firm_id: &'static str,
+    pub uid: c_long,
+    pub price_currency: &'static str,
+    pub settle_currency: &'static str,
+}
+
+impl Default for Trade {
+    fn default() -> Self {
+        Trade {
+            mode: 0,
+            trade_num: 1,
+            order_num: 1,
+            class_code: "TQBR",
+            sec_code: "SBER",
+            price: 0.0,
+            quantity: 0,
+            is_sell: 0,
+            value: 0.0,
+            date: 20241115,
+            time: 103000,
+            settle_code: "T0",
+            settle_time: 0,
+            is_marginal: 0,
+            user_id: "",
+            firm_id: "",
+            uid: 0,
+            price_currency: "SUR",
+            settle_currency: "SUR",
+        }
+    }
+}
+
+/// Serializes the tests using the mock, the callbacks work with the process-global state.
+/// The state of the mock and the global state of the crate are cleared.
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    let guard = MOCK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    *state() = MockState::default();
+    reset_globals();
+
+    guard
+}
+
+fn state() -> MutexGuard<'static, MockState> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Transactions sent through the stubs in the order of sending.
+pub(crate) fn sent() -> Vec<String> {
+    state().sent.clone()
+}
+
+/// The QUIK directories connected to through the stubs in the order of connecting.
+pub(crate) fn connections() -> Vec<String> {
+    state().connections.clone()
+}
+
+/// Sets the results returned by TRANS2QUIK_SEND_ASYNC_TRANSACTION for the next transactions,
+/// the transactions with a result other than `Trans2QuikResult::Success` get no reply.
+pub(crate) fn set_send_results(results: &[c_long]) {
+    state().send_results = results.iter().copied().collect();
+}
+
+/// Sets the results returned by TRANS2QUIK_SUBSCRIBE_ORDERS and TRANS2QUIK_SUBSCRIBE_TRADES
+/// for the next subscriptions.
+pub(crate) fn set_subscribe_results(results: &[c_long]) {
+    state().subscribe_results = results.iter().copied().collect();
+}
+
+/// Sets the reply code and the message of the next synchronous transactions, which place
+/// no order unless the reply code is `3`.
+pub(crate) fn set_sync_reply(reply_code: c_long, message: &'static str) {
+    state().sync_reply = Some((reply_code, message));
+}
+
+/// Sets the duration of the next calls of TRANS2QUIK_CONNECT.
+pub(crate) fn set_connect_delay(delay: Duration) {
+    state().connect_delay = delay;
+}
+
+/// Sets the duration of the next synchronous transactions.
+pub(crate) fn set_sync_delay(delay: Duration) {
+    state().sync_delay = delay;
+}
+
+/// Sets the encoding of the messages written by the stubs, e.g. of the terminal in another locale.
+pub(crate) fn set_message_encoding(encoding: &'static Encoding) {
+    state().message_encoding = Some(encoding);
+}
+
+/// Sets the error message of the next asynchronous transactions not sent.
+pub(crate) fn set_async_error_message(message: &'static str) {
+    state().async_error_message = Some(message);
+}
+
+/// Sets the results returned by TRANS2QUIK_CONNECT for the next calls.
+pub(crate) fn set_connect_results(results: &[c_long]) {
+    state().connect_results = results.iter().copied().collect();
+}
+
+/// Sets the results returned by TRANS2QUIK_IS_QUIK_CONNECTED for the next calls.
+pub(crate) fn set_quik_connected_results(results: &[c_long]) {
+    state().quik_connected_results = results.iter().copied().collect();
+}
+
+/// The number of the calls of TRANS2QUIK_IS_QUIK_CONNECTED.
+pub(crate) fn quik_connected_checks() -> usize {
+    state().quik_connected_checks
+}
+
+/// The number of the calls of TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK.
+pub(crate) fn connection_callback_sets() -> usize {
+    state().connection_callback_sets
+}
+
+/// Sets the transaction reply to every asynchronous transaction sent,
+/// the reply is passed to the transaction_reply_callback before the sending function returns.
+pub(crate) fn set_auto_reply(auto_reply: fn(&str) -> Option<Reply>) {
+    state().auto_reply = Some(auto_reply);
+}
+
+fn next_descriptor(state: &mut MockState) -> intptr_t {
+    state.next_descriptor += 1;
+    state.next_descriptor
+}
+
+/// The functions of the library Trans2QUIK.dll replaced by the stubs.
+const SYMBOLS: &[&str] = &[
+    "TRANS2QUIK_CONNECT",
+    "TRANS2QUIK_DISCONNECT",
+    "TRANS2QUIK_IS_QUIK_CONNECTED",
+    "TRANS2QUIK_IS_DLL_CONNECTED",
+    "TRANS2QUIK_SEND_SYNC_TRANSACTION",
+    "TRANS2QUIK_SEND_ASYNC_TRANSACTION",
+    "TRANS2QUIK_SET_CONNECTION_STATUS_CALLBACK",
+    "TRANS2QUIK_SET_TRANSACTIONS_REPLY_CALLBACK",
+    "TRANS2QUIK_SUBSCRIBE_ORDERS",
+    "TRANS2QUIK_SUBSCRIBE_TRADES",
+    "TRANS2QUIK_START_ORDERS",
+    "TRANS2QUIK_START_TRADES",
+    "TRANS2QUIK_UNSUBSCRIBE_ORDERS",
+    "TRANS2QUIK_UNSUBSCRIBE_TRADES",
+    "TRANS2QUIK_TRANSACTION_REPLY_SEC_CODE",
+    "TRANS2QUIK_TRANSACTION_REPLY_PRICE",
+    "TRANS2QUIK_ORDER_DATE",
+    "TRANS2QUIK_ORDER_TIME",
+    "TRANS2QUIK_ORDER_QTY",
+    "TRANS2QUIK_TRADE_DATE",
+    "TRANS2QUIK_TRADE_TIME",
+    "TRANS2QUIK_TRADE_SETTLE_CODE",
+    "TRANS2QUIK_TRADE_USERID",
+    "TRANS2QUIK_TRADE_FIRMID",
+    "TRANS2QUIK_TRADE_UID",
+    "TRANS2QUIK_TRADE_CURRENCY",
+    "TRANS2QUIK_TRADE_SETTLE_CURRENCY",
+];
+
+/// The `Terminal` working with the stub functions.
+pub(crate) fn terminal() -> Terminal {
+    Terminal {
+        path_to_quik: String::from(r"C:\QUIK"),
+        instance_id: next_instance_id(),
+        default_account: None,
+        library_dir: None,
+        loaded_symbols: SYMBOLS.to_vec(),
+        rate_limiter: Arc::new(Mutex::new(RateLimiter::default())),
+        idempotency_keys: Arc::new(Mutex::new(IdempotencyKeys::default())),
+        check_decimal_separator: Arc::new(AtomicBool::new(true)),
+        missing_reply_callback: Arc::new(Mutex::new(MissingReplyCallback::default())),
+        callback_state: Arc::new(Mutex::new(CallbackState::default())),
+        instrument_info: Arc::new(Mutex::new(None)),
+        subscriptions: Arc::new(Mutex::new(Subscriptions::default())),
+        last_error: Arc::new(Mutex::new(None)),
+        reconnect: Arc::new(Mutex::new(None)),
+        library: Arc::new(this_library()),
+        trans2quik_connect: connect,
+        trans2quik_disconnect: disconnect,
+        trans2quik_is_quik_connected: is_quik_connected,
+        trans2quik_is_dll_connected: is_dll_connected,
+        trans2quik_send_sync_transaction: send_sync_transaction,
+        trans2quik_send_async_transaction: send_async_transaction,
+        trans2quik_set_connection_status_callback: set_connection_status_callback,
+        trans2quik_set_transactions_reply_callback: set_transactions_reply_callback,
+        trans2quik_subscribe_orders: subscribe_orders,
+        trans2quik_subscribe_trades: subscribe_trades,
+        trans2quik_start_orders: start_orders,
+        trans2quik_start_trades: start_trades,
+        trans2quik_unsubscribe_orders: unsubscribe_orders,
+        trans2quik_unsubscribe_trades: unsubscribe_trades,
+        trans2quik_transaction_reply_sec_code: transaction_reply_sec_code,
+        trans2quik_transaction_reply_price: transaction_reply_price,
+        trans2quik_transaction_reply_firm_id: transaction_reply_firm_id,
+        trans2quik_transaction_reply_client_code: transaction_reply_client_code,
+        trans2quik_transaction_reply_account: transaction_reply_account,
+        trans2quik_order_date: order_date,
+        trans2quik_order_time: order_time,
+        trans2quik_order_qty: order_qty,
+        trans2quik_order_exec_type: Some(order_exec_type),
+        trans2quik_trade_date: trade_date,
+        trans2quik_trade_time: trade_time,
+        trans2quik_trade_settle_code: trade_settle_code,
+        trans2quik_trade_settle_time: Some(trade_settle_time),
+        trans2quik_trade_is_marginal: Some(trade_is_marginal),
+        trans2quik_trade_userid: Some(trade_userid),
+        trans2quik_trade_firm_id: Some(trade_firm_id),
+        trans2quik_trade_uid: Some(trade_uid),
+        trans2quik_trade_currency: Some(trade_currency),
+        trans2quik_trade_settle_currency: Some(trade_settle_currency),
+    }
+}
+
+/// The handle of the current process, it doesn't export any Trans2QUIK.dll functions.
+#[cfg(unix)]
+fn this_library() -> Library {
+    libloading::os::unix::Library::this().into()
+}
+
+/// The handle of the current process, it doesn't export any Trans2QUIK.dll functions.
+#[cfg(windows)]
+fn this_library() -> Library {
+    libloading::os::windows::Library::this().unwrap().into()
+}
+
+/// Calls the connection_status_callback as the library Trans2QUIK.dll does.
+pub(crate) fn connection_status(connection_event: c_long) {
+    connection_error(connection_event, 0, "");
+}
+
+/// Calls the connection_status_callback with the error as the library Trans2QUIK.dll does.
+pub(crate) fn connection_error(connection_event: c_long, error_code: c_long, error_message: &str) {
+    let error_message = encode_lpstr(error_message).unwrap();
+
+    unsafe {
+        connection_status_callback(
+            connection_event,
+            error_code,
+            error_message.as_ptr() as *mut c_char,
+        )
+    };
+}
+
+/// Calls the transaction_reply_callback as the library Trans2QUIK.dll does.
+pub(crate) fn transaction_reply(reply: Reply) {
+    let descriptor = {
+        let mut state = state();
+        let descriptor = next_descriptor(&mut state);
+        state.replies.insert(
+            descriptor,
+            ReplyDescriptor {
+                sec_code: encode_lpstr(reply.sec_code).unwrap(),
+                price: reply.price,
+                firm_id: encode_lpstr(reply.firm_id).unwrap(),
+                client_code: encode_lpstr(reply.client_code).unwrap(),
+                account: encode_lpstr(reply.account).unwrap(),
+            },
+        );
+        descriptor
+    };
+    let reply_message = encode_lpstr(reply.reply_message).unwrap();
+
+    unsafe {
+        transaction_reply_callback(
+            reply.result_code,
+            reply.error_code,
+            reply.reply_code,
+            reply.trans_id,
+            reply.order_num,
+            reply_message.as_ptr() as *mut c_char,
+            descriptor,
+        )
+    };
+
+    state().replies.remove(&descriptor);
+}
+
+/// Calls the order_status_callback as the library Trans2QUIK.dll does.
+pub(crate) fn order(order: Order) {
+    let descriptor = order_descriptor(&order);
+    let class_code = encode_lpstr(order.class_code).unwrap();
+    let sec_code = encode_lpstr(order.sec_code).unwrap();
+
+    unsafe {
+        order_status_callback(
+            order.mode,
+            order.trans_id,
+            order.order_num,
+            class_code.as_ptr() as *mut c_char,
+            sec_code.as_ptr() as *mut c_char,
+            order.price,
+            order.balance,
+            order.value,
+            order.is_sell,
+            order.status,
+            descriptor,
+        )
+    };
+
+    state().orders.remove(&descriptor);
+}
+
+/// The order descriptor with the data of the order available through the stubs
+/// until the end of the test.
+pub(crate) fn order_descriptor(order: &Order) -> intptr_t {
+    let mut state = state();
+    let descriptor = next_descriptor(&mut state);
+    state.orders.insert(
+        descriptor,
+        OrderDescriptor {
+            date: order.date,
+            time: order.time,
+            qty: order.qty,
+            exec_type: order.exec_type,
+        },
+    );
+    descriptor
+}
+
+/// Calls the trade_status_callback as the library Trans2QUIK.dll does.
+pub(crate) fn trade(trade: Trade) {
+    let descriptor = {
+        let mut state = state();
+        let descriptor = next_descriptor(&mut state);
+        state.trades.insert(
+            descriptor,
+            TradeDescriptor {
+                date: trade.date,
+                time: trade.time,
+                settle_code: encode_lpstr(trade.settle_code).unwrap(),
+                settle_time: trade.settle_time,
+                is_marginal: trade.is_marginal,
+                user_id: encode_lpstr(trade.user_id).unwrap(),
+                firm_id: encode_lpstr(trade.firm_id).unwrap(),
+                uid: trade.uid,
+                price_currency: encode_lpstr(trade.price_currency).unwrap(),
+                settle_currency: encode_lpstr(trade.settle_currency).unwrap(),
+            },
+        );
+        descriptor
+    };
+    let class_code = encode_lpstr(trade.class_code).unwrap();
+    let sec_code = encode_lpstr(trade.sec_code).unwrap();
+
+    unsafe {
+        trade_status_callback(
+            trade.mode,
+            trade.trade_num,
+            trade.order_num,
+            class_code.as_ptr() as *mut c_char,
+            sec_code.as_ptr() as *mut c_char,
+            trade.price,
+            trade.quantity,
+            trade.is_sell,
+            trade.value,
+            descriptor,
+        )
+    };
+
+    state().trades.remove(&descriptor);
+}
+
+unsafe extern "C" fn connect(
+    connection_str: *mut c_char,
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    let connection_str = CStr::from_ptr(connection_str)
+        .to_string_lossy()
+        .into_owned();
+    let delay = state().connect_delay;
+    std::thread::sleep(delay);
+    let mut state = state();
+    state.connections.push(connection_str);
+    state.connect_results.pop_front().unwrap_or(0)
+}
+
+unsafe extern "C" fn disconnect(
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    0
+}
+
+unsafe extern "C" fn is_quik_connected(
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    let mut state = state();
+    state.quik_connected_checks += 1;
+    state.quik_connected_results.pop_front().unwrap_or(8)
+}
+
+unsafe extern "C" fn is_dll_connected(
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    0
+}
+
+/// The order number of the orders placed by the synchronous transactions.
+pub(crate) const SYNC_ORDER_NUM: u64 = 12345;
+
+/// Writes the message to the buffer of the library as the library does.
+unsafe fn write_message(buffer: *mut c_char, buffer_len: c_long, message: &str) {
+    let encoding = state().message_encoding.unwrap_or(WINDOWS_1251);
+    let (message, _, _) = encoding.encode(message);
+    let message = CString::new(message.into_owned()).unwrap();
+    let message = message.as_bytes_with_nul();
+
+    if message.len() <= buffer_len as usize {
+        std::ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, buffer, message.len());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn send_sync_transaction(
+    trans_str: *mut c_char,
+    reply_code: *mut c_long,
+    trans_id: *mut c_long,
+    order_num: *mut c_double,
+    result_message: *mut c_char,
+    result_message_len: c_long,
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    let transaction = decode_lpstr(trans_str).unwrap();
+
+    let (code, message, delay) = {
+        let state = state();
+        let (code, message) = state.sync_reply.unwrap_or((3, "Order accepted"));
+        (code, message, state.sync_delay)
+    };
+    std::thread::sleep(delay);
+
+    *reply_code = code;
+    *trans_id = transaction_param(&transaction, "TRANS_ID")
+        .and_then(|trans_id| trans_id.parse().ok())
+        .unwrap_or(0);
+    *order_num = match code {
+        3 => SYNC_ORDER_NUM as c_double,
+        _ => 0.0,
+    };
+    write_message(result_message, result_message_len, message);
+
+    state().sent.push(transaction);
+    0
+}
+
+unsafe extern "C" fn send_async_transaction(
+    trans_str: *mut c_char,
+    error_code: *mut c_long,
+    error_message: *mut c_char,
+    error_message_len: c_long,
+) -> c_long {
+    let transaction = decode_lpstr(trans_str).unwrap();
+    let (auto_reply, result) = {
+        let mut state = state();
+        state.sent.push(transaction.clone());
+        (
+            state.auto_reply,
+            state.send_results.pop_front().unwrap_or(0),
+        )
+    };
+
+    if result != 0 {
+        *error_code = result;
+        let message = state()
+            .async_error_message
+            .unwrap_or("Transaction not sent");
+        write_message(error_message, error_message_len, message);
+        return result;
+    }
+
+    if let Some(reply) = auto_reply.and_then(|auto_reply| auto_reply(&transaction)) {
+        transaction_reply(reply);
+    }
+
+    0
+}
+
+unsafe extern "C" fn set_connection_status_callback(
+    _callback: Trans2QuikConnectionStatusCallback,
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    state().connection_callback_sets += 1;
+    0
+}
+
+unsafe extern "C" fn set_transactions_reply_callback(
+    _callback: Trans2QuikTransactionReplyCallback,
+    _error_code: *mut c_long,
+    _error_message: *mut c_char,
+    _error_message_len: c_long,
+) -> c_long {
+    0
+}
+
+unsafe extern "C" fn subscribe_orders(_class_code: *mut c_char, _sec_code: *mut c_char) -> c_long {
+    state().subscribe_results.pop_front().unwrap_or(0)
+}
+
+unsafe extern "C" fn subscribe_trades(_class_code: *mut c_char, _sec_code: *mut c_char) -> c_long {
+    state().subscribe_results.pop_front().unwrap_or(0)
+}
+
+unsafe extern "C" fn start_orders(_callback: Trans2QuikOrderStatusCallback) {}
+
+unsafe extern "C" fn start_trades(_callback: Trans2QuikTradeStatusCallback) {}
+
+unsafe extern "C" fn unsubscribe_orders() -> c_long {
+    0
+}
+
+unsafe extern "C" fn unsubscribe_trades() -> c_long {
+    0
+}
+
+unsafe extern "C" fn transaction_reply_sec_code(trans_reply_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .replies
+        .get(&trans_reply_descriptor)
+        .map_or(std::ptr::null_mut(), |reply| {
+            reply.sec_code.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn transaction_reply_price(trans_reply_descriptor: intptr_t) -> c_double {
+    state()
+        .replies
+        .get(&trans_reply_descriptor)
+        .map_or(0.0, |reply| reply.price)
+}
+
+unsafe extern "C" fn transaction_reply_firm_id(trans_reply_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .replies
+        .get(&trans_reply_descriptor)
+        .map_or(std::ptr::null_mut(), |reply| {
+            reply.firm_id.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn transaction_reply_client_code(
+    trans_reply_descriptor: intptr_t,
+) -> *mut c_char {
+    state()
+        .replies
+        .get(&trans_reply_descriptor)
+        .map_or(std::ptr::null_mut(), |reply| {
+            reply.client_code.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn transaction_reply_account(trans_reply_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .replies
+        .get(&trans_reply_descriptor)
+        .map_or(std::ptr::null_mut(), |reply| {
+            reply.account.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn order_date(order_descriptor: intptr_t) -> c_long {
+    state()
+        .orders
+        .get(&order_descriptor)
+        .map_or(0, |order| order.date)
+}
+
+unsafe extern "C" fn order_time(order_descriptor: intptr_t) -> c_long {
+    state()
+        .orders
+        .get(&order_descriptor)
+        .map_or(0, |order| order.time)
+}
+
+unsafe extern "C" fn order_qty(order_descriptor: intptr_t) -> i64 {
+    state()
+        .orders
+        .get(&order_descriptor)
+        .map_or(0, |order| order.qty)
+}
+
+unsafe extern "C" fn order_exec_type(order_descriptor: intptr_t) -> c_long {
+    state()
+        .orders
+        .get(&order_descriptor)
+        .map_or(0, |order| order.exec_type)
+}
+
+unsafe extern "C" fn trade_date(trade_descriptor: intptr_t) -> c_long {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(0, |trade| trade.date)
+}
+
+unsafe extern "C" fn trade_time(trade_descriptor: intptr_t) -> c_long {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(0, |trade| trade.time)
+}
+
+unsafe extern "C" fn trade_settle_code(trade_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(std::ptr::null_mut(), |trade| {
+            trade.settle_code.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn trade_settle_time(trade_descriptor: intptr_t) -> c_long {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(0, |trade| trade.settle_time)
+}
+
+unsafe extern "C" fn trade_is_marginal(trade_descriptor: intptr_t) -> c_long {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(0, |trade| trade.is_marginal)
+}
+
+unsafe extern "C" fn trade_userid(trade_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(std::ptr::null_mut(), |trade| {
+            trade.user_id.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn trade_firm_id(trade_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(std::ptr::null_mut(), |trade| {
+            trade.firm_id.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn trade_uid(trade_descriptor: intptr_t) -> c_long {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(0, |trade| trade.uid)
+}
+
+unsafe extern "C" fn trade_currency(trade_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(std::ptr::null_mut(), |trade| {
+            trade.price_currency.as_ptr() as *mut c_char
+        })
+}
+
+unsafe extern "C" fn trade_settle_currency(trade_descriptor: intptr_t) -> *mut c_char {
+    state()
+        .trades
+        .get(&trade_descriptor)
+        .map_or(std::ptr::null_mut(), |trade| {
+            trade.settle_currency.as_ptr() as *mut c_char
+        })
+}
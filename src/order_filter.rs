@@ -0,0 +1,100 @@
+//! Filtering of the repeated states of the orders received from the order_status_callback.
+use crate::{Mode, OrderInfo, Status};
+use std::collections::HashMap;
+
+/**
+Drops the order states already received, e.g. the state of the order delivered
+as `Mode::InitialOrder` after the subscription and again as `Mode::NewOrder`.
+
+# Example of use
+```ignore
+let mut filter = OrderStateFilter::live_only();
+while let Some(order_info) = order_receiver.recv().await {
+    if filter.accept(&order_info) {
+        // New state of the order.
+    }
+}
+```
+*/
+#[derive(Debug, Clone, Default)]
+pub struct OrderStateFilter {
+    last_states: HashMap<u64, (Status, i64)>,
+    live_only: bool,
+}
+
+impl OrderStateFilter {
+    /// Accepts the orders of all the modes.
+    pub fn new() -> Self {
+        OrderStateFilter::default()
+    }
+
+    /// Drops the orders replayed after the subscription, `Mode::InitialOrder`
+    /// and `Mode::LastOrderReceived`, their states are still remembered.
+    pub fn live_only() -> Self {
+        OrderStateFilter {
+            live_only: true,
+            ..Default::default()
+        }
+    }
+
+    /// `true` if the state of the order differs from the last state received for the order.
+    pub fn accept(&mut self, order_info: &OrderInfo) -> bool {
+        let (order_num, status, balance) = order_info.state_key();
+        let state = (status, balance);
+
+        let is_new_state = self.last_states.get(&order_num) != Some(&state);
+        if is_new_state {
+            self.last_states.insert(order_num, state);
+        }
+
+        let is_replay = matches!(
+            order_info.mode,
+            Mode::InitialOrder | Mode::LastOrderReceived
+        );
+
+        is_new_state && !(self.live_only && is_replay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransId;
+
+    fn order(mode: Mode, order_num: u64, status: Status, balance: i64) -> OrderInfo {
+        OrderInfo {
+            mode,
+            trans_id: TransId::Id(1),
+            order_num,
+            class_code: String::from("TQBR"),
+            sec_code: String::from("SBER"),
+            price: 250.0,
+            qty: 10,
+            balance,
+            value: 2500.0,
+            status,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_duplicate_state() {
+        let mut filter = OrderStateFilter::new();
+
+        assert!(filter.accept(&order(Mode::InitialOrder, 1, Status::Active, 10)));
+        assert!(!filter.accept(&order(Mode::NewOrder, 1, Status::Active, 10)));
+        assert!(filter.accept(&order(Mode::NewOrder, 1, Status::Active, 4)));
+        assert!(filter.accept(&order(Mode::NewOrder, 2, Status::Active, 4)));
+        assert!(filter.accept(&order(Mode::NewOrder, 1, Status::Executed, 0)));
+        assert!(!filter.accept(&order(Mode::NewOrder, 1, Status::Executed, 0)));
+    }
+
+    #[test]
+    fn test_live_only() {
+        let mut filter = OrderStateFilter::live_only();
+
+        assert!(!filter.accept(&order(Mode::InitialOrder, 1, Status::Active, 10)));
+        assert!(!filter.accept(&order(Mode::NewOrder, 1, Status::Active, 10)));
+        assert!(filter.accept(&order(Mode::NewOrder, 1, Status::Canceled, 10)));
+    }
+}
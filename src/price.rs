@@ -0,0 +1,77 @@
+//! Rounding of the prices to the price step of the instrument.
+//!
+//! The QUIK server rejects the orders with the prices not multiple of the price step.
+
+/// The price step of the instrument, e.g. `0.01` for SBER or `1.0` for Si.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceStep {
+    step: f64,
+    decimals: i32,
+}
+
+impl PriceStep {
+    /// The step must be positive, otherwise the prices are not rounded.
+    pub fn new(step: f64) -> Self {
+        PriceStep {
+            step,
+            decimals: decimals(step),
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Rounds the price to the nearest multiple of the step.
+    pub fn round(&self, price: f64) -> f64 {
+        if self.step <= 0.0 || !self.step.is_finite() || !price.is_finite() {
+            return price;
+        }
+
+        let rounded = (price / self.step).round() * self.step;
+
+        // Removes the error of the multiplication, e.g. 7103.499999999999 for the step 0.01.
+        let scale = 10f64.powi(self.decimals);
+        (rounded * scale).round() / scale
+    }
+}
+
+/// Rounds the price to the nearest multiple of the step, see `PriceStep::round`.
+pub fn round_to_step(price: f64, step: f64) -> f64 {
+    PriceStep::new(step).round(price)
+}
+
+/// The number of the decimal places of the step.
+fn decimals(step: f64) -> i32 {
+    let step = step.to_string();
+
+    step.split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_to_step() {
+        assert_eq!(round_to_step(7103.495, 0.01), 7103.5);
+        assert_eq!(round_to_step(7103.4949, 0.01), 7103.49);
+        assert_eq!(round_to_step(250.26, 0.5), 250.5);
+        assert_eq!(round_to_step(250.24, 0.5), 250.0);
+        assert_eq!(round_to_step(1.23456, 0.0001), 1.2346);
+        assert_eq!(round_to_step(0.70000001, 0.0001), 0.7);
+        assert_eq!(round_to_step(95123.0, 10.0), 95120.0);
+        assert_eq!(round_to_step(95123.0, 0.0), 95123.0);
+    }
+
+    #[test]
+    fn test_no_float_noise() {
+        let step = PriceStep::new(0.01);
+
+        for cents in 700_000..710_000 {
+            let price = step.round(cents as f64 / 100.0 + 0.001);
+            assert_eq!(price.to_string(), format!("{}", cents as f64 / 100.0));
+        }
+    }
+}
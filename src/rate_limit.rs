@@ -0,0 +1,93 @@
+//! Limiting of the transactions sent per second.
+//!
+//! The QUIK server rejects the transactions exceeding the limit of the broker
+//! and can throttle the terminal, so the limit is better kept on the client side.
+use std::time::{Duration, Instant};
+
+/// What to do with the transaction exceeding the rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RateLimitMode {
+    /// Wait until the transaction can be sent.
+    #[default]
+    Block,
+    /// Don't send the transaction, return `Trans2QuikError::WouldBlock`.
+    WouldBlock,
+}
+
+/// Token bucket: up to `per_second` transactions at once, refilled at `per_second` tokens per second.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    per_second: Option<u32>,
+    tokens: f64,
+    last_refill: Option<Instant>,
+    pub(crate) mode: RateLimitMode,
+}
+
+impl RateLimiter {
+    /// Sets the limit of the transactions per second, `0` removes the limit.
+    pub(crate) fn set_rate(&mut self, per_second: u32) {
+        self.per_second = (per_second > 0).then_some(per_second);
+        self.tokens = per_second as f64;
+        self.last_refill = None;
+    }
+
+    /// Takes the token at `now`, otherwise returns how long to wait for the next token.
+    pub(crate) fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        let per_second = match self.per_second {
+            Some(per_second) => per_second as f64,
+            None => return Ok(()),
+        };
+
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * per_second).min(per_second);
+        }
+        self.last_refill = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire(now).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_burst_is_paced() {
+        let mut limiter = RateLimiter::default();
+        limiter.set_rate(10);
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(start).is_ok());
+        }
+
+        let wait = limiter.try_acquire(start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(100));
+
+        assert!(limiter.try_acquire(start + wait).is_ok());
+        assert!(limiter.try_acquire(start + wait).is_err());
+
+        // The bucket is refilled up to the limit only.
+        let later = start + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(later).is_ok());
+        }
+        assert!(limiter.try_acquire(later).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Classification of the reasons of the rejected transactions by the reply messages of QUIK.
+
+/// The reason of the rejection of the transaction recognized in the reply message.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum RejectionReason {
+    /// Not enough funds or collateral for the order.
+    InsufficientFunds,
+    /// The price is outside the price limits of the instrument.
+    PriceOutOfLimits,
+    /// The trading session is closed or the instrument is not traded now.
+    MarketClosed,
+    /// The quantity is not valid, e.g. not a multiple of the lot.
+    InvalidQuantity,
+    /// The order to cancel or replace is not found.
+    OrderNotFound,
+    /// Not recognized, the reply message as is.
+    Other(String),
+}
+
+impl RejectionReason {
+    /// The rejection can be fixed by changing the transaction, e.g. the price or the quantity.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            RejectionReason::PriceOutOfLimits | RejectionReason::InvalidQuantity
+        )
+    }
+}
+
+/// Fragments of the reply messages of QUIK, checked in order against the lowercase message.
+const PATTERNS: &[(&str, RejectionReason)] = &[
+    ("недостаточно средств", RejectionReason::InsufficientFunds),
+    ("не хватает средств", RejectionReason::InsufficientFunds),
+    (
+        "недостаточно обеспечения",
+        RejectionReason::InsufficientFunds,
+    ),
+    (
+        "недостаточно денежных средств",
+        RejectionReason::InsufficientFunds,
+    ),
+    ("вне лимит", RejectionReason::PriceOutOfLimits),
+    ("за пределами", RejectionReason::PriceOutOfLimits),
+    ("вне диапазона", RejectionReason::PriceOutOfLimits),
+    ("сессия закрыта", RejectionReason::MarketClosed),
+    ("сессия не открыта", RejectionReason::MarketClosed),
+    ("торги не проводятся", RejectionReason::MarketClosed),
+    ("неторговый период", RejectionReason::MarketClosed),
+    ("не торгуется", RejectionReason::MarketClosed),
+    ("количество в заявке", RejectionReason::InvalidQuantity),
+    ("целым числом лотов", RejectionReason::InvalidQuantity),
+    ("не кратн", RejectionReason::InvalidQuantity),
+    ("не найдена заявка", RejectionReason::OrderNotFound),
+    ("заявка не найдена", RejectionReason::OrderNotFound),
+];
+
+/// Best-effort classification of the reply message of the rejected transaction.
+pub fn classify_rejection(msg: &str) -> RejectionReason {
+    let lowercase = msg.to_lowercase();
+
+    PATTERNS
+        .iter()
+        .find(|(pattern, _)| lowercase.contains(pattern))
+        .map_or_else(
+            || RejectionReason::Other(msg.to_string()),
+            |(_, reason)| reason.clone(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_rejection() {
+        assert_eq!(
+            classify_rejection("Недостаточно средств для совершения операции. Недостаток: 1234,56"),
+            RejectionReason::InsufficientFunds
+        );
+        assert_eq!(
+            classify_rejection("(161) Цена заявки вне лимитов по инструменту"),
+            RejectionReason::PriceOutOfLimits
+        );
+        assert_eq!(
+            classify_rejection("(37) Торговая сессия закрыта"),
+            RejectionReason::MarketClosed
+        );
+        assert_eq!(
+            classify_rejection("Количество в заявке должно быть целым числом лотов"),
+            RejectionReason::InvalidQuantity
+        );
+        assert_eq!(
+            classify_rejection("Превышено максимальное количество транзакций"),
+            RejectionReason::Other(String::from("Превышено максимальное количество транзакций"))
+        );
+        assert_eq!(
+            classify_rejection("Не найдена заявка для удаления"),
+            RejectionReason::OrderNotFound
+        );
+        assert_eq!(
+            classify_rejection("Неизвестная ошибка"),
+            RejectionReason::Other(String::from("Неизвестная ошибка"))
+        );
+    }
+
+    #[test]
+    fn test_is_fixable() {
+        assert!(RejectionReason::PriceOutOfLimits.is_fixable());
+        assert!(!RejectionReason::MarketClosed.is_fixable());
+    }
+}
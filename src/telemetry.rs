@@ -0,0 +1,79 @@
+//! The metrics of the transactions and the callback functions, emitted with the `metrics` crate
+//! if the feature `metrics` is enabled. Without the feature the functions do nothing.
+//!
+//! The metrics:
+//! - `trans2quik_transactions_sent_total`, the counter labelled with `mode` (`sync` or `async`)
+//!   and `result`, the `Trans2QuikResult` of the sending;
+//! - `trans2quik_send_sync_duration_seconds`, the histogram of the duration of
+//!   the synchronous transactions;
+//! - `trans2quik_transaction_replies_total`, the counter of the replies of the synchronous
+//!   and asynchronous transactions labelled with `reply_code`;
+//! - `trans2quik_transaction_rejections_total`, the counter of the rejected transactions;
+//! - `trans2quik_callback_events_total`, the counter labelled with `channel`
+//!   (`connection`, `transaction_reply`, `orders` or `trades`);
+//! - `trans2quik_disconnects_total`, the counter labelled with `source` (`quik` or `dll`);
+//! - `trans2quik_reconnects_total`, the counter of the connections restored
+//!   by `Terminal::enable_auto_reconnect`.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+use crate::{ConnectionEvent, ReplyCode, Trans2QuikResult};
+use std::time::Duration;
+
+#[inline]
+pub(crate) fn transaction_sent(mode: &'static str, result: &Trans2QuikResult) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(
+        "trans2quik_transactions_sent_total",
+        "mode" => mode,
+        "result" => format!("{:?}", result)
+    )
+    .increment(1);
+}
+
+#[inline]
+pub(crate) fn send_sync_duration(duration: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!("trans2quik_send_sync_duration_seconds").record(duration.as_secs_f64());
+}
+
+/// The reply of the synchronous transaction or the transaction_reply_callback.
+#[inline]
+pub(crate) fn transaction_reply(reply_code: &ReplyCode) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!(
+            "trans2quik_transaction_replies_total",
+            "reply_code" => format!("{:?}", reply_code)
+        )
+        .increment(1);
+
+        if reply_code.is_rejected() {
+            ::metrics::counter!("trans2quik_transaction_rejections_total").increment(1);
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn callback_event(channel: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("trans2quik_callback_events_total", "channel" => channel).increment(1);
+}
+
+#[inline]
+pub(crate) fn connection_event(event: &ConnectionEvent) {
+    #[cfg(feature = "metrics")]
+    {
+        let source = match event {
+            ConnectionEvent::QuikDisconnected(_) => "quik",
+            ConnectionEvent::DllDisconnected(_) => "dll",
+            ConnectionEvent::QuikConnected | ConnectionEvent::DllConnected => return,
+        };
+
+        ::metrics::counter!("trans2quik_disconnects_total", "source" => source).increment(1);
+    }
+}
+
+#[inline]
+pub(crate) fn reconnect() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("trans2quik_reconnects_total").increment(1);
+}
@@ -0,0 +1,71 @@
+//! Classification of the trades into the spot, the repo and the margin trades.
+
+/// The kind of the trade, see `TradeKind::classify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub enum TradeKind {
+    /// The trade without the borrowed funds or securities.
+    Spot,
+    /// The trade of the repo, the first or the second leg.
+    Repo,
+    /// The trade made with the funds or the securities borrowed from the broker.
+    Margin,
+    /// The library Trans2QUIK.dll doesn't report whether the trade is marginal.
+    #[default]
+    Unknown,
+}
+
+/// Prefixes of the class codes of the repo modes of the Moscow Exchange, e.g. `EQRP_INFO`,
+/// `PSRP` for the negotiated repo and `GCRP` for the repo with the central counterparty
+/// against the general collateral.
+const REPO_CLASS_PREFIXES: &[&str] = &["EQRP", "PSRP", "GCRP", "RPEU", "RPMA"];
+
+impl TradeKind {
+    /**
+    Classifies the trade of the class `class_code`.
+
+    The library Trans2QUIK.dll reports no repo flag, so the repo is recognized by the class code:
+    the classes starting with `EQRP`, `PSRP`, `GCRP`, `RPEU` or `RPMA`, or containing `REPO`.
+    The other trades are `Margin` or `Spot` by `is_marginal`, the value of
+    TRANS2QUIK_TRADE_IS_MARGINAL, and `Unknown` if the library doesn't export it.
+    */
+    pub fn classify(class_code: &str, is_marginal: Option<bool>) -> TradeKind {
+        let class_code = class_code.to_ascii_uppercase();
+
+        let is_repo = REPO_CLASS_PREFIXES
+            .iter()
+            .any(|prefix| class_code.starts_with(prefix))
+            || class_code.contains("REPO");
+
+        match (is_repo, is_marginal) {
+            (true, _) => TradeKind::Repo,
+            (false, Some(true)) => TradeKind::Margin,
+            (false, Some(false)) => TradeKind::Spot,
+            (false, None) => TradeKind::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(TradeKind::classify("TQBR", Some(false)), TradeKind::Spot);
+        assert_eq!(TradeKind::classify("TQBR", Some(true)), TradeKind::Margin);
+        assert_eq!(TradeKind::classify("TQBR", None), TradeKind::Unknown);
+
+        assert_eq!(
+            TradeKind::classify("EQRP_INFO", Some(false)),
+            TradeKind::Repo
+        );
+        assert_eq!(TradeKind::classify("PSRP", None), TradeKind::Repo);
+        assert_eq!(TradeKind::classify("GCRP", Some(true)), TradeKind::Repo);
+        assert_eq!(TradeKind::classify("rpeu", Some(false)), TradeKind::Repo);
+        assert_eq!(TradeKind::classify("SPBREPO", None), TradeKind::Repo);
+
+        // Not the repo despite the letters.
+        assert_eq!(TradeKind::classify("TQRP", Some(false)), TradeKind::Spot);
+    }
+}
@@ -0,0 +1,969 @@
+//! Building of the transaction strings in the format of the QUIK transaction files (.tri):
+//! `ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER; ...`.
+use crate::instrument::InstrumentInfoCache;
+use crate::{to_quik_date, to_quik_time, IsSell, PriceStep};
+use chrono::{NaiveDate, NaiveTime};
+use encoding_rs::WINDOWS_1251;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use tracing::debug;
+
+/// The maximum lengths of the parameters in characters as sent in WINDOWS-1251,
+/// `CLIENT_CODE` includes the broker reference.
+const MAX_LENGTHS: &[(&str, usize)] = &[("COMMENT", 20), ("CLIENT_CODE", 20)];
+
+/// The parameters masked in the log of the built transactions by default.
+const DEFAULT_MASKED_PARAMS: &[&str] = &["ACCOUNT", "CLIENT_CODE"];
+
+/// The type of the transaction, the `ACTION` parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// New order.
+    NewOrder,
+    /// Cancel order.
+    KillOrder,
+    /// Cancel all the orders in the class, or in the instrument if `SECCODE` is set.
+    KillAllOrders,
+    /// Negotiated deal with the partner.
+    NewNegDeal,
+    /// Repo trade with the partner.
+    NewRepoTrade,
+    /// New stop order, see `StopOrderKind`.
+    NewStopOrder,
+}
+
+impl Action {
+    /// The value of the `ACTION` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::NewOrder => "NEW_ORDER",
+            Action::KillOrder => "KILL_ORDER",
+            Action::KillAllOrders => "KILL_ALL_ORDERS",
+            Action::NewNegDeal => "NEW_NEG_DEAL",
+            Action::NewRepoTrade => "NEW_REPO_TRADE",
+            Action::NewStopOrder => "NEW_STOP_ORDER",
+        }
+    }
+
+    /// The parameters without which the QUIK server rejects the transaction.
+    fn required_params(&self) -> &'static [&'static str] {
+        match self {
+            Action::NewOrder => &[
+                "TRANS_ID",
+                "CLASSCODE",
+                "SECCODE",
+                "ACCOUNT",
+                "OPERATION",
+                "PRICE",
+                "QUANTITY",
+            ],
+            Action::KillOrder => &["TRANS_ID", "CLASSCODE", "SECCODE", "ORDER_KEY"],
+            Action::KillAllOrders => &["TRANS_ID", "CLASSCODE"],
+            Action::NewNegDeal => &[
+                "TRANS_ID",
+                "CLASSCODE",
+                "SECCODE",
+                "ACCOUNT",
+                "OPERATION",
+                "PRICE",
+                "QUANTITY",
+                "PARTNER",
+                "SETTLE_CODE",
+            ],
+            Action::NewRepoTrade => &[
+                "TRANS_ID",
+                "CLASSCODE",
+                "SECCODE",
+                "ACCOUNT",
+                "OPERATION",
+                "PRICE",
+                "QUANTITY",
+                "PARTNER",
+                "REPORATE",
+                "REPOTERM",
+            ],
+            Action::NewStopOrder => &[
+                "TRANS_ID",
+                "CLASSCODE",
+                "SECCODE",
+                "ACCOUNT",
+                "OPERATION",
+                "PRICE",
+                "QUANTITY",
+                "STOPPRICE",
+            ],
+        }
+    }
+}
+
+/// The type of the stop order, the `STOP_ORDER_KIND` parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopOrderKind {
+    /// Stop-limit order, the default of QUIK.
+    Simple,
+    /// Stop-limit order with the linked limit order (OCO): when one of them is executed,
+    /// the other one is cancelled. Requires `LINKED_ORDER_PRICE`.
+    WithLinkedLimitOrder,
+}
+
+impl StopOrderKind {
+    /// The value of the `STOP_ORDER_KIND` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopOrderKind::Simple => "SIMPLE_STOP_ORDER",
+            StopOrderKind::WithLinkedLimitOrder => "WITH_LINKED_LIMIT_ORDER",
+        }
+    }
+}
+
+/// The type of the order, the `TYPE` parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderType {
+    /// Limit order with `PRICE`, the default of QUIK.
+    Limit,
+    /// Market order with `PRICE` omitted or zero.
+    Market,
+}
+
+impl OrderType {
+    /// The value of the `TYPE` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Limit => "L",
+            OrderType::Market => "M",
+        }
+    }
+}
+
+/// The prices rounded to the price step set with `TransactionBuilder::price_step`.
+const PRICE_PARAMS: &[&str] = &["PRICE", "STOPPRICE", "LINKED_ORDER_PRICE"];
+
+/// The parameters of the linked limit order, valid only with `StopOrderKind::WithLinkedLimitOrder`.
+const LINKED_ORDER_PARAMS: &[&str] = &["LINKED_ORDER_PRICE", "KILL_IF_LINKED_ORDER_PARTLY_FILLED"];
+
+/// Error of building the transaction string.
+#[derive(Debug, PartialEq)]
+pub enum TransactionBuildError {
+    MissingParam(&'static str),
+    /// The value of the parameter is longer than the maximum number of characters. The characters
+    /// not representable in WINDOWS-1251 count as their references sent instead, e.g. `&#945;`.
+    TooLong(&'static str, usize),
+    /// The parameter is not valid for the action or the kind of the stop order.
+    UnexpectedParam(&'static str),
+    /// The quantity is set in the securities, but the lot size of the instrument is unknown.
+    UnknownLotSize,
+    /// The quantity in the securities is not a multiple of the lot size.
+    NotMultipleOfLot(i64, i64),
+    /// The lot size of the instrument is not positive, e.g. not loaded from QUIK.
+    InvalidLotSize(i64),
+}
+
+impl fmt::Display for TransactionBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionBuildError::MissingParam(name) => {
+                write!(f, "Missing transaction parameter: {}", name)
+            }
+            TransactionBuildError::TooLong(name, max_len) => {
+                write!(
+                    f,
+                    "Transaction parameter {} is longer than {} characters",
+                    name, max_len
+                )
+            }
+            TransactionBuildError::UnexpectedParam(name) => {
+                write!(f, "Unexpected transaction parameter: {}", name)
+            }
+            TransactionBuildError::UnknownLotSize => {
+                write!(f, "Unknown lot size of the instrument")
+            }
+            TransactionBuildError::NotMultipleOfLot(units, lot_size) => {
+                write!(
+                    f,
+                    "Quantity {} is not a multiple of the lot size {}",
+                    units, lot_size
+                )
+            }
+            TransactionBuildError::InvalidLotSize(lot_size) => {
+                write!(f, "Invalid lot size of the instrument: {}", lot_size)
+            }
+        }
+    }
+}
+
+impl error::Error for TransactionBuildError {}
+
+/**
+Builder of the transaction string passed to `Terminal::send_sync_transaction` and
+`Terminal::send_async_transaction`.
+
+The default account and client code are added to every built transaction
+unless `ACCOUNT` and `CLIENT_CODE` are set explicitly.
+
+# Example of use
+```ignore
+let transaction_str = TransactionBuilder::new(Action::NewOrder)
+    .default_account("L01-00000F00", "OPEN12345")
+    .trans_id(1)
+    .class_code("TQBR")
+    .sec_code("SBER")
+    .operation(IsSell::Buy)
+    .price(250.5)
+    .quantity(1)
+    .build()?;
+```
+*/
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    action: Action,
+    params: Vec<(String, String)>,
+    default_account: Option<(String, String)>,
+    broker_ref: Option<String>,
+    price_step: Option<PriceStep>,
+    instrument_info: Option<Arc<InstrumentInfoCache>>,
+    units: Option<i64>,
+    order_type: Option<OrderType>,
+    masked_params: Vec<String>,
+}
+
+impl TransactionBuilder {
+    pub fn new(action: Action) -> Self {
+        TransactionBuilder {
+            action,
+            params: Vec::new(),
+            default_account: None,
+            broker_ref: None,
+            price_step: None,
+            instrument_info: None,
+            units: None,
+            order_type: None,
+            masked_params: DEFAULT_MASKED_PARAMS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+
+    /// The account and client code used when `ACCOUNT` and `CLIENT_CODE` are not set.
+    pub fn default_account(mut self, account: &str, client_code: &str) -> Self {
+        self.default_account = Some((account.to_string(), client_code.to_string()));
+        self
+    }
+
+    /// Sets the parameter of the transaction, replacing the previous value.
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_ascii_uppercase();
+
+        match self.params.iter_mut().find(|(key, _)| *key == name) {
+            Some((_, param_value)) => *param_value = value.to_string(),
+            None => self.params.push((name, value.to_string())),
+        }
+
+        self
+    }
+
+    /// The TRANS_ID must be positive to be correlated to the reply of the transaction.
+    pub fn trans_id(self, trans_id: i32) -> Self {
+        self.param("TRANS_ID", &trans_id.to_string())
+    }
+
+    pub fn class_code(self, class_code: &str) -> Self {
+        self.param("CLASSCODE", class_code)
+    }
+
+    pub fn sec_code(self, sec_code: &str) -> Self {
+        self.param("SECCODE", sec_code)
+    }
+
+    pub fn account(self, account: &str) -> Self {
+        self.param("ACCOUNT", account)
+    }
+
+    pub fn client_code(self, client_code: &str) -> Self {
+        self.param("CLIENT_CODE", client_code)
+    }
+
+    pub fn operation(self, operation: IsSell) -> Self {
+        let operation = match operation {
+            IsSell::Buy => "B",
+            IsSell::Sell => "S",
+        };
+
+        self.param("OPERATION", operation)
+    }
+
+    /// Price of the order, QUIK expects a comma as the decimal separator.
+    pub fn price(self, price: f64) -> Self {
+        self.param("PRICE", &format_price(price))
+    }
+
+    /// Quantity of the order in lots.
+    pub fn quantity(self, quantity: i64) -> Self {
+        self.param("QUANTITY", &quantity.to_string())
+    }
+
+    /// Quantity of the order in the securities, converted to the lots when the transaction is built,
+    /// see `Terminal::set_instrument_info`. Replaces `QUANTITY`.
+    pub fn quantity_units(mut self, units: i64) -> Self {
+        self.units = Some(units);
+        self
+    }
+
+    /// Market order: `TYPE=M` and `PRICE=0`, the stock market executes it at the best prices.
+    /// The futures market requires the price of the market order, set it after this call.
+    /// Unlike `order_type(OrderType::Market)` the price is not checked.
+    pub fn market(self) -> Self {
+        self.param("TYPE", "M").price(0.0)
+    }
+
+    /// The type of the order checked when the transaction is built: the limit order requires
+    /// `PRICE`, the market order requires `PRICE` omitted or zero, `PRICE=0` is added if omitted.
+    pub fn order_type(mut self, order_type: OrderType) -> Self {
+        self.order_type = Some(order_type);
+        self.param("TYPE", order_type.as_str())
+    }
+
+    /// Number of the order to cancel.
+    pub fn order_key(self, order_num: u64) -> Self {
+        self.param("ORDER_KEY", &order_num.to_string())
+    }
+
+    /// Code of the partner firm of the negotiated deal or the repo trade.
+    pub fn partner(self, partner: &str) -> Self {
+        self.param("PARTNER", partner)
+    }
+
+    /// Settlement code of the negotiated deal, for example `Y0` or `T0`.
+    pub fn settle_code(self, settle_code: &str) -> Self {
+        self.param("SETTLE_CODE", settle_code)
+    }
+
+    /// Repo rate in percent.
+    pub fn repo_rate(self, repo_rate: f64) -> Self {
+        self.param("REPORATE", &format_price(repo_rate))
+    }
+
+    /// Repo term in calendar days.
+    pub fn repo_term(self, repo_term: u32) -> Self {
+        self.param("REPOTERM", &repo_term.to_string())
+    }
+
+    /// Price triggering the stop order.
+    pub fn stop_price(self, stop_price: f64) -> Self {
+        self.param("STOPPRICE", &format_price(stop_price))
+    }
+
+    pub fn stop_order_kind(self, kind: StopOrderKind) -> Self {
+        self.param("STOP_ORDER_KIND", kind.as_str())
+    }
+
+    /// Price of the limit order linked to the stop order (OCO).
+    pub fn linked_order_price(self, price: f64) -> Self {
+        self.param("LINKED_ORDER_PRICE", &format_price(price))
+    }
+
+    /// Cancel the stop order when the linked limit order is partly filled.
+    pub fn kill_if_linked_order_partly_filled(self, kill: bool) -> Self {
+        self.param(
+            "KILL_IF_LINKED_ORDER_PARTLY_FILLED",
+            if kill { "YES" } else { "NO" },
+        )
+    }
+
+    /// The order or the stop order is active until the end of the date, `EXPIRY_DATE=20241115`.
+    pub fn expiry_date(self, date: NaiveDate) -> Self {
+        self.param("EXPIRY_DATE", &to_quik_date(date).to_string())
+    }
+
+    /// The stop order is active from `from` to `to` within the trading day.
+    pub fn active_time(self, from: NaiveTime, to: NaiveTime) -> Self {
+        self.param("IS_ACTIVE_IN_TIME", "YES")
+            .param("ACTIVE_FROM_TIME", &format!("{:06}", to_quik_time(from)))
+            .param("ACTIVE_TO_TIME", &format!("{:06}", to_quik_time(to)))
+    }
+
+    /// Text comment of the transaction, up to 20 characters.
+    /// The Cyrillic text is encoded in WINDOWS-1251 when the transaction is sent.
+    pub fn comment(self, comment: &str) -> Self {
+        self.param("COMMENT", comment)
+    }
+
+    /// Reference of the order added to the client code after a slash: `CLIENT_CODE=OPEN12345/bot1`.
+    /// The orders and the trades of the transaction carry it in the brokerref field,
+    /// which allows to attribute the fills to the strategy.
+    pub fn broker_ref(mut self, broker_ref: &str) -> Self {
+        self.broker_ref = Some(broker_ref.to_string());
+        self
+    }
+
+    /// The price step of the instrument, the prices are rounded to it when the transaction is built.
+    pub fn price_step(mut self, price_step: PriceStep) -> Self {
+        self.price_step = Some(price_step);
+        self
+    }
+
+    /// The metadata of the instruments of the terminal: the price step used unless set with
+    /// `price_step`, the lot size used by `quantity_units`.
+    pub(crate) fn instrument_info(mut self, instrument_info: Arc<InstrumentInfoCache>) -> Self {
+        self.instrument_info = Some(instrument_info);
+        self
+    }
+
+    /// The parameters whose values are replaced by `***` when `build` logs the transaction
+    /// at the debug level, `ACCOUNT` and `CLIENT_CODE` by default. No parameters are masked
+    /// with the empty list.
+    pub fn mask_in_log(mut self, names: &[&str]) -> Self {
+        self.masked_params = names.iter().map(|name| name.to_ascii_uppercase()).collect();
+        self
+    }
+
+    /// Returns the value of the parameter set in the builder.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Checks that the parameters of the linked limit order are set together
+    /// with `StopOrderKind::WithLinkedLimitOrder` only.
+    fn check_linked_order(&self) -> Result<(), TransactionBuildError> {
+        let with_linked_order = self.action == Action::NewStopOrder
+            && self.get("STOP_ORDER_KIND") == Some(StopOrderKind::WithLinkedLimitOrder.as_str());
+
+        if with_linked_order {
+            if self.get("LINKED_ORDER_PRICE").is_none() {
+                return Err(TransactionBuildError::MissingParam("LINKED_ORDER_PRICE"));
+            }
+        } else if let Some(param) = LINKED_ORDER_PARAMS
+            .iter()
+            .find(|param| self.get(param).is_some())
+        {
+            return Err(TransactionBuildError::UnexpectedParam(param));
+        }
+
+        Ok(())
+    }
+
+    /// Builds the transaction string, checking that the required parameters are set
+    /// and the lengths of the values. The transaction is logged at the debug level on the target
+    /// `trans2quik` with the values of the parameters of `mask_in_log` masked.
+    pub fn build(&self) -> Result<String, TransactionBuildError> {
+        let mut params: Vec<(&str, String)> = vec![("ACTION", self.action.as_str().to_string())];
+        params.extend(
+            self.params
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        );
+
+        let instrument_info = match (
+            &self.instrument_info,
+            self.get("CLASSCODE"),
+            self.get("SECCODE"),
+        ) {
+            (Some(instrument_info), Some(class_code), Some(sec_code)) => {
+                instrument_info.get(class_code, sec_code)
+            }
+            _ => None,
+        };
+
+        if let Some(units) = self.units {
+            let lot_size = instrument_info
+                .map(|info| info.lot_size)
+                .ok_or(TransactionBuildError::UnknownLotSize)?;
+
+            if lot_size <= 0 {
+                return Err(TransactionBuildError::InvalidLotSize(lot_size));
+            }
+
+            if units % lot_size != 0 {
+                return Err(TransactionBuildError::NotMultipleOfLot(units, lot_size));
+            }
+
+            let lots = (units / lot_size).to_string();
+            match params.iter_mut().find(|(name, _)| *name == "QUANTITY") {
+                Some((_, quantity)) => *quantity = lots,
+                None => params.push(("QUANTITY", lots)),
+            }
+        }
+
+        let price_step = self
+            .price_step
+            .or_else(|| instrument_info.map(|info| info.price_step()));
+
+        if let Some(price_step) = &price_step {
+            for (name, value) in params.iter_mut() {
+                if !PRICE_PARAMS.contains(name) {
+                    continue;
+                }
+                if let Ok(price) = value.replace(',', ".").parse::<f64>() {
+                    *value = format_price(price_step.round(price));
+                }
+            }
+        }
+
+        match (self.order_type, self.get("PRICE")) {
+            (Some(OrderType::Limit), None) => {
+                return Err(TransactionBuildError::MissingParam("PRICE"));
+            }
+            (Some(OrderType::Market), None) => params.push(("PRICE", format_price(0.0))),
+            (Some(OrderType::Market), Some(price)) => {
+                let is_zero = price
+                    .replace(',', ".")
+                    .parse::<f64>()
+                    .is_ok_and(|price| price == 0.0);
+                if !is_zero {
+                    return Err(TransactionBuildError::UnexpectedParam("PRICE"));
+                }
+            }
+            _ => {}
+        }
+
+        if let Some((account, client_code)) = &self.default_account {
+            if self.get("ACCOUNT").is_none() {
+                params.push(("ACCOUNT", account.clone()));
+            }
+            if self.get("CLIENT_CODE").is_none() {
+                params.push(("CLIENT_CODE", client_code.clone()));
+            }
+        }
+
+        if let Some(broker_ref) = &self.broker_ref {
+            match params.iter_mut().find(|(name, _)| *name == "CLIENT_CODE") {
+                Some((_, client_code)) => *client_code = format!("{}/{}", client_code, broker_ref),
+                None => return Err(TransactionBuildError::MissingParam("CLIENT_CODE")),
+            }
+        }
+
+        for required in self.action.required_params() {
+            if !params.iter().any(|(name, _)| name == required) {
+                return Err(TransactionBuildError::MissingParam(required));
+            }
+        }
+
+        // The reply can't be correlated to the transaction without a positive TRANS_ID.
+        let has_trans_id = self
+            .get("TRANS_ID")
+            .and_then(|trans_id| trans_id.parse::<i32>().ok())
+            .is_some_and(|trans_id| trans_id > 0);
+        if !has_trans_id {
+            return Err(TransactionBuildError::MissingParam("TRANS_ID"));
+        }
+
+        self.check_linked_order()?;
+
+        for &(limited, max_len) in MAX_LENGTHS {
+            let too_long = params
+                .iter()
+                .any(|(name, value)| *name == limited && encoded_len(value) > max_len);
+
+            if too_long {
+                return Err(TransactionBuildError::TooLong(limited, max_len));
+            }
+        }
+
+        let transaction_str = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        debug!(
+            target: "trans2quik",
+            "transaction built: {}",
+            mask_params(&transaction_str, &self.masked_params)
+        );
+
+        Ok(transaction_str)
+    }
+}
+
+/// The transaction string with the values of the `masked` parameters replaced by `***`.
+fn mask_params(transaction_str: &str, masked: &[String]) -> String {
+    transaction_str
+        .split("; ")
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if masked.iter().any(|masked| masked == name) => {
+                format!("{}=***", name)
+            }
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Formats the price with a comma as the decimal separator.
+fn format_price(price: f64) -> String {
+    price.to_string().replace('.', ",")
+}
+
+/// The length of the value encoded in WINDOWS-1251 like the sent transaction.
+fn encoded_len(value: &str) -> usize {
+    WINDOWS_1251.encode(value).0.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{InstrumentInfo, InstrumentTable};
+
+    fn new_order() -> TransactionBuilder {
+        TransactionBuilder::new(Action::NewOrder)
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .operation(IsSell::Buy)
+            .price(250.5)
+            .quantity(10)
+    }
+
+    #[test]
+    fn test_build_new_order() {
+        let transaction_str = new_order().account("L01-00000F00").build().unwrap();
+
+        assert_eq!(
+            transaction_str,
+            "ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER; OPERATION=B; PRICE=250,5; QUANTITY=10; ACCOUNT=L01-00000F00"
+        );
+    }
+
+    #[test]
+    fn test_build_missing_param() {
+        assert_eq!(
+            new_order().build(),
+            Err(TransactionBuildError::MissingParam("ACCOUNT"))
+        );
+    }
+
+    #[test]
+    fn test_build_missing_trans_id() {
+        assert_eq!(
+            new_order().account("L01-00000F00").trans_id(0).build(),
+            Err(TransactionBuildError::MissingParam("TRANS_ID"))
+        );
+        assert_eq!(
+            TransactionBuilder::new(Action::KillAllOrders)
+                .class_code("TQBR")
+                .build(),
+            Err(TransactionBuildError::MissingParam("TRANS_ID"))
+        );
+    }
+
+    #[test]
+    fn test_build_order_type() {
+        let limit_order = new_order()
+            .account("L01-00000F00")
+            .order_type(OrderType::Limit);
+        assert!(limit_order.build().unwrap().contains("; TYPE=L"));
+
+        let market_order = TransactionBuilder::new(Action::NewOrder)
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .account("L01-00000F00")
+            .operation(IsSell::Buy)
+            .quantity(10)
+            .order_type(OrderType::Market);
+        assert_eq!(
+            market_order.build().unwrap(),
+            "ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER; ACCOUNT=L01-00000F00; OPERATION=B; QUANTITY=10; TYPE=M; PRICE=0"
+        );
+        assert!(market_order.clone().price(0.0).build().is_ok());
+
+        assert_eq!(
+            market_order.price(250.5).build(),
+            Err(TransactionBuildError::UnexpectedParam("PRICE"))
+        );
+        assert_eq!(
+            TransactionBuilder::new(Action::NewOrder)
+                .trans_id(1)
+                .order_type(OrderType::Limit)
+                .build(),
+            Err(TransactionBuildError::MissingParam("PRICE"))
+        );
+    }
+
+    fn repo_trade() -> TransactionBuilder {
+        TransactionBuilder::new(Action::NewRepoTrade)
+            .trans_id(2)
+            .class_code("PSRP")
+            .sec_code("SU26238RMFS4")
+            .account("L01-00000F00")
+            .operation(IsSell::Sell)
+            .price(60.5)
+            .quantity(100)
+            .partner("MC0000000000")
+    }
+
+    #[test]
+    fn test_build_repo_trade() {
+        let transaction_str = repo_trade().repo_rate(16.25).repo_term(7).build().unwrap();
+
+        assert_eq!(
+            transaction_str,
+            "ACTION=NEW_REPO_TRADE; TRANS_ID=2; CLASSCODE=PSRP; SECCODE=SU26238RMFS4; ACCOUNT=L01-00000F00; OPERATION=S; PRICE=60,5; QUANTITY=100; PARTNER=MC0000000000; REPORATE=16,25; REPOTERM=7"
+        );
+    }
+
+    #[test]
+    fn test_build_repo_trade_missing_param() {
+        assert_eq!(
+            repo_trade().repo_rate(16.25).build(),
+            Err(TransactionBuildError::MissingParam("REPOTERM"))
+        );
+    }
+
+    #[test]
+    fn test_build_neg_deal_missing_param() {
+        let builder = TransactionBuilder::new(Action::NewNegDeal)
+            .trans_id(3)
+            .class_code("PSOB")
+            .sec_code("SU26238RMFS4")
+            .account("L01-00000F00")
+            .operation(IsSell::Buy)
+            .price(60.5)
+            .quantity(100)
+            .partner("MC0000000000");
+
+        assert_eq!(
+            builder.build(),
+            Err(TransactionBuildError::MissingParam("SETTLE_CODE"))
+        );
+        assert!(builder.settle_code("Y0").build().is_ok());
+    }
+
+    fn stop_order() -> TransactionBuilder {
+        TransactionBuilder::new(Action::NewStopOrder)
+            .trans_id(4)
+            .class_code("SPBOPT")
+            .sec_code("Si95000BK6")
+            .account("SPBFUT00000")
+            .operation(IsSell::Sell)
+            .stop_price(1500.0)
+            .price(1450.0)
+            .quantity(1)
+    }
+
+    #[test]
+    fn test_build_oco_stop_order() {
+        let transaction_str = stop_order()
+            .stop_order_kind(StopOrderKind::WithLinkedLimitOrder)
+            .linked_order_price(1700.0)
+            .kill_if_linked_order_partly_filled(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            transaction_str,
+            "ACTION=NEW_STOP_ORDER; TRANS_ID=4; CLASSCODE=SPBOPT; SECCODE=Si95000BK6; ACCOUNT=SPBFUT00000; OPERATION=S; STOPPRICE=1500; PRICE=1450; QUANTITY=1; STOP_ORDER_KIND=WITH_LINKED_LIMIT_ORDER; LINKED_ORDER_PRICE=1700; KILL_IF_LINKED_ORDER_PARTLY_FILLED=YES"
+        );
+    }
+
+    #[test]
+    fn test_build_stop_order_dates() {
+        let transaction_str = stop_order()
+            .expiry_date(NaiveDate::from_ymd_opt(2024, 11, 15).unwrap())
+            .active_time(
+                NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(18, 45, 0).unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.ends_with(
+            "EXPIRY_DATE=20241115; IS_ACTIVE_IN_TIME=YES; ACTIVE_FROM_TIME=070000; ACTIVE_TO_TIME=184500"
+        ));
+    }
+
+    #[test]
+    fn test_build_oco_stop_order_partial() {
+        assert_eq!(
+            stop_order()
+                .stop_order_kind(StopOrderKind::WithLinkedLimitOrder)
+                .build(),
+            Err(TransactionBuildError::MissingParam("LINKED_ORDER_PRICE"))
+        );
+        assert_eq!(
+            stop_order().linked_order_price(1700.0).build(),
+            Err(TransactionBuildError::UnexpectedParam("LINKED_ORDER_PRICE"))
+        );
+        assert_eq!(
+            stop_order()
+                .stop_order_kind(StopOrderKind::Simple)
+                .kill_if_linked_order_partly_filled(false)
+                .build(),
+            Err(TransactionBuildError::UnexpectedParam(
+                "KILL_IF_LINKED_ORDER_PARTLY_FILLED"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_price_step() {
+        let transaction_str = stop_order()
+            .stop_price(1500.123)
+            .price(1450.4999)
+            .price_step(PriceStep::new(0.5))
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.contains("; STOPPRICE=1500; PRICE=1450,5;"));
+
+        let transaction_str = new_order()
+            .account("L01-00000F00")
+            .price(7103.495)
+            .price_step(PriceStep::new(0.01))
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.contains("; PRICE=7103,5;"));
+    }
+
+    #[test]
+    fn test_instrument_info() {
+        let mut table = InstrumentTable::new();
+        table.insert(
+            "TQBR",
+            "SBER",
+            InstrumentInfo {
+                lot_size: 10,
+                price_step: 0.01,
+            },
+        );
+        table.insert(
+            "TQBR",
+            "GAZP",
+            InstrumentInfo {
+                lot_size: 0,
+                price_step: 0.01,
+            },
+        );
+        let instrument_info = InstrumentInfoCache::new(table);
+
+        let builder = TransactionBuilder::new(Action::NewOrder)
+            .instrument_info(Arc::clone(&instrument_info))
+            .trans_id(1)
+            .class_code("TQBR")
+            .sec_code("SBER")
+            .account("L01-00000F00")
+            .operation(IsSell::Buy)
+            .price(250.506);
+
+        let transaction_str = builder.clone().quantity_units(30).build().unwrap();
+        assert!(transaction_str.ends_with("; OPERATION=B; PRICE=250,51; QUANTITY=3"));
+
+        assert_eq!(
+            builder.clone().quantity_units(25).build(),
+            Err(TransactionBuildError::NotMultipleOfLot(25, 10))
+        );
+        assert_eq!(
+            builder.clone().sec_code("LKOH").quantity_units(10).build(),
+            Err(TransactionBuildError::UnknownLotSize)
+        );
+        assert_eq!(
+            builder.sec_code("GAZP").quantity_units(10).build(),
+            Err(TransactionBuildError::InvalidLotSize(0))
+        );
+    }
+
+    #[test]
+    fn test_comment_and_broker_ref() {
+        let transaction_str = new_order()
+            .default_account("L01-00000F00", "OPEN12345")
+            .comment("Робот-1")
+            .broker_ref("bot1")
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.contains("; COMMENT=Робот-1;"));
+        assert!(transaction_str.ends_with("; CLIENT_CODE=OPEN12345/bot1"));
+    }
+
+    #[test]
+    fn test_comment_too_long() {
+        let builder = new_order()
+            .account("L01-00000F00")
+            .comment("Комментарий длиннее двадцати символов");
+
+        assert_eq!(
+            builder.build(),
+            Err(TransactionBuildError::TooLong("COMMENT", 20))
+        );
+
+        // 6 characters sent as `&#945;&#946;&#947;abc`.
+        assert_eq!(
+            new_order()
+                .account("L01-00000F00")
+                .comment("αβγabc")
+                .build(),
+            Err(TransactionBuildError::TooLong("COMMENT", 20))
+        );
+    }
+
+    #[test]
+    fn test_broker_ref_requires_client_code() {
+        let builder = new_order().account("L01-00000F00").broker_ref("bot1");
+
+        assert_eq!(
+            builder.build(),
+            Err(TransactionBuildError::MissingParam("CLIENT_CODE"))
+        );
+        assert_eq!(
+            builder
+                .client_code("OPEN12345")
+                .broker_ref("strategy-number-one")
+                .build(),
+            Err(TransactionBuildError::TooLong("CLIENT_CODE", 20))
+        );
+    }
+
+    #[test]
+    fn test_default_account_applied() {
+        let transaction_str = new_order()
+            .default_account("L01-00000F00", "OPEN12345")
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.ends_with("; ACCOUNT=L01-00000F00; CLIENT_CODE=OPEN12345"));
+    }
+
+    #[test]
+    fn test_explicit_account_overrides_default() {
+        let transaction_str = new_order()
+            .default_account("L01-00000F00", "OPEN12345")
+            .account("L01-00000F01")
+            .build()
+            .unwrap();
+
+        assert!(transaction_str.contains("ACCOUNT=L01-00000F01"));
+        assert!(!transaction_str.contains("ACCOUNT=L01-00000F00"));
+        assert!(transaction_str.contains("CLIENT_CODE=OPEN12345"));
+    }
+
+    #[test]
+    fn test_mask_params() {
+        let builder = new_order()
+            .account("L01-00000F00")
+            .client_code("OPEN12345")
+            .broker_ref("bot1");
+        let transaction_str = builder.build().unwrap();
+
+        let masked = mask_params(&transaction_str, &builder.masked_params);
+        assert!(masked.contains("; ACCOUNT=***; CLIENT_CODE=***"));
+        assert!(!masked.contains("L01-00000F00"));
+        assert!(!masked.contains("OPEN12345"));
+        assert!(masked.starts_with("ACTION=NEW_ORDER; TRANS_ID=1; CLASSCODE=TQBR; SECCODE=SBER;"));
+
+        let builder = builder.mask_in_log(&["trans_id"]);
+        let masked = mask_params(&transaction_str, &builder.masked_params);
+        assert!(masked.contains("TRANS_ID=***"));
+        assert!(masked.contains("ACCOUNT=L01-00000F00; CLIENT_CODE=OPEN12345/bot1"));
+
+        let builder = builder.mask_in_log(&[]);
+        assert_eq!(
+            mask_params(&transaction_str, &builder.masked_params),
+            transaction_str
+        );
+    }
+}
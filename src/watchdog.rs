@@ -0,0 +1,114 @@
+//! Detection of the stall of the callback functions, e.g. when the library Trans2QUIK.dll hangs.
+use chrono::{Local, NaiveTime};
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// The time of the last call of any callback function.
+    static ref LAST_CALLBACK: Mutex<Option<Instant>> = Mutex::new(None);
+    /// The market hours in the local time, the stall is not reported outside of them.
+    static ref MARKET_HOURS: Mutex<Option<(NaiveTime, NaiveTime)>> = Mutex::new(None);
+}
+
+/// Incremented to stop the running watchdog thread.
+static WATCHDOG_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Records the call of the callback function.
+pub(crate) fn touch() {
+    *LAST_CALLBACK.lock().unwrap() = Some(Instant::now());
+}
+
+/// The time since the last call of any callback function, `None` if none was called.
+pub(crate) fn last_callback_age() -> Option<Duration> {
+    LAST_CALLBACK.lock().unwrap().map(|last| last.elapsed())
+}
+
+pub(crate) fn set_market_hours(market_hours: Option<(NaiveTime, NaiveTime)>) {
+    *MARKET_HOURS.lock().unwrap() = market_hours;
+}
+
+/// Starts the thread calling `on_stall` with the idle time when no callback function is called
+/// within `max_idle`, once per stall. Stops the thread started before.
+pub(crate) fn start<F>(max_idle: Duration, on_stall: F)
+where
+    F: Fn(Duration) + Send + 'static,
+{
+    let generation = WATCHDOG_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let poll_interval = (max_idle / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+    let started = Instant::now();
+
+    thread::spawn(move || {
+        let mut reported: Option<Instant> = None;
+
+        while WATCHDOG_GENERATION.load(Ordering::SeqCst) == generation {
+            thread::sleep(poll_interval);
+
+            // The callbacks are awaited since the start of the watchdog.
+            let last = LAST_CALLBACK
+                .lock()
+                .unwrap()
+                .unwrap_or(started)
+                .max(started);
+            let idle = last.elapsed();
+            let market_hours = *MARKET_HOURS.lock().unwrap();
+
+            if idle > max_idle
+                && reported != Some(last)
+                && in_market_hours(market_hours, Local::now().time())
+                && WATCHDOG_GENERATION.load(Ordering::SeqCst) == generation
+            {
+                reported = Some(last);
+                on_stall(idle);
+            }
+        }
+    });
+}
+
+/// Stops the watchdog thread.
+pub(crate) fn stop() {
+    WATCHDOG_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Clears the state for the tests.
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) fn reset() {
+    stop();
+    *LAST_CALLBACK.lock().unwrap() = None;
+    *MARKET_HOURS.lock().unwrap() = None;
+}
+
+/// The time is within the market hours, which can pass midnight, e.g. from 19:00 to 02:00.
+fn in_market_hours(market_hours: Option<(NaiveTime, NaiveTime)>, time: NaiveTime) -> bool {
+    match market_hours {
+        None => true,
+        Some((start, end)) if start <= end => start <= time && time < end,
+        Some((start, end)) => start <= time || time < end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hm(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_in_market_hours() {
+        let day = Some((hm(10, 0), hm(18, 50)));
+        assert!(in_market_hours(day, hm(10, 0)));
+        assert!(!in_market_hours(day, hm(18, 50)));
+        assert!(!in_market_hours(day, hm(9, 59)));
+
+        let evening = Some((hm(19, 0), hm(2, 0)));
+        assert!(in_market_hours(evening, hm(23, 30)));
+        assert!(in_market_hours(evening, hm(1, 0)));
+        assert!(!in_market_hours(evening, hm(12, 0)));
+
+        assert!(in_market_hours(None, hm(3, 0)));
+    }
+}
@@ -0,0 +1,6 @@
+//! The compile-fail tests of the lifetimes of the public API.
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
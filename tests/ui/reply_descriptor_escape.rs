@@ -0,0 +1,14 @@
+// The transaction reply descriptor is valid only during the transaction_reply_callback,
+// so it can't be stored by the reply handler.
+use std::sync::Mutex;
+use trans2quik::{ReplyDescriptor, Terminal};
+
+static STORED: Mutex<Option<&'static ReplyDescriptor<'static>>> = Mutex::new(None);
+
+fn store(terminal: &Terminal) {
+    terminal.set_transaction_reply_handler(|_, access| {
+        *STORED.lock().unwrap() = Some(access.descriptor());
+    });
+}
+
+fn main() {}